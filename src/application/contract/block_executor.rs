@@ -0,0 +1,305 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::domain::runner::StorageAccessLog;
+
+/// A call of a block together with the storage it accessed.
+pub struct Execution<R> {
+    pub outcome: R,
+    pub access_log: StorageAccessLog,
+}
+
+pub struct BlockOutcome<R> {
+    pub outcome: R,
+    /// The speculative result could not be used, the call ran again in its turn.
+    pub re_executed: bool,
+}
+
+/// Runs the calls of a block optimistically in parallel, with the result of running them
+/// one after the other.
+///
+/// Every call first runs speculatively against the state before the block, with its
+/// writes buffered. The calls are then committed in order: a speculation stands if no
+/// earlier call of the block wrote what it read, otherwise the call runs again for real
+/// once everything before it is committed. A call that reached state the access log can
+/// not see, e.g. through a nested call, invalidates every speculation after it.
+pub struct BlockExecutor {
+    parallelism: usize,
+}
+
+impl BlockExecutor {
+    pub fn new(parallelism: usize) -> Self {
+        Self {
+            parallelism: parallelism.max(1),
+        }
+    }
+
+    /// `scopes` holds the storage of every call, calls in different scopes never conflict.
+    /// `speculate` must leave the host untouched, `commit` applies the writes of a
+    /// speculation and `execute` runs a call for real, both in block order.
+    pub fn execute<K, R, S, C, E>(
+        &self,
+        scopes: &[K],
+        speculate: S,
+        mut commit: C,
+        mut execute: E,
+    ) -> anyhow::Result<Vec<BlockOutcome<R>>>
+    where
+        K: Eq + Hash,
+        R: Send,
+        S: Fn(usize) -> anyhow::Result<Execution<R>> + Sync,
+        C: FnMut(usize, &StorageAccessLog) -> anyhow::Result<()>,
+        E: FnMut(usize) -> anyhow::Result<Execution<R>>,
+    {
+        let speculations = self.speculate_all(scopes.len(), &speculate);
+
+        let mut written: HashMap<&K, HashSet<Vec<u8>>> = HashMap::new();
+        let mut escaped = false;
+        let mut outcomes = Vec::with_capacity(scopes.len());
+
+        for (index, (scope, speculation)) in scopes.iter().zip(speculations).enumerate() {
+            let speculation = speculation.filter(|speculation| {
+                let access_log = &speculation.access_log;
+
+                !escaped
+                    && !access_log.is_escaped()
+                    && written
+                        .get(scope)
+                        .is_none_or(|written| written.is_disjoint(access_log.reads()))
+            });
+
+            let (execution, re_executed) = match speculation {
+                Some(speculation) => {
+                    commit(index, &speculation.access_log)?;
+                    (speculation, false)
+                }
+                None => (execute(index)?, true),
+            };
+
+            escaped |= execution.access_log.is_escaped();
+            written
+                .entry(scope)
+                .or_default()
+                .extend(execution.access_log.writes().keys().cloned());
+
+            outcomes.push(BlockOutcome {
+                outcome: execution.outcome,
+                re_executed,
+            });
+        }
+
+        Ok(outcomes)
+    }
+
+    /// Speculations that failed to run are re-executed like conflicting ones.
+    fn speculate_all<R, S>(&self, count: usize, speculate: &S) -> Vec<Option<Execution<R>>>
+    where
+        R: Send,
+        S: Fn(usize) -> anyhow::Result<Execution<R>> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let slots: Vec<Mutex<Option<Execution<R>>>> = (0..count).map(|_| Mutex::new(None)).collect();
+
+        thread::scope(|scope| {
+            for _ in 0..self.parallelism.min(count) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= count {
+                        break;
+                    }
+
+                    *slots[index].lock().unwrap() = speculate(index).ok();
+                });
+            }
+        });
+
+        slots.into_iter().map(|slot| slot.into_inner().unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use wasmer::Value;
+
+    use crate::domain::runner::test_host::{TestHost, TEST_MAX_GAS};
+    use crate::domain::runner::{ContractRunner, StorageOverlay};
+
+    type Storage = BTreeMap<Vec<u8>, Vec<u8>>;
+
+    /// Adds the values of `reads` and writes the sum to `write`.
+    struct Transfer {
+        reads: Vec<u8>,
+        write: u8,
+    }
+
+    fn pointer(byte: u8) -> Vec<u8> {
+        vec![byte; 32]
+    }
+
+    fn run(transfer: &Transfer, storage: &Storage) -> Execution<u64> {
+        let mut access_log = StorageAccessLog::default();
+        let mut sum = 0;
+
+        for &read in &transfer.reads {
+            access_log.record_read(&pointer(read));
+            sum += storage.get(&pointer(read)).map_or(0, |value| value[0] as u64);
+        }
+
+        access_log.record_write(&[pointer(transfer.write), vec![sum as u8]].concat());
+
+        Execution {
+            outcome: sum,
+            access_log,
+        }
+    }
+
+    fn apply(storage: &mut Storage, access_log: &StorageAccessLog) {
+        storage.extend(access_log.writes().clone());
+    }
+
+    fn block() -> Vec<Transfer> {
+        vec![
+            Transfer { reads: vec![1, 2], write: 3 },
+            Transfer { reads: vec![4], write: 5 },
+            Transfer { reads: vec![3], write: 6 },
+            Transfer { reads: vec![1, 6], write: 1 },
+            Transfer { reads: vec![2], write: 7 },
+        ]
+    }
+
+    fn initial_storage() -> Storage {
+        (1..=7).map(|byte| (pointer(byte), vec![byte])).collect()
+    }
+
+    #[test]
+    fn matches_sequential_execution() {
+        let transfers = block();
+
+        let mut expected_storage = initial_storage();
+        let expected: Vec<u64> = transfers
+            .iter()
+            .map(|transfer| {
+                let execution = run(transfer, &expected_storage);
+                apply(&mut expected_storage, &execution.access_log);
+                execution.outcome
+            })
+            .collect();
+
+        let before_block = initial_storage();
+        let storage = Mutex::new(initial_storage());
+        let outcomes = BlockExecutor::new(4)
+            .execute(
+                &vec![0; transfers.len()],
+                |index| Ok(run(&transfers[index], &before_block)),
+                |_, access_log| {
+                    apply(&mut storage.lock().unwrap(), access_log);
+                    Ok(())
+                },
+                |index| {
+                    let mut storage = storage.lock().unwrap();
+                    let execution = run(&transfers[index], &storage);
+                    apply(&mut storage, &execution.access_log);
+                    Ok(execution)
+                },
+            )
+            .unwrap();
+
+        let outcomes_only: Vec<u64> = outcomes.iter().map(|outcome| outcome.outcome).collect();
+        let re_executed: Vec<bool> = outcomes.iter().map(|outcome| outcome.re_executed).collect();
+
+        assert_eq!(outcomes_only, expected);
+        assert_eq!(*storage.lock().unwrap(), expected_storage);
+        assert_eq!(re_executed, vec![false, false, true, true, false]);
+    }
+
+    /// The second call makes a nested call, whatever it did is invisible to the log.
+    fn escaping(index: usize, mut execution: Execution<u64>) -> Execution<u64> {
+        if index == 1 {
+            execution.access_log.mark_escaped();
+        }
+
+        execution
+    }
+
+    #[test]
+    fn separates_scopes_and_re_executes_after_escapes() {
+        let transfers = block();
+        let scopes = [0, 1, 2, 3, 4];
+
+        let outcomes = BlockExecutor::new(2)
+            .execute(
+                &scopes,
+                |index| Ok(escaping(index, run(&transfers[index], &initial_storage()))),
+                |_, _| Ok(()),
+                |index| Ok(escaping(index, run(&transfers[index], &initial_storage()))),
+            )
+            .unwrap();
+
+        let re_executed: Vec<bool> = outcomes.iter().map(|outcome| outcome.re_executed).collect();
+
+        assert_eq!(re_executed, vec![false, true, true, true, true]);
+    }
+
+    /// Runs the calls of a block on forks of a real instance, like `BlockExecutionTask`.
+    #[test]
+    fn re_executes_conflicting_calls_of_a_contract() {
+        let host = TestHost::default();
+        host.set(1, &[10]);
+
+        let mut live = host.runner(
+            r#"(func (export "increment") (param $from i32) (param $to i32) (result i32)
+                (local $value i32)
+                (local.set $value (i32.add (call $load_byte (local.get $from)) (i32.const 1)))
+                (call $store_byte (local.get $to) (local.get $value))
+                (local.get $value))"#,
+        );
+        let snapshot = live.snapshot().unwrap();
+        let live = Mutex::new(live);
+
+        // The second call reads what the first one writes, the third is independent.
+        let calls = [(1, 2), (2, 3), (4, 5)];
+        let run = |index: usize, speculative: bool| -> anyhow::Result<Execution<i32>> {
+            let mut env = host.env();
+            env.access_log = Some(Mutex::new(StorageAccessLog::default()));
+            if speculative {
+                env.storage_overlay = Some(Mutex::new(StorageOverlay::default()));
+                env.speculative = true;
+            }
+
+            let mut runner = live.lock().unwrap().fork_from(&snapshot, TEST_MAX_GAS, env)?;
+            let (from, to) = calls[index];
+            let result = runner.call("increment", &[Value::I32(from), Value::I32(to)])?;
+
+            Ok(Execution {
+                outcome: result[0].unwrap_i32(),
+                access_log: runner.take_storage_access_log().unwrap_or_default(),
+            })
+        };
+
+        let outcomes = BlockExecutor::new(3)
+            .execute(
+                &[0; 3],
+                |index| run(index, true),
+                |_, access_log| {
+                    host.storage.lock().unwrap().extend(access_log.writes().clone());
+                    Ok(())
+                },
+                |index| run(index, false),
+            )
+            .unwrap();
+
+        let outcomes_only: Vec<i32> = outcomes.iter().map(|outcome| outcome.outcome).collect();
+        let re_executed: Vec<bool> = outcomes.iter().map(|outcome| outcome.re_executed).collect();
+
+        assert_eq!(outcomes_only, vec![11, 12, 0]);
+        assert_eq!(re_executed, vec![false, true, false]);
+        assert_eq!(host.get(3), Some(vec![12]));
+    }
+}
@@ -1,36 +1,90 @@
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use napi::Error;
-use wasmer::{MemoryAccessError, Value};
+use wasmer::{MemoryAccessError, RuntimeError, Value};
 
-use crate::domain::runner::{AbortData, ContractRunner};
+use crate::domain::runner::{AbortData, CallOptions, CancellationToken, ContractRunner, ExecutionCancelled, ExecutionTimeout, ExecutionTrace, GasProfile, InstanceSnapshot, StorageReadCache};
 
 pub struct ContractService {
     max_gas: u64,
     runner: Arc<Mutex<dyn ContractRunner>>,
+    /// Set once a call was stopped midway. Its memory and globals are whatever the
+    /// contract had written by then, so the instance refuses every later call.
+    interrupted: bool,
 }
 
 impl ContractService {
     pub fn new(max_gas: u64, runner: Arc<Mutex<dyn ContractRunner>>) -> Self {
-        Self { max_gas, runner }
+        Self { max_gas, runner, interrupted: false }
     }
 
     pub fn call(&mut self, function: &str, params: &[Value]) -> anyhow::Result<Box<[Value]>> {
+        self.call_with_options(function, params, CallOptions::default())
+    }
+
+    /// Like [`ContractService::call`], but fails with [`ExecutionTimeout`] once the timeout
+    /// elapsed and with [`ExecutionCancelled`] once the cancellation is cancelled, including
+    /// while waiting for the host. The read cache and the access list only apply to this call.
+    /// Either failure leaves the instance unusable, see [`ContractService::is_interrupted`].
+    pub fn call_with_options(
+        &mut self,
+        function: &str,
+        params: &[Value],
+        options: CallOptions,
+    ) -> anyhow::Result<Box<[Value]>> {
+        let CallOptions {
+            timeout,
+            cancellation,
+            read_cache,
+            access_list,
+            is_static,
+        } = options;
+
+        if self.is_interrupted() {
+            return Err(anyhow::anyhow!("instance was left midway through a cancelled or timed out call"));
+        }
+
+        if cancellation.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(anyhow::Error::new(ExecutionCancelled));
+        }
+
         let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
-        let response = runner.call(function, params).map_err(|e| {
-            if e.to_string().contains("unreachable") {
-                let gas_used = runner.get_remaining_gas();
-                if gas_used == 0 {
-                    anyhow::anyhow!("out of gas")
-                } else {
-                    let out_of_memory = runner.is_out_of_memory().unwrap_or(false);
-
-                    if out_of_memory {
-                        anyhow::anyhow!("out of memory")
-                    } else {
-                        anyhow::anyhow!(e)
-                    }
-                }
+        runner.set_deadline(timeout.map(|timeout| Instant::now() + timeout));
+        runner.set_cancellation(cancellation.clone());
+        runner.set_read_cache(StorageReadCache::from_entries(read_cache));
+        runner.set_access_list(access_list);
+        runner.set_static(is_static);
+
+        let response = runner.call(function, params);
+        runner.set_deadline(None);
+        runner.set_cancellation(None);
+        runner.set_read_cache(StorageReadCache::default());
+        runner.set_access_list(None);
+        runner.set_static(false);
+
+        // Only a call that was stopped midway was interrupted. One that returned before the
+        // cancellation or the deadline already reached the host with its writes and calls.
+        if let Err(e) = &response {
+            if runner.is_timed_out() {
+                self.interrupted = true;
+                return Err(anyhow::Error::new(ExecutionTimeout));
+            }
+
+            // The watchdog drains the gas, a host wait fails the import right away.
+            let cancelled = cancellation.as_ref().is_some_and(CancellationToken::is_cancelled);
+            let host_cancelled = e.downcast_ref::<RuntimeError>().is_some_and(RuntimeError::is::<ExecutionCancelled>);
+            if cancelled && (runner.get_remaining_gas() == 0 || host_cancelled) {
+                self.interrupted = true;
+                return Err(anyhow::Error::new(ExecutionCancelled));
+            }
+        }
+
+        let response = response.map_err(|e| {
+            if e.to_string().contains("unreachable") && runner.get_remaining_gas() == 0 {
+                anyhow::anyhow!("out of gas")
+            } else if let Some(out_of_memory) = runner.get_out_of_memory() {
+                anyhow::Error::new(out_of_memory)
             } else {
                 anyhow::anyhow!(e)
             }
@@ -39,15 +93,26 @@ impl ContractService {
         response
     }
 
+    /// Whether a call was cancelled or timed out midway. Only a reset of the runner, which
+    /// comes with a new service, makes the instance usable again.
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupted
+    }
+
     pub fn get_used_gas(&mut self) -> u64 {
         let remaining_gas = self.get_remaining_gas();
-        let gas_used = self.max_gas - remaining_gas;
+        let gas_used = self.max_gas.saturating_sub(remaining_gas);
 
         gas_used
     }
 
     pub fn set_used_gas(&mut self, gas: u64) {
-        self.set_remaining_gas(self.max_gas - gas);
+        self.set_remaining_gas(self.max_gas.saturating_sub(gas));
+    }
+
+    pub fn get_constructor_gas_used(&self) -> u64 {
+        let runner = self.runner.lock().unwrap();
+        runner.get_constructor_gas_used()
     }
 
     pub fn get_remaining_gas(&mut self) -> u64 {
@@ -57,7 +122,7 @@ impl ContractService {
 
     pub fn set_remaining_gas(&mut self, gas: u64) {
         let mut runner = self.runner.lock().unwrap();
-        runner.set_remaining_gas(self.max_gas - gas);
+        runner.set_remaining_gas(self.max_gas.saturating_sub(gas));
     }
 
     pub fn use_gas(&mut self, gas: u64) {
@@ -85,6 +150,41 @@ impl ContractService {
         runner.get_abort_data()
     }
 
+    pub fn get_gas_profile(&self) -> Option<GasProfile> {
+        let mut runner = self.runner.lock().unwrap();
+        runner.get_gas_profile()
+    }
+
+    pub fn start_trace(&self) {
+        let mut runner = self.runner.lock().unwrap();
+        runner.start_trace()
+    }
+
+    pub fn take_trace(&self) -> Option<ExecutionTrace> {
+        let mut runner = self.runner.lock().unwrap();
+        runner.take_trace()
+    }
+
+    pub fn start_replay(&self, trace: ExecutionTrace) -> anyhow::Result<()> {
+        let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+        runner.start_replay(trace)
+    }
+
+    pub fn finish_replay(&self) -> anyhow::Result<()> {
+        let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+        runner.finish_replay()
+    }
+
+    pub fn snapshot(&self) -> anyhow::Result<InstanceSnapshot> {
+        let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+        runner.snapshot()
+    }
+
+    pub fn restore(&self, snapshot: &InstanceSnapshot) -> anyhow::Result<()> {
+        let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+        runner.restore(snapshot)
+    }
+
     #[allow(dead_code)]
     fn print_results(&mut self, response: &anyhow::Result<Box<[Value]>>) {
         let mut runner = self.runner.lock().unwrap();
@@ -102,7 +202,172 @@ impl ContractService {
         }
 
         let max_gas = self.max_gas;
-        let gas_used = max_gas - remaining_gas;
+        let gas_used = max_gas.saturating_sub(remaining_gas);
         println!("Gas used: {gas_used}/{max_gas}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    use crate::domain::runner::test_host::{TestHost, TEST_MAX_GAS};
+    use crate::domain::runner::{AccessList, OutOfMemory, WasmerRunner};
+
+    /// Cancels its token right after each call returned, before the service looks at it.
+    struct CancelledAfterCall {
+        runner: WasmerRunner,
+        cancellation: CancellationToken,
+    }
+
+    impl ContractRunner for CancelledAfterCall {
+        fn call(&mut self, function: &str, params: &[Value]) -> anyhow::Result<Box<[Value]>> {
+            let response = ContractRunner::call(&mut self.runner, function, params);
+            self.cancellation.cancel();
+
+            response
+        }
+
+        fn set_deadline(&mut self, deadline: Option<Instant>) {
+            self.runner.set_deadline(deadline)
+        }
+
+        fn set_cancellation(&mut self, cancellation: Option<CancellationToken>) {
+            self.runner.set_cancellation(cancellation)
+        }
+
+        fn is_timed_out(&self) -> bool {
+            ContractRunner::is_timed_out(&self.runner)
+        }
+
+        fn set_read_cache(&mut self, read_cache: StorageReadCache) {
+            self.runner.set_read_cache(read_cache)
+        }
+
+        fn set_access_list(&mut self, access_list: Option<AccessList>) {
+            self.runner.set_access_list(access_list)
+        }
+
+        fn set_static(&mut self, is_static: bool) {
+            self.runner.set_static(is_static)
+        }
+
+        fn read_memory(&self, offset: u64, length: u64) -> Result<Vec<u8>, MemoryAccessError> {
+            self.runner.read_memory(offset, length)
+        }
+
+        fn write_memory(&self, offset: u64, data: &[u8]) -> Result<(), MemoryAccessError> {
+            self.runner.write_memory(offset, data)
+        }
+
+        fn write_buffer(&mut self, value: &[u8], id: i32, align: u32) -> Result<i64, Error> {
+            self.runner.write_buffer(value, id, align)
+        }
+
+        fn get_remaining_gas(&mut self) -> u64 {
+            self.runner.get_remaining_gas()
+        }
+
+        fn get_out_of_memory(&mut self) -> Option<OutOfMemory> {
+            ContractRunner::get_out_of_memory(&mut self.runner)
+        }
+
+        fn set_remaining_gas(&mut self, gas: u64) {
+            self.runner.set_remaining_gas(gas)
+        }
+
+        fn use_gas(&mut self, gas: u64) {
+            self.runner.use_gas(gas)
+        }
+
+        fn get_abort_data(&self) -> Option<AbortData> {
+            self.runner.get_abort_data()
+        }
+
+        fn get_gas_profile(&mut self) -> Option<GasProfile> {
+            self.runner.get_gas_profile()
+        }
+
+        fn get_constructor_gas_used(&self) -> u64 {
+            self.runner.get_constructor_gas_used()
+        }
+
+        fn start_trace(&mut self) {
+            self.runner.start_trace()
+        }
+
+        fn take_trace(&mut self) -> Option<ExecutionTrace> {
+            self.runner.take_trace()
+        }
+
+        fn start_replay(&mut self, trace: ExecutionTrace) -> anyhow::Result<()> {
+            self.runner.start_replay(trace)
+        }
+
+        fn finish_replay(&mut self) -> anyhow::Result<()> {
+            self.runner.finish_replay()
+        }
+
+        fn snapshot(&mut self) -> anyhow::Result<InstanceSnapshot> {
+            self.runner.snapshot()
+        }
+
+        fn restore(&mut self, snapshot: &InstanceSnapshot) -> anyhow::Result<()> {
+            self.runner.restore(snapshot)
+        }
+    }
+
+    #[test]
+    fn refuses_calls_once_a_call_was_cancelled_midway() {
+        let host = TestHost::default();
+        let cancellation = CancellationToken::new();
+        host.on_call({
+            let cancellation = cancellation.clone();
+            move |_, _| {
+                cancellation.cancel();
+                Ok(TestHost::call_response(&[1], 0))
+            }
+        });
+
+        let runner = host.runner(
+            r#"(global $calls (mut i32) (i32.const 0))
+            (func (export "run") (result i32)
+                (global.set $calls (i32.add (global.get $calls) (i32.const 1)))
+                (drop (call $call_byte))
+                (loop (br 0))
+                (global.get $calls))"#,
+        );
+        let mut service = ContractService::new(TEST_MAX_GAS, Arc::new(Mutex::new(runner)));
+
+        let options = CallOptions { cancellation: Some(cancellation), ..Default::default() };
+        let error = service.call_with_options("run", &[], options).unwrap_err();
+        assert!(error.is::<ExecutionCancelled>());
+        assert!(service.is_interrupted());
+
+        let error = service.call("run", &[]).unwrap_err();
+        assert!(error.to_string().contains("midway"), "{}", error);
+        assert_eq!(host.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn returns_calls_that_finished_before_the_cancellation() {
+        let host = TestHost::default();
+        let cancellation = CancellationToken::new();
+        let runner = host.runner(
+            r#"(func (export "run") (result i32)
+                (call $store_byte (i32.const 1) (i32.const 7))
+                (i32.const 7))"#,
+        );
+        let runner = CancelledAfterCall { runner, cancellation: cancellation.clone() };
+        let mut service = ContractService::new(TEST_MAX_GAS, Arc::new(Mutex::new(runner)));
+
+        let options = CallOptions { cancellation: Some(cancellation), ..Default::default() };
+        let result = service.call_with_options("run", &[], options).unwrap();
+
+        assert_eq!(result[0], Value::I32(7));
+        assert_eq!(host.get(1), Some(vec![7]));
+        assert!(!service.is_interrupted());
+        assert!(service.call("run", &[]).is_ok());
+    }
+}
@@ -0,0 +1,168 @@
+use wasmer::Value;
+
+pub struct GasEstimate {
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    pub result: Box<[Value]>,
+    pub attempts: u32,
+}
+
+/// Finds the smallest gas limit a call succeeds with.
+///
+/// `gas_used` alone is not a safe quote: a contract may branch on its remaining gas or on
+/// the outcome of sub-calls, so every candidate limit is actually executed. Each attempt
+/// must run on a copy of the instance whose storage writes are discarded.
+pub struct GasEstimator {
+    max_gas: u64,
+}
+
+impl GasEstimator {
+    pub fn new(max_gas: u64) -> Self {
+        Self { max_gas }
+    }
+
+    /// `execute` runs the call with the given gas limit and returns its result and the gas used.
+    pub fn estimate<F>(&self, mut execute: F) -> anyhow::Result<GasEstimate>
+    where
+        F: FnMut(u64) -> anyhow::Result<(Box<[Value]>, u64)>,
+    {
+        let mut attempts = 1;
+        let (result, gas_used) = execute(self.max_gas)
+            .map_err(|e| anyhow::anyhow!("call fails with the maximum gas limit: {}", e))?;
+
+        let mut best = GasEstimate {
+            gas_limit: self.max_gas,
+            gas_used,
+            result,
+            attempts,
+        };
+
+        if gas_used >= self.max_gas {
+            return Ok(best);
+        }
+
+        // The call can never succeed with less than what it used, so start there.
+        attempts += 1;
+        if let Ok((result, gas_used)) = execute(gas_used) {
+            return Ok(GasEstimate {
+                gas_limit: gas_used,
+                gas_used,
+                result,
+                attempts,
+            });
+        }
+
+        let mut low = gas_used + 1;
+        let mut high = self.max_gas;
+        while low < high {
+            let mid = low + (high - low) / 2;
+
+            attempts += 1;
+            match execute(mid) {
+                Ok((result, gas_used)) => {
+                    high = mid;
+                    best = GasEstimate {
+                        gas_limit: mid,
+                        gas_used,
+                        result,
+                        attempts,
+                    };
+                }
+                Err(_) => low = mid + 1,
+            }
+        }
+
+        best.attempts = attempts;
+
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, Mutex};
+
+    use crate::application::contract::ContractService;
+    use crate::domain::runner::test_host::{TestHost, TEST_MAX_GAS};
+    use crate::domain::runner::StorageOverlay;
+    use crate::interfaces::HostRequestMode;
+
+    fn run(gas_limit: u64, gas_used: u64, required: u64) -> anyhow::Result<(Box<[Value]>, u64)> {
+        if gas_limit < required {
+            return Err(anyhow::anyhow!("out of gas"));
+        }
+
+        Ok((vec![Value::I32(1)].into_boxed_slice(), gas_used))
+    }
+
+    #[test]
+    fn returns_gas_used_when_it_is_enough() {
+        let estimate = GasEstimator::new(1_000_000).estimate(|limit| run(limit, 21_000, 21_000)).unwrap();
+
+        assert_eq!(estimate.gas_limit, 21_000);
+        assert_eq!(estimate.gas_used, 21_000);
+        assert_eq!(estimate.attempts, 2);
+    }
+
+    #[test]
+    fn searches_the_minimal_limit_when_the_call_needs_headroom() {
+        // Reserves gas for a sub-call before using it, like a contract checking its remaining gas.
+        let estimate = GasEstimator::new(1_000_000).estimate(|limit| run(limit, 21_000, 63_123)).unwrap();
+
+        assert_eq!(estimate.gas_limit, 63_123);
+        assert_eq!(estimate.gas_used, 21_000);
+        assert_eq!(estimate.result.len(), 1);
+    }
+
+    #[test]
+    fn fails_when_the_maximum_is_not_enough() {
+        let estimate = GasEstimator::new(1_000).estimate(|limit| run(limit, 1_000, 5_000));
+
+        assert!(estimate.is_err());
+    }
+
+    #[test]
+    fn charges_calls_to_other_contracts_and_deployments() {
+        let contract = r#"(func (export "run") (result i32)
+            (call $store_byte (i32.const 1) (i32.const 1))
+            (call $deploy)
+            (call $call_byte))"#;
+        let answer = |_: &[u8], mode: HostRequestMode| {
+            Ok(match mode.is_simulation {
+                true => TestHost::simulated_call_response(&[1], 50_000_000),
+                false => TestHost::call_response(&[1], 50_000_000),
+            })
+        };
+
+        let host = TestHost::default();
+        host.on_call(answer);
+        let mut call = ContractService::new(TEST_MAX_GAS, Arc::new(Mutex::new(host.runner(contract))));
+        call.call("run", &[]).unwrap();
+
+        // Like a gas estimation task, on forks whose storage writes stay in an overlay.
+        let estimated = TestHost::default();
+        estimated.on_call(answer);
+        let mut live = estimated.runner(contract);
+        let estimate = GasEstimator::new(TEST_MAX_GAS)
+            .estimate(|gas_limit| {
+                let mut env = estimated.env();
+                env.storage_overlay = Some(Mutex::new(StorageOverlay::default()));
+                env.simulation = true;
+
+                let mut contract = ContractService::new(gas_limit, Arc::new(Mutex::new(live.fork(gas_limit, env)?)));
+                let result = contract.call("run", &[])?;
+
+                Ok((result, contract.get_used_gas()))
+            })
+            .unwrap();
+
+        assert_eq!(estimate.result[0], Value::I32(1));
+        assert_eq!(estimate.gas_used, call.get_used_gas());
+        assert_eq!(estimate.gas_limit, estimate.gas_used);
+        assert_eq!(estimated.calls.load(Ordering::SeqCst), estimate.attempts as usize);
+        assert_eq!(estimated.deployments.load(Ordering::SeqCst), estimate.attempts as usize);
+        assert_eq!(estimated.stores.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,3 +1,7 @@
+pub use self::block_executor::*;
 pub use self::contract_service::*;
+pub use self::gas_estimator::*;
 
+mod block_executor;
 mod contract_service;
+mod gas_estimator;
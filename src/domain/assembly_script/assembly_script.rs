@@ -94,8 +94,10 @@ impl AssemblyScript {
             .write_memory(store, pinned_buffer_value as u64, &value)
             .unwrap();
 
-        // Unpin the buffer
-        Self::__unpin(store, instance, pinned_buffer_value as i32).unwrap();
+        // Unpin the buffer, the call traps if the contract was interrupted meanwhile
+        if let Err(e) = Self::__unpin(store, instance, pinned_buffer_value as i32) {
+            return Err(Error::from_reason(format!("Failed to unpin buffer: {:?}", e)));
+        }
 
         Ok(header_value as i64)
     }
@@ -135,6 +137,25 @@ impl AssemblyScript {
         Ok(buffer)
     }
 
+    /// Reads a `string`, stored as UTF-16 with its byte length in the object header.
+    pub fn read_string(
+        store: &(impl AsStoreRef + ?Sized),
+        instance: &InstanceWrapper,
+        offset: u32,
+    ) -> anyhow::Result<String> {
+        let header_offset = offset.checked_sub(4).ok_or(anyhow!("invalid string pointer"))?;
+        let header = instance.read_memory(store, header_offset as u64, 4)?;
+        let length = Self::bytes_to_u32_le(header, 0);
+
+        let bytes = instance.read_memory(store, offset as u64, length as u64)?;
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+
+        Ok(String::from_utf16_lossy(&units))
+    }
+
     pub fn read_buffer(
         store: &(impl AsStoreRef + ?Sized),
         instance: &InstanceWrapper,
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::domain::runner::{
+    ACCESS_LIST_ADDRESS_COST, ACCESS_LIST_STORAGE_KEY_COST, MAX_ACCESS_LIST_ADDRESSES, MAX_ACCESS_LIST_STORAGE_KEYS,
+    STORAGE_POINTER_SIZE,
+};
+
+/// A strict call touched a storage key or a contract it did not declare.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessListViolation {
+    pub access: String,
+}
+
+impl fmt::Display for AccessListViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "access list violation: {} was not declared", self.access)
+    }
+}
+
+impl std::error::Error for AccessListViolation {}
+
+/// Storage keys and contracts a call declares up front. Declared keys are prefetched and
+/// every access to them is charged the warm price, the declaration itself is paid for
+/// when the call starts.
+///
+/// `call` and `deployFromAddress` requests start with the address of the contract they
+/// target, terminated by a NUL byte as `encodeAddress` produces it. That address must be
+/// declared exactly for the request to be warm.
+#[derive(Clone, Debug, Default)]
+pub struct AccessList {
+    storage_keys: HashSet<Vec<u8>>,
+    addresses: HashSet<Vec<u8>>,
+    /// Fails any access outside the list instead of charging the cold price.
+    strict: bool,
+}
+
+impl AccessList {
+    pub fn new(
+        storage_keys: impl IntoIterator<Item = Vec<u8>>,
+        addresses: impl IntoIterator<Item = Vec<u8>>,
+        strict: bool,
+    ) -> Result<Self, String> {
+        let storage_keys: HashSet<Vec<u8>> = storage_keys.into_iter().collect();
+        if storage_keys.len() > MAX_ACCESS_LIST_STORAGE_KEYS {
+            return Err(format!(
+                "At most {} storage keys can be declared, got {}",
+                MAX_ACCESS_LIST_STORAGE_KEYS,
+                storage_keys.len()
+            ));
+        }
+
+        if let Some(key) = storage_keys.iter().find(|key| key.len() != STORAGE_POINTER_SIZE) {
+            return Err(format!(
+                "Storage key must be {} bytes, got {}",
+                STORAGE_POINTER_SIZE,
+                key.len()
+            ));
+        }
+
+        let addresses: HashSet<Vec<u8>> = addresses.into_iter().collect();
+        if addresses.len() > MAX_ACCESS_LIST_ADDRESSES {
+            return Err(format!(
+                "At most {} addresses can be declared, got {}",
+                MAX_ACCESS_LIST_ADDRESSES,
+                addresses.len()
+            ));
+        }
+
+        if addresses.iter().any(|address| address.is_empty() || address.contains(&0)) {
+            return Err("Address must not be empty or contain a NUL byte".to_string());
+        }
+
+        Ok(Self {
+            storage_keys,
+            addresses,
+            strict,
+        })
+    }
+
+    pub fn storage_keys(&self) -> impl Iterator<Item = &Vec<u8>> {
+        self.storage_keys.iter()
+    }
+
+    /// Gas charged for the declaration when the call starts.
+    pub fn declaration_cost(&self) -> u64 {
+        ACCESS_LIST_STORAGE_KEY_COST
+            .saturating_mul(self.storage_keys.len() as u64)
+            .saturating_add(ACCESS_LIST_ADDRESS_COST.saturating_mul(self.addresses.len() as u64))
+    }
+
+    /// Price of an access to `pointer`, `warm` if it was declared.
+    pub fn storage_key_price(&self, pointer: &[u8], cold: u64, warm: u64) -> Result<u64, AccessListViolation> {
+        if self.storage_keys.contains(pointer) {
+            return Ok(warm);
+        }
+
+        self.undeclared(cold, || format!("storage key 0x{}", hex::encode(pointer)))
+    }
+
+    /// Price of a request addressed to another contract, `warm` if the contract was declared.
+    pub fn address_price(&self, request: &[u8], cold: u64, warm: u64) -> Result<u64, AccessListViolation> {
+        let address = Self::request_address(request);
+        if address.is_some_and(|address| self.addresses.contains(address)) {
+            return Ok(warm);
+        }
+
+        self.undeclared(cold, || match address {
+            Some(address) => format!("contract {}", String::from_utf8_lossy(address)),
+            None => "contract".to_string(),
+        })
+    }
+
+    /// The address a request starts with, `None` if it is not terminated.
+    fn request_address(request: &[u8]) -> Option<&[u8]> {
+        let end = request.iter().position(|byte| *byte == 0)?;
+
+        Some(&request[..end])
+    }
+
+    fn undeclared(&self, cold: u64, access: impl FnOnce() -> String) -> Result<u64, AccessListViolation> {
+        if self.strict {
+            return Err(AccessListViolation { access: access() });
+        }
+
+        Ok(cold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Vec<u8> {
+        vec![byte; STORAGE_POINTER_SIZE]
+    }
+
+    #[test]
+    fn charges_warm_prices_for_declared_accesses_only() {
+        let list = AccessList::new([key(1)], [b"bc1qdeclared".to_vec()], false).unwrap();
+
+        assert_eq!(list.storage_key_price(&key(1), 10, 1), Ok(1));
+        assert_eq!(list.storage_key_price(&key(2), 10, 1), Ok(10));
+        assert_eq!(list.address_price(b"bc1qdeclared\x00calldata", 10, 1), Ok(1));
+        assert_eq!(list.address_price(b"bc1qother\x00calldata", 10, 1), Ok(10));
+        assert_eq!(
+            list.declaration_cost(),
+            ACCESS_LIST_STORAGE_KEY_COST + ACCESS_LIST_ADDRESS_COST
+        );
+    }
+
+    #[test]
+    fn strict_lists_reject_undeclared_accesses() {
+        let list = AccessList::new([key(1)], [], true).unwrap();
+
+        assert_eq!(list.storage_key_price(&key(1), 10, 1), Ok(1));
+        assert!(list.storage_key_price(&key(2), 10, 1).is_err());
+        assert!(list.address_price(b"bc1qother", 10, 1).is_err());
+    }
+
+    #[test]
+    fn matches_the_whole_address_only() {
+        let list = AccessList::new([], [b"bc1q".to_vec()], true).unwrap();
+
+        assert_eq!(list.address_price(b"bc1q\x00calldata", 10, 1), Ok(1));
+        assert!(list.address_price(b"bc1qother\x00calldata", 10, 1).is_err());
+        assert!(list.address_price(b"bc1q", 10, 1).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_or_oversized_lists() {
+        assert!(AccessList::new([vec![1; 20]], [], false).is_err());
+        assert!(AccessList::new([], [Vec::new()], false).is_err());
+        assert!(AccessList::new([], [b"bc1q\x00".to_vec()], false).is_err());
+
+        let keys = (0..=MAX_ACCESS_LIST_STORAGE_KEYS as u32).map(|index| {
+            let mut key = vec![0; STORAGE_POINTER_SIZE];
+            key[..4].copy_from_slice(&index.to_le_bytes());
+            key
+        });
+        assert!(AccessList::new(keys, [], false).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum BitcoinNetwork {
     Mainnet,
     Testnet,
@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+use crate::domain::runner::{AccessList, CancellationToken};
+
+/// Per-call settings of the host. None of them changes the gas a call uses.
+#[derive(Clone, Default)]
+pub struct CallOptions {
+    /// Wall-clock budget, including time spent waiting for the host.
+    pub timeout: Option<Duration>,
+    pub cancellation: Option<CancellationToken>,
+    /// Storage values the host already knows, served to `load` and `loadMany` without a
+    /// roundtrip.
+    pub read_cache: Vec<(Vec<u8>, Vec<u8>)>,
+    pub access_list: Option<AccessList>,
+    /// Fails the call if it writes storage, deploys or emits events, nested calls included.
+    pub is_static: bool,
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+use crate::domain::runner::Watchdog;
+
+/// A call was cancelled by its caller before it finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecutionCancelled;
+
+impl fmt::Display for ExecutionCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution cancelled")
+    }
+}
+
+impl std::error::Error for ExecutionCancelled {}
+
+#[derive(Default)]
+struct CancellationState {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+/// Shared flag that stops the calls it was handed to. Cancelling is permanent, a token
+/// can not be reused once cancelled.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<CancellationState>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops running calls at their next metering check and wakes up pending host awaits.
+    pub fn cancel(&self) {
+        if self.state.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        self.state.notify.notify_waiters();
+        Watchdog::global().wake();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // Registered before the check, `notify_waiters` does not store a permit.
+            let notified = self.state.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wakes_up_pending_awaits() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let token = CancellationToken::new();
+        let clone = token.clone();
+
+        let cancel = std::thread::spawn(move || clone.cancel());
+        runtime.block_on(token.cancelled());
+        cancel.join().unwrap();
+
+        assert!(token.is_cancelled());
+    }
+}
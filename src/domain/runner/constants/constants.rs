@@ -1,8 +1,15 @@
 pub const MAX_PAGES: u32 = 128 * 4; // 1 page = 64KB, 32 MB.
 pub const STACK_SIZE: usize = 1024 * 1024; // 1MB
-pub const MAX_GAS_CONSTRUCTOR: u64 = 100_000_000; // only allow 100_000_000 gas for constructor
+pub const MAX_PAGES_LIMIT: u32 = 128 * 16; // highest per-contract memory limit by default, 128 MB
+pub const MIN_STACK_SIZE: usize = 64 * 1024; // 64KB
+pub const MAX_STACK_SIZE_LIMIT: usize = 8 * 1024 * 1024; // highest per-contract stack size by default, 8MB
+pub const MAX_GAS_CONSTRUCTOR: u64 = 100_000_000; // initial metering points, replaced by the caller's limit before the constructor runs
 pub const PAGE_MEMORY_SIZE: u64 = 64 * 1024;
-pub const MAX_MEMORY_SIZE: u64 = (MAX_PAGES as u64) * PAGE_MEMORY_SIZE;
+pub const MAX_GLOBALS: usize = 32_768; // per instance, leaves room for the globals injected by metering and gas profiling
+pub const MAX_CACHED_ENGINES: usize = 16; // per kind of engine, the least recently used one is dropped past it
+pub const DEFAULT_CONTRACT_CACHE_SIZE: u64 = 512 * 1024 * 1024; // 512 MiB of compiled contracts in memory
+pub const DEFAULT_MAX_POOLED_INSTANCES: usize = 4; // idle instances kept per compiled module
+pub const DEFAULT_MODULE_CACHE_SIZE: u64 = 1024 * 1024 * 1024; // 1 GiB of compiled artifacts on disk
 
 /** Gas cost for custom functions */
 pub const LOAD_COST: u64 = 21_000_000;
@@ -11,3 +18,15 @@ pub const CALL_COST: u64 = 343_000_000;
 pub const DEPLOY_COST: u64 = 2_500_000_000;
 pub const ENCODE_ADDRESS_COST: u64 = 4_000_000;
 pub const SHA256_COST: u64 = 1_000_000;
+pub const DEPLOYMENT_GAS_PER_BYTE: u64 = 20_000;
+
+/** Gas cost of accesses declared in the access list of a call */
+pub const WARM_LOAD_COST: u64 = 1_000_000;
+pub const WARM_STORE_COST: u64 = 200_000_000;
+pub const WARM_CALL_COST: u64 = 318_000_000;
+pub const ACCESS_LIST_STORAGE_KEY_COST: u64 = 19_000_000; // per declared key, paid when the call starts
+pub const ACCESS_LIST_ADDRESS_COST: u64 = 24_000_000; // per declared contract, paid when the call starts
+pub const MAX_ACCESS_LIST_STORAGE_KEYS: usize = 1024; // per call, bounds the prefetch
+pub const MAX_ACCESS_LIST_ADDRESSES: usize = 256; // per call
+pub const STATIC_CALL_CONFIRMATION: &[u8] = b"static"; // follows the gas in the host's answer to a `call` made by a static call
+pub const SIMULATION_CONFIRMATION: &[u8] = b"simulated"; // in the host's answer to a `call` or `deployFromAddress` made while estimating gas
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+/// Identifies compiled code independently of the addresses it is deployed at.
+pub fn code_hash(bytecode: &[u8]) -> [u8; 32] {
+    Sha256::digest(bytecode).into()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the entry that was used the longest time ago.
+    #[default]
+    Lru,
+    /// Evict the entry that was used the fewest times, oldest first on ties.
+    Lfu,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ContractCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub pinned: usize,
+    pub size: u64,
+    pub max_size: u64,
+}
+
+struct ContractCacheEntry<V> {
+    value: V,
+    size: u64,
+    last_used: u64,
+    uses: u64,
+    pinned: bool,
+}
+
+/// In-memory cache of compiled contracts with a byte budget.
+///
+/// Pinned entries are never evicted, but still count towards the budget, so pinning
+/// more than `max_size` bytes leaves no room for anything else.
+pub struct ContractCache<V: Clone> {
+    entries: HashMap<String, ContractCacheEntry<V>>,
+    policy: EvictionPolicy,
+    max_size: u64,
+    size: u64,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl<V: Clone> ContractCache<V> {
+    pub fn new(max_size: u64, policy: EvictionPolicy) -> Self {
+        Self {
+            entries: HashMap::new(),
+            policy,
+            max_size,
+            size: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<V> {
+        self.clock += 1;
+
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                entry.uses += 1;
+                self.hits += 1;
+
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses += 1;
+
+                None
+            }
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Inserts `value`, evicting other entries if needed. Returns `false` without caching
+    /// when the value does not fit next to the pinned entries.
+    pub fn insert(&mut self, key: String, value: V, size: u64) -> bool {
+        if size > self.max_size {
+            return false;
+        }
+
+        let pinned = self.remove(&key).unwrap_or(false);
+
+        // Make room first, so a fresh entry is never the one chosen for eviction.
+        self.evict(size);
+        if self.size + size > self.max_size {
+            return false;
+        }
+
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            ContractCacheEntry {
+                value,
+                size,
+                last_used: self.clock,
+                uses: 1,
+                pinned,
+            },
+        );
+        self.size += size;
+
+        true
+    }
+
+    /// Removes an entry whether or not it is pinned, returning whether it was pinned.
+    pub fn remove(&mut self, key: &str) -> Option<bool> {
+        let entry = self.entries.remove(key)?;
+        self.size -= entry.size;
+
+        Some(entry.pinned)
+    }
+
+    pub fn pin(&mut self, key: &str) -> bool {
+        self.set_pinned(key, true)
+    }
+
+    pub fn unpin(&mut self, key: &str) -> bool {
+        let unpinned = self.set_pinned(key, false);
+        self.evict(0);
+
+        unpinned
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+    }
+
+    pub fn stats(&self) -> ContractCacheStats {
+        ContractCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            entries: self.entries.len(),
+            pinned: self.entries.values().filter(|entry| entry.pinned).count(),
+            size: self.size,
+            max_size: self.max_size,
+        }
+    }
+
+    fn set_pinned(&mut self, key: &str, pinned: bool) -> bool {
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.pinned = pinned;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn evict(&mut self, incoming: u64) {
+        while self.size + incoming > self.max_size {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| !entry.pinned)
+                .min_by_key(|(_, entry)| match self.policy {
+                    EvictionPolicy::Lru => (entry.last_used, 0),
+                    EvictionPolicy::Lfu => (entry.uses, entry.last_used),
+                })
+                .map(|(key, _)| key.clone());
+
+            match victim {
+                Some(key) => {
+                    self.remove(&key);
+                    self.evictions += 1;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_entry() {
+        let mut cache = ContractCache::new(100, EvictionPolicy::Lru);
+        cache.insert("a".to_string(), 1, 40);
+        cache.insert("b".to_string(), 2, 40);
+        cache.get("a");
+        cache.insert("c".to_string(), 3, 40);
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.stats().size, 80);
+    }
+
+    #[test]
+    fn lfu_evicts_the_least_frequently_used_entry() {
+        let mut cache = ContractCache::new(100, EvictionPolicy::Lfu);
+        cache.insert("a".to_string(), 1, 40);
+        cache.get("a");
+        cache.get("a");
+        cache.insert("b".to_string(), 2, 40);
+        cache.get("b");
+        cache.get("a");
+        cache.insert("c".to_string(), 3, 40);
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert!(cache.contains("c"));
+    }
+
+    #[test]
+    fn pinned_entries_survive_eviction() {
+        let mut cache = ContractCache::new(100, EvictionPolicy::Lru);
+        cache.insert("a".to_string(), 1, 60);
+        assert!(cache.pin("a"));
+        cache.insert("b".to_string(), 2, 60);
+
+        assert!(cache.contains("a"));
+        assert!(!cache.contains("b"));
+        assert_eq!(cache.stats().pinned, 1);
+
+        assert!(!cache.insert("huge".to_string(), 3, 101));
+    }
+
+    #[test]
+    fn counts_hits_and_misses() {
+        let mut cache = ContractCache::new(100, EvictionPolicy::Lru);
+        cache.insert("a".to_string(), 1, 10);
+
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.remove("a"), Some(false));
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.entries, stats.size), (1, 1, 0, 0));
+    }
+}
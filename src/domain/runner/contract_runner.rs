@@ -1,15 +1,38 @@
+use std::time::Instant;
+
 use wasmer::{MemoryAccessError, Value};
 
-use crate::domain::runner::AbortData;
+use crate::domain::runner::{AbortData, AccessList, CancellationToken, ExecutionTrace, GasProfile, InstanceSnapshot, OutOfMemory, StorageReadCache};
 
 pub trait ContractRunner: Send + Sync {
     fn call(&mut self, function: &str, params: &[Value]) -> anyhow::Result<Box<[Value]>>;
+    /// Wall-clock deadline of the following calls, `None` lets them run until out of gas.
+    fn set_deadline(&mut self, deadline: Option<Instant>);
+    /// Token that cancels the following calls, see [`CancellationToken`].
+    fn set_cancellation(&mut self, cancellation: Option<CancellationToken>);
+    fn is_timed_out(&self) -> bool;
+    /// Storage values served to the following calls without asking the host.
+    fn set_read_cache(&mut self, read_cache: StorageReadCache);
+    /// Accesses the following calls declare up front, see [`AccessList`].
+    fn set_access_list(&mut self, access_list: Option<AccessList>);
+    /// Makes the following calls static, see [`crate::domain::runner::StaticCallViolation`].
+    fn set_static(&mut self, is_static: bool);
     fn read_memory(&self, offset: u64, length: u64) -> Result<Vec<u8>, MemoryAccessError>;
     fn write_memory(&self, offset: u64, data: &[u8]) -> Result<(), MemoryAccessError>;
     fn write_buffer(&mut self, value: &[u8], id: i32, align: u32) -> Result<i64, napi::Error>;
     fn get_remaining_gas(&mut self) -> u64;
-    fn is_out_of_memory(&self) -> Result<bool, MemoryAccessError>;
+    fn get_out_of_memory(&mut self) -> Option<OutOfMemory>;
     fn set_remaining_gas(&mut self, gas: u64);
     fn use_gas(&mut self, gas: u64);
     fn get_abort_data(&self) -> Option<AbortData>;
+    fn get_gas_profile(&mut self) -> Option<GasProfile>;
+    fn get_constructor_gas_used(&self) -> u64;
+    /// Records the host imports of the following calls, see [`ExecutionTrace`].
+    fn start_trace(&mut self);
+    fn take_trace(&mut self) -> Option<ExecutionTrace>;
+    /// Serves the host imports of the following calls from a trace recorded on the same code.
+    fn start_replay(&mut self, trace: ExecutionTrace) -> anyhow::Result<()>;
+    fn finish_replay(&mut self) -> anyhow::Result<()>;
+    fn snapshot(&mut self) -> anyhow::Result<InstanceSnapshot>;
+    fn restore(&mut self, snapshot: &InstanceSnapshot) -> anyhow::Result<()>;
 }
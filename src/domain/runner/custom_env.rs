@@ -1,45 +1,246 @@
 use crate::domain::runner::bitcoin_network::BitcoinNetwork;
-use crate::domain::runner::{AbortData, InstanceWrapper};
+use crate::domain::runner::{AbortData, AccessList, CancellationToken, ExecutionTimeout, ExecutionTrace, TraceReplay, ImportGasProfile, InstanceWrapper, SpeculationAborted, StaticCallViolation, StorageAccessLog, StorageOverlay, StorageReadCache};
 use crate::interfaces::{
     CallOtherContractExternalFunction, ConsoleLogExternalFunction,
-    DeployFromAddressExternalFunction, StorageLoadExternalFunction,
-    StorageStoreExternalFunction,
+    DeployFromAddressExternalFunction, HostRequestMode, HostRuntime, StorageLoadExternalFunction,
+    StorageLoadManyExternalFunction, StorageStoreExternalFunction,
 };
-use std::sync::Arc;
-use tokio::runtime::Runtime;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use wasmer::RuntimeError;
 
 pub struct CustomEnv {
     pub instance: Option<InstanceWrapper>,
     pub network: BitcoinNetwork,
     pub abort_data: Option<AbortData>,
     pub storage_load_external: StorageLoadExternalFunction,
+    pub storage_load_many_external: StorageLoadManyExternalFunction,
     pub storage_store_external: StorageStoreExternalFunction,
     pub call_other_contract_external: CallOtherContractExternalFunction,
     pub deploy_from_address_external: DeployFromAddressExternalFunction,
     pub console_log_external: ConsoleLogExternalFunction,
-    pub runtime: Arc<Runtime>,
+    pub runtime: Arc<HostRuntime>,
+    pub gas_profile: Option<Mutex<ImportGasProfile>>,
+    pub storage_overlay: Option<Mutex<StorageOverlay>>,
+    /// Storage values known for the running call, see [`StorageReadCache`].
+    pub read_cache: Mutex<StorageReadCache>,
+    /// Accesses declared by the running call, see [`AccessList`].
+    pub access_list: Option<AccessList>,
+    /// The running call must not change state, see [`CustomEnv::check_static`].
+    pub is_static: bool,
+    /// Storage accessed by the running call, only kept for calls of a block.
+    pub access_log: Option<Mutex<StorageAccessLog>>,
+    /// The call runs ahead of its turn in a block and must not touch the host beyond its
+    /// storage.
+    pub speculative: bool,
+    /// The call only estimates its gas. Its nested calls and deployments still reach the
+    /// host, which runs them against throwaway state, see [`CustomEnv::request_mode`].
+    pub simulation: bool,
+    /// Records every import of the running call, see [`ExecutionTrace`].
+    pub trace: Option<Mutex<ExecutionTrace>>,
+    /// Serves the imports of the running call from a recorded trace instead of the host.
+    pub replay: Option<Mutex<TraceReplay>>,
+    /// Number of calls that left the VM, i.e. whose result depends on the host.
+    pub host_calls: AtomicU64,
+    /// Wall-clock deadline of the running call, also bounds the time spent awaiting JS.
+    pub deadline: Option<Instant>,
+    pub timed_out: Arc<AtomicBool>,
+    /// Cancels the running call and the host await it is blocked on.
+    pub cancellation: Option<CancellationToken>,
 }
 
 impl CustomEnv {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         network: BitcoinNetwork,
         storage_load_external: StorageLoadExternalFunction,
+        storage_load_many_external: StorageLoadManyExternalFunction,
         storage_store_external: StorageStoreExternalFunction,
         call_other_contract_external: CallOtherContractExternalFunction,
         deploy_from_address_external: DeployFromAddressExternalFunction,
         console_log_external: ConsoleLogExternalFunction,
-        runtime: Arc<Runtime>,
+        runtime: Arc<HostRuntime>,
     ) -> anyhow::Result<Self> {
         Ok(Self {
             instance: None,
             network,
             abort_data: None,
             storage_load_external,
+            storage_load_many_external,
             storage_store_external,
             call_other_contract_external,
             deploy_from_address_external,
             console_log_external,
             runtime,
+            gas_profile: None,
+            storage_overlay: None,
+            read_cache: Mutex::new(StorageReadCache::default()),
+            access_list: None,
+            is_static: false,
+            access_log: None,
+            speculative: false,
+            simulation: false,
+            trace: None,
+            replay: None,
+            host_calls: AtomicU64::new(0),
+            deadline: None,
+            timed_out: Arc::new(AtomicBool::new(false)),
+            cancellation: None,
         })
     }
+
+    pub fn record_host_call(&self) {
+        self.host_calls.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_host_calls(&self) -> u64 {
+        self.host_calls.load(Ordering::Relaxed)
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Flags the call as timed out when the host did not answer before the deadline, the
+    /// watchdog may not have noticed yet.
+    pub fn check_host_timeout(&self, error: RuntimeError) -> RuntimeError {
+        if error.is::<ExecutionTimeout>() {
+            self.timed_out.store(true, Ordering::SeqCst);
+        }
+
+        error
+    }
+
+    /// How the host must run the nested calls and deployments of the running call.
+    pub fn request_mode(&self) -> HostRequestMode {
+        HostRequestMode {
+            is_static: self.is_static,
+            is_simulation: self.simulation,
+        }
+    }
+
+    /// Fails imports that change state during a static call. Every import that writes
+    /// storage, deploys or emits events must call this before doing anything else.
+    pub fn check_static(&self, import: &'static str) -> Result<(), RuntimeError> {
+        if self.is_static {
+            return Err(RuntimeError::user(Box::new(StaticCallViolation { import })));
+        }
+
+        Ok(())
+    }
+
+    pub fn record_read(&self, pointer: &[u8]) {
+        if let Some(access_log) = &self.access_log {
+            access_log.lock().unwrap().record_read(pointer);
+        }
+    }
+
+    pub fn record_write(&self, request: &[u8]) {
+        if let Some(access_log) = &self.access_log {
+            access_log.lock().unwrap().record_write(request);
+        }
+
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().record_write(request);
+        }
+
+        if let Some(replay) = &self.replay {
+            replay.lock().unwrap().record_write(request);
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some() || self.replay.is_some()
+    }
+
+    pub fn begin_import(&self, import: &'static str, input: &[u8], gas_before: u64) -> Result<(), RuntimeError> {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().begin(import, input, gas_before);
+        }
+
+        if let Some(replay) = &self.replay {
+            replay
+                .lock()
+                .unwrap()
+                .begin(import, input, gas_before)
+                .map_err(|e| RuntimeError::user(Box::new(e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves what the running import acts upon. A replay serves the recorded value and
+    /// never calls `resolve`, so it never reaches the host.
+    pub fn import_result(
+        &self,
+        resolve: impl FnOnce() -> Result<Vec<u8>, RuntimeError>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        if let Some(replay) = &self.replay {
+            return replay.lock().unwrap().output().map_err(|e| RuntimeError::user(Box::new(e)));
+        }
+
+        let result = resolve()?;
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().set_output(&result);
+        }
+
+        Ok(result)
+    }
+
+    pub fn end_import(&self, gas_after: u64) -> Result<(), RuntimeError> {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().end(gas_after);
+        }
+
+        if let Some(replay) = &self.replay {
+            replay
+                .lock()
+                .unwrap()
+                .end(gas_after)
+                .map_err(|e| RuntimeError::user(Box::new(e)))?;
+        }
+
+        Ok(())
+    }
+
+    /// Notes an import whose effects the access log can not follow. Speculative calls
+    /// fail instead of making it.
+    pub fn record_host_effect(&self, import: &'static str) -> Result<(), RuntimeError> {
+        if let Some(access_log) = &self.access_log {
+            access_log.lock().unwrap().mark_escaped();
+        }
+
+        if self.speculative {
+            return Err(RuntimeError::user(Box::new(SpeculationAborted { import })));
+        }
+
+        Ok(())
+    }
+
+    /// Price of an access to a storage key, fails strict calls that did not declare it.
+    pub fn storage_key_price(&self, pointer: &[u8], cold: u64, warm: u64) -> Result<u64, RuntimeError> {
+        match &self.access_list {
+            Some(access_list) => access_list
+                .storage_key_price(pointer, cold, warm)
+                .map_err(|e| RuntimeError::user(Box::new(e))),
+            None => Ok(cold),
+        }
+    }
+
+    /// Price of a request to another contract, fails strict calls that did not declare it.
+    pub fn address_price(&self, request: &[u8], cold: u64, warm: u64) -> Result<u64, RuntimeError> {
+        match &self.access_list {
+            Some(access_list) => access_list
+                .address_price(request, cold, warm)
+                .map_err(|e| RuntimeError::user(Box::new(e))),
+            None => Ok(cold),
+        }
+    }
+
+    pub fn record_import_gas(&self, import: &'static str, gas: u64) {
+        if let Some(gas_profile) = &self.gas_profile {
+            gas_profile.lock().unwrap().record(import, gas);
+        }
+    }
 }
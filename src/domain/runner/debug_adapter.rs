@@ -0,0 +1,650 @@
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{Ipv4Addr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use wasmer::Value;
+
+use crate::domain::runner::{Breakpoint, DebugStop, Debugger, JsonValue};
+
+/// How often the adapter looks for a stop while waiting for the IDE.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Requests larger than this are not read.
+const MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+
+/// The only thread a contract has.
+const THREAD_ID: i64 = 1;
+
+const LOCALS_REFERENCE: i64 = 1;
+const GLOBALS_REFERENCE: i64 = 2;
+
+/// Serves the [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/)
+/// for a debug build on a local port, so an IDE can attach to it while the contract runs,
+/// e.g. through the `debugServer` setting of VS Code. One IDE is served at a time.
+///
+/// Breakpoints are set by function or by instruction, the module offset being the
+/// instruction reference. Source breakpoints are refused: the module carries no line
+/// information. Stops show the stopped function as the only frame, with its locals and the
+/// exported globals as scopes, and `readMemory` reads the linear memory.
+///
+/// The adapter must be closed before the runner of its debugger is dropped, see
+/// [`Debugger::attach`].
+pub struct DebugAdapter {
+    port: u16,
+    closed: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DebugAdapter {
+    /// Listens on `127.0.0.1:port`, any free port when `port` is 0.
+    pub fn listen(debugger: Arc<Debugger>, port: u16) -> anyhow::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        let port = listener.local_addr()?.port();
+
+        let closed = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let closed = closed.clone();
+            thread::Builder::new()
+                .name("opvm-debug-adapter".to_string())
+                .spawn(move || Self::serve(listener, debugger, &closed))?
+        };
+
+        Ok(Self {
+            port,
+            closed,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Ends the session and stops listening. Returns once the debugger is not used anymore.
+    pub fn close(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn serve(listener: TcpListener, debugger: Arc<Debugger>, closed: &AtomicBool) {
+        while !closed.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(false).is_ok() {
+                        DebugSession::new(debugger.clone()).run(stream, closed);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl Drop for DebugAdapter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// One IDE attached to the debugger.
+struct DebugSession {
+    debugger: Arc<Debugger>,
+    seq: i64,
+    function_breakpoints: Vec<Breakpoint>,
+    instruction_breakpoints: Vec<Breakpoint>,
+    stop: Option<DebugStop>,
+    /// Why the next stop happens, as DAP names it.
+    stop_reason: &'static str,
+    disconnected: bool,
+}
+
+impl DebugSession {
+    fn new(debugger: Arc<Debugger>) -> Self {
+        Self {
+            debugger,
+            seq: 0,
+            function_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            stop: None,
+            stop_reason: "breakpoint",
+            disconnected: false,
+        }
+    }
+
+    /// Answers the IDE and reports stops until it disconnects or the adapter is closed.
+    /// Requests are read on a thread of their own, so stops are seen while the IDE is quiet.
+    fn run(mut self, stream: TcpStream, closed: &AtomicBool) {
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let (sender, requests) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            while let Ok(request) = read_message(&mut reader) {
+                if sender.send(request).is_err() {
+                    return;
+                }
+            }
+        });
+
+        let mut writer = &stream;
+        while !self.disconnected && !closed.load(Ordering::SeqCst) {
+            let messages = match requests.recv_timeout(POLL_INTERVAL) {
+                Ok(request) => self.handle(&request),
+                Err(RecvTimeoutError::Timeout) => Vec::new(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+
+            let written = messages
+                .iter()
+                .chain(self.poll().as_ref())
+                .try_for_each(|message| write_message(&mut writer, message));
+            if written.is_err() {
+                break;
+            }
+        }
+
+        // Nobody is left to resume the contract.
+        self.debugger.detach();
+
+        let _ = stream.shutdown(Shutdown::Both);
+        let _ = reader.join();
+    }
+
+    fn handle(&mut self, request: &JsonValue) -> Vec<JsonValue> {
+        let command = request.get("command").and_then(JsonValue::as_str).unwrap_or_default();
+        let arguments = request.get("arguments").cloned().unwrap_or(JsonValue::Object(Vec::new()));
+
+        let result = match command {
+            "initialize" => {
+                let capabilities = JsonValue::object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsFunctionBreakpoints", true.into()),
+                    ("supportsInstructionBreakpoints", true.into()),
+                    ("supportsReadMemoryRequest", true.into()),
+                ]);
+                let response = self.response(request, Ok(capabilities));
+                let initialized = self.event("initialized", JsonValue::object([]));
+
+                return vec![response, initialized];
+            }
+            "attach" | "launch" | "configurationDone" => Ok(JsonValue::object([])),
+            "setFunctionBreakpoints" => {
+                self.function_breakpoints = Self::breakpoint_arguments(&arguments)
+                    .iter()
+                    .map(|breakpoint| {
+                        let name = breakpoint.get("name").and_then(JsonValue::as_str).unwrap_or_default();
+                        Breakpoint::Function(name.to_string())
+                    })
+                    .collect();
+
+                Ok(self.apply_breakpoints(true))
+            }
+            "setInstructionBreakpoints" => {
+                self.instruction_breakpoints = Self::breakpoint_arguments(&arguments)
+                    .iter()
+                    .map(|breakpoint| {
+                        let reference = breakpoint.get("instructionReference").and_then(JsonValue::as_str);
+                        let offset = breakpoint.get("offset").and_then(JsonValue::as_i64).unwrap_or_default();
+                        let address = reference.and_then(parse_reference).map(|address| address + offset);
+
+                        // A negative address matches no instruction and is reported as such.
+                        Breakpoint::Offset(address.and_then(|address| usize::try_from(address).ok()).unwrap_or(usize::MAX))
+                    })
+                    .collect();
+
+                Ok(self.apply_breakpoints(false))
+            }
+            "setBreakpoints" => {
+                let breakpoints = Self::breakpoint_arguments(&arguments)
+                    .iter()
+                    .map(|_| {
+                        JsonValue::object([
+                            ("verified", false.into()),
+                            ("message", "Contracts have no line information, use function or instruction breakpoints".into()),
+                        ])
+                    })
+                    .collect();
+
+                Ok(JsonValue::object([("breakpoints", JsonValue::Array(breakpoints))]))
+            }
+            "threads" => {
+                let thread = JsonValue::object([("id", THREAD_ID.into()), ("name", "contract".into())]);
+
+                Ok(JsonValue::object([("threads", JsonValue::Array(vec![thread]))]))
+            }
+            "stackTrace" => {
+                let frames: Vec<JsonValue> = self.stop.iter().map(Self::frame).collect();
+                let total = frames.len() as i64;
+
+                Ok(JsonValue::object([("stackFrames", JsonValue::Array(frames)), ("totalFrames", total.into())]))
+            }
+            "scopes" => {
+                let scope = |name: &str, reference: i64| {
+                    JsonValue::object([
+                        ("name", name.into()),
+                        ("variablesReference", reference.into()),
+                        ("expensive", false.into()),
+                    ])
+                };
+
+                Ok(JsonValue::object([(
+                    "scopes",
+                    JsonValue::Array(vec![scope("Locals", LOCALS_REFERENCE), scope("Globals", GLOBALS_REFERENCE)]),
+                )]))
+            }
+            "variables" => self.variables(&arguments),
+            "readMemory" => self.read_memory(&arguments),
+            "continue" => {
+                self.resume("breakpoint");
+                self.debugger.resume();
+
+                Ok(JsonValue::object([("allThreadsContinued", true.into())]))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.resume("step");
+                self.debugger.step();
+
+                Ok(JsonValue::object([]))
+            }
+            "pause" => {
+                self.stop_reason = "pause";
+                self.debugger.pause();
+
+                Ok(JsonValue::object([]))
+            }
+            "disconnect" => {
+                self.disconnected = true;
+
+                Ok(JsonValue::object([]))
+            }
+            command => Err(format!("{} is not supported", command)),
+        };
+
+        vec![self.response(request, result)]
+    }
+
+    /// A `stopped` event when the contract stopped since the last poll, a `continued` one
+    /// when it was resumed from elsewhere.
+    fn poll(&mut self) -> Option<JsonValue> {
+        let stop = self.debugger.poll();
+        if stop == self.stop {
+            return None;
+        }
+
+        self.stop = stop;
+        match self.stop {
+            Some(_) => {
+                let reason = std::mem::replace(&mut self.stop_reason, "breakpoint");
+                let body = JsonValue::object([
+                    ("reason", reason.into()),
+                    ("threadId", THREAD_ID.into()),
+                    ("allThreadsStopped", true.into()),
+                ]);
+
+                Some(self.event("stopped", body))
+            }
+            None => {
+                let body = JsonValue::object([("threadId", THREAD_ID.into()), ("allThreadsContinued", true.into())]);
+
+                Some(self.event("continued", body))
+            }
+        }
+    }
+
+    fn resume(&mut self, next_stop_reason: &'static str) {
+        self.stop = None;
+        self.stop_reason = next_stop_reason;
+    }
+
+    /// Sets the breakpoints of both kinds again, the debugger can only clear all of them.
+    /// Reports on the function breakpoints, or on the instruction ones.
+    fn apply_breakpoints(&self, functions: bool) -> JsonValue {
+        self.debugger.clear_breakpoints();
+
+        let mut reports = Vec::new();
+        for (is_function, breakpoint) in self
+            .function_breakpoints
+            .iter()
+            .map(|breakpoint| (true, breakpoint))
+            .chain(self.instruction_breakpoints.iter().map(|breakpoint| (false, breakpoint)))
+        {
+            let report = match self.debugger.set_breakpoint(breakpoint) {
+                Ok(()) => JsonValue::object([("verified", true.into())]),
+                Err(e) => JsonValue::object([("verified", false.into()), ("message", JsonValue::string(e.to_string()))]),
+            };
+
+            if is_function == functions {
+                reports.push(report);
+            }
+        }
+
+        JsonValue::object([("breakpoints", JsonValue::Array(reports))])
+    }
+
+    fn variables(&self, arguments: &JsonValue) -> Result<JsonValue, String> {
+        let stop = self.stop.as_ref().ok_or("the contract is not stopped")?;
+
+        let values: Vec<(String, &Value)> = match arguments.get("variablesReference").and_then(JsonValue::as_i64) {
+            Some(LOCALS_REFERENCE) => stop.locals.iter().map(|(index, value)| (format!("local{}", index), value)).collect(),
+            Some(GLOBALS_REFERENCE) => stop.globals.iter().map(|(name, value)| (name.clone(), value)).collect(),
+            _ => return Err("unknown variables reference".to_string()),
+        };
+
+        let variables = values
+            .into_iter()
+            .map(|(name, value)| {
+                let (value_type, value) = describe_value(value);
+                JsonValue::object([
+                    ("name", JsonValue::String(name)),
+                    ("value", JsonValue::String(value)),
+                    ("type", value_type.into()),
+                    ("variablesReference", 0.into()),
+                ])
+            })
+            .collect();
+
+        Ok(JsonValue::object([("variables", JsonValue::Array(variables))]))
+    }
+
+    fn read_memory(&self, arguments: &JsonValue) -> Result<JsonValue, String> {
+        let reference = arguments
+            .get("memoryReference")
+            .and_then(JsonValue::as_str)
+            .and_then(parse_reference)
+            .ok_or("invalid memory reference")?;
+        let offset = arguments.get("offset").and_then(JsonValue::as_i64).unwrap_or_default();
+        let count = arguments.get("count").and_then(JsonValue::as_i64).unwrap_or_default();
+
+        let address = u64::try_from(reference + offset).map_err(|_| "negative address")?;
+        let count = u64::try_from(count).map_err(|_| "negative count")?.min(MAX_MESSAGE_LENGTH as u64);
+        let memory = self.debugger.read_memory(address, count).map_err(|e| e.to_string())?;
+
+        Ok(JsonValue::object([
+            ("address", JsonValue::String(format!("0x{:x}", address))),
+            ("data", JsonValue::String(encode_base64(&memory))),
+        ]))
+    }
+
+    fn frame(stop: &DebugStop) -> JsonValue {
+        let mut frame = JsonValue::object([
+            ("id", 0.into()),
+            ("name", JsonValue::string(stop.function.as_str())),
+            ("line", 0.into()),
+            ("column", 0.into()),
+        ]);
+
+        if let (JsonValue::Object(entries), Some(offset)) = (&mut frame, stop.offset) {
+            entries.push(("instructionPointerReference".to_string(), JsonValue::String(format!("0x{:x}", offset))));
+        }
+
+        frame
+    }
+
+    fn breakpoint_arguments(arguments: &JsonValue) -> &[JsonValue] {
+        arguments.get("breakpoints").and_then(JsonValue::as_array).unwrap_or_default()
+    }
+
+    fn response(&mut self, request: &JsonValue, result: Result<JsonValue, String>) -> JsonValue {
+        let mut response = vec![
+            ("seq".to_string(), self.next_seq().into()),
+            ("type".to_string(), "response".into()),
+            ("request_seq".to_string(), request.get("seq").cloned().unwrap_or(JsonValue::Null)),
+            ("success".to_string(), result.is_ok().into()),
+            ("command".to_string(), request.get("command").cloned().unwrap_or(JsonValue::Null)),
+        ];
+
+        match result {
+            Ok(body) => response.push(("body".to_string(), body)),
+            Err(message) => response.push(("message".to_string(), JsonValue::String(message))),
+        }
+
+        JsonValue::Object(response)
+    }
+
+    fn event(&mut self, event: &str, body: JsonValue) -> JsonValue {
+        JsonValue::object([
+            ("seq", self.next_seq().into()),
+            ("type", "event".into()),
+            ("event", event.into()),
+            ("body", body),
+        ])
+    }
+
+    fn next_seq(&mut self) -> i64 {
+        self.seq += 1;
+
+        self.seq
+    }
+}
+
+/// Reads one `Content-Length` framed message.
+fn read_message(reader: &mut impl BufRead) -> std::io::Result<JsonValue> {
+    let invalid = |message: String| std::io::Error::new(ErrorKind::InvalidData, message);
+
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|e| invalid(e.to_string()))?);
+        }
+    }
+
+    let length = length
+        .filter(|length| *length <= MAX_MESSAGE_LENGTH)
+        .ok_or_else(|| invalid("missing or oversized Content-Length".to_string()))?;
+
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    let content = String::from_utf8(content).map_err(|e| invalid(e.to_string()))?;
+
+    JsonValue::parse(&content).map_err(invalid)
+}
+
+fn write_message(writer: &mut impl Write, message: &JsonValue) -> std::io::Result<()> {
+    let content = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", content.len(), content)?;
+
+    writer.flush()
+}
+
+/// A memory or instruction reference, `0x` prefixed hexadecimal or decimal.
+fn parse_reference(reference: &str) -> Option<i64> {
+    match reference.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => reference.parse().ok(),
+    }
+}
+
+fn describe_value(value: &Value) -> (&'static str, String) {
+    match value {
+        Value::I32(value) => ("i32", value.to_string()),
+        Value::I64(value) => ("i64", value.to_string()),
+        Value::F32(value) => ("f32", value.to_string()),
+        Value::F64(value) => ("f64", value.to_string()),
+        value => ("unknown", format!("{:?}", value)),
+    }
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| group | (*byte as u32) << (16 - 8 * index));
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::domain::runner::test_host::TestHost;
+    use crate::domain::runner::ContractRunner;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+        seq: i64,
+    }
+
+    impl Client {
+        fn connect(port: u16) -> Self {
+            let writer = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            writer.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+
+            Self {
+                reader: BufReader::new(writer.try_clone().unwrap()),
+                writer,
+                seq: 0,
+            }
+        }
+
+        /// Sends a request and returns the body of its response.
+        fn request(&mut self, command: &str, arguments: JsonValue) -> JsonValue {
+            self.seq += 1;
+            let request = JsonValue::object([
+                ("seq", self.seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments),
+            ]);
+            write_message(&mut self.writer, &request).unwrap();
+
+            let seq = self.seq;
+            let response = self.expect(|message| message.get("request_seq").and_then(JsonValue::as_i64) == Some(seq));
+            assert_eq!(response.get("success"), Some(&JsonValue::Bool(true)), "{}", response);
+
+            response.get("body").cloned().unwrap_or(JsonValue::Null)
+        }
+
+        fn event(&mut self, event: &str) -> JsonValue {
+            let message = self.expect(|message| message.get("event").and_then(JsonValue::as_str) == Some(event));
+
+            message.get("body").cloned().unwrap_or(JsonValue::Null)
+        }
+
+        fn expect(&mut self, matches: impl Fn(&JsonValue) -> bool) -> JsonValue {
+            loop {
+                let message = read_message(&mut self.reader).unwrap();
+                if matches(&message) {
+                    return message;
+                }
+            }
+        }
+    }
+
+    fn variable<'a>(variables: &'a JsonValue, name: &str) -> Option<&'a str> {
+        variables
+            .get("variables")?
+            .as_array()?
+            .iter()
+            .find(|variable| variable.get("name").and_then(JsonValue::as_str) == Some(name))?
+            .get("value")?
+            .as_str()
+    }
+
+    #[test]
+    fn an_ide_stops_inspects_and_steps_a_running_call() {
+        let mut runner = TestHost::default().debug_runner(
+            r#"(func $sum (export "sum") (param $n i32) (result i32)
+                (local $total i32)
+                (block
+                    (loop
+                        (br_if 1 (i32.eqz (local.get $n)))
+                        (local.set $total (i32.add (local.get $total) (local.get $n)))
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br 0)))
+                (local.get $total))"#,
+        );
+        let mut adapter = DebugAdapter::listen(runner.debugger().unwrap(), 0).unwrap();
+        let mut client = Client::connect(adapter.port());
+
+        let capabilities = client.request("initialize", JsonValue::object([("adapterID", "opvm".into())]));
+        assert_eq!(capabilities.get("supportsFunctionBreakpoints"), Some(&JsonValue::Bool(true)));
+        client.event("initialized");
+
+        let function = JsonValue::object([("name", "sum".into())]);
+        let breakpoints = client.request(
+            "setFunctionBreakpoints",
+            JsonValue::object([("breakpoints", JsonValue::Array(vec![function]))]),
+        );
+        assert_eq!(breakpoints.to_string(), r#"{"breakpoints":[{"verified":true}]}"#);
+        client.request("configurationDone", JsonValue::object([]));
+
+        let call = thread::spawn(move || {
+            let result = runner.call("sum", &[Value::I32(3)]).map(|result| result.to_vec());
+            (runner, result)
+        });
+
+        let stopped = client.event("stopped");
+        assert_eq!(stopped.get("reason").and_then(JsonValue::as_str), Some("breakpoint"));
+
+        let stack = client.request("stackTrace", JsonValue::object([("threadId", THREAD_ID.into())]));
+        let frame = &stack.get("stackFrames").and_then(JsonValue::as_array).unwrap()[0];
+        assert_eq!(frame.get("name").and_then(JsonValue::as_str), Some("sum"));
+
+        let locals = client.request("variables", JsonValue::object([("variablesReference", LOCALS_REFERENCE.into())]));
+        assert_eq!(variable(&locals, "local0"), Some("3"));
+        assert_eq!(variable(&locals, "local1"), Some("0"));
+
+        let memory = client.request(
+            "readMemory",
+            JsonValue::object([("memoryReference", "0x0".into()), ("count", 4.into())]),
+        );
+        assert_eq!(memory.get("data").and_then(JsonValue::as_str), Some("YmMxcQ=="));
+
+        client.request("next", JsonValue::object([("threadId", THREAD_ID.into())]));
+        let stopped = client.event("stopped");
+        assert_eq!(stopped.get("reason").and_then(JsonValue::as_str), Some("step"));
+
+        client.request("continue", JsonValue::object([("threadId", THREAD_ID.into())]));
+        client.request("disconnect", JsonValue::object([]));
+
+        let started = Instant::now();
+        let (runner, result) = call.join().unwrap();
+        assert_eq!(result.unwrap(), vec![Value::I32(6)]);
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // The adapter reads through the runner, so it goes first.
+        adapter.close();
+        drop(runner);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+    }
+}
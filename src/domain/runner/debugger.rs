@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use wasmer::wasmparser::ValType;
+use wasmer::{AsStoreMut, Function, FunctionEnv, FunctionEnvMut, Type, Value};
+use wasmer_types::RawValue;
+use wasmer_vm::VMMemoryDefinition;
+
+use crate::domain::runner::InstanceWrapper;
+use crate::domain::vm::{
+    DebugInstrumentation, DebugSymbols, ProbeEvent, BREAK_AT_ANY_PROBE, DEBUG_EVENT_EXPORT, DEBUG_LOCATION_EXPORT,
+    DEBUG_PROBE_TABLE_EXPORT, DEBUG_STEPPING_EXPORT, GLOBAL_EXPORT_PREFIX, MAX_DEBUG_LOCALS,
+};
+
+/// How often a paused probe looks at the remaining points. The watchdog drains them from
+/// its own thread without waking the probe up.
+const PAUSED_PROBE_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stops when the function is entered.
+    Function(String),
+    /// Stops at the first probe at or after this module offset, e.g. from a source map.
+    Offset(usize),
+}
+
+/// Where a debug build stopped and what it held there.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugStop {
+    pub function_index: u32,
+    pub function: String,
+    /// Index of the instruction in the function body.
+    pub position: u32,
+    /// Module offset of that instruction, `None` when leaving at the end of the body.
+    pub offset: Option<usize>,
+    pub event: ProbeEvent,
+    /// Numeric locals by index, parameters first.
+    pub locals: Vec<(u32, Value)>,
+    pub globals: Vec<(String, Value)>,
+}
+
+struct DebugGlobal {
+    name: String,
+    ty: Type,
+    value: *mut RawValue,
+}
+
+/// Drives the probes of a debug build from another thread while the contract runs, see
+/// [`DebugInstrumentation`]. The contract blocks in the host probe until the debugger
+/// resumes it, so the runner stays locked by its call the whole time and everything here
+/// goes through the addresses of the exported globals and memory instead.
+pub struct Debugger {
+    symbols: DebugSymbols,
+    remaining_points: *mut u64,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    stepping: *mut RawValue,
+    location: *mut RawValue,
+    event: *mut RawValue,
+    breaks: Vec<*mut RawValue>,
+    locals: Vec<*mut RawValue>,
+    globals: Vec<DebugGlobal>,
+    memory: *const VMMemoryDefinition,
+    breakpoints: Mutex<BTreeMap<u32, BTreeSet<u32>>>,
+}
+
+// The pointers are only dereferenced while the instance they belong to is alive, and the
+// contract only reads what the debugger writes between probes.
+unsafe impl Send for Debugger {}
+unsafe impl Sync for Debugger {}
+
+impl Debugger {
+    /// # Safety
+    ///
+    /// `instance` must be compiled with the [`DebugInstrumentation`] of `symbols`, and the
+    /// debugger must not be used once the instance or its store is dropped.
+    pub unsafe fn attach(
+        instance: &InstanceWrapper,
+        store: &mut impl AsStoreMut,
+        symbols: DebugSymbols,
+    ) -> anyhow::Result<Arc<Self>> {
+        let remaining_points = instance
+            .remaining_gas_ptr(store)
+            .ok_or_else(|| anyhow::anyhow!("instance is not metered"))?;
+
+        let mut global = |name: &str| {
+            instance
+                .global_ptr(store, name)
+                .ok_or_else(|| anyhow::anyhow!("the module is not a debug build, {} is missing", name))
+        };
+
+        let stepping = global(DEBUG_STEPPING_EXPORT)?;
+        let location = global(DEBUG_LOCATION_EXPORT)?;
+        let event = global(DEBUG_EVENT_EXPORT)?;
+        let breaks = (0..symbols.functions.len() as u32)
+            .map(|function| global(&DebugInstrumentation::break_export(function)))
+            .collect::<anyhow::Result<_>>()?;
+        let locals = (0..symbols.max_locals().min(MAX_DEBUG_LOCALS) as u32)
+            .map(|local| global(&DebugInstrumentation::local_export(local)))
+            .collect::<anyhow::Result<_>>()?;
+
+        let globals = instance
+            .global_exports(store, GLOBAL_EXPORT_PREFIX)
+            .into_iter()
+            .filter_map(|(name, ty)| {
+                let value = instance.global_ptr(store, &name)?;
+                Some(DebugGlobal { name, ty, value })
+            })
+            .collect();
+
+        let memory = instance
+            .memory_definition_ptr(store)
+            .ok_or_else(|| anyhow::anyhow!("the module has no memory"))?;
+
+        let debugger = Arc::new(Self {
+            symbols,
+            remaining_points,
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            stepping,
+            location,
+            event,
+            breaks,
+            locals,
+            globals,
+            memory,
+            breakpoints: Mutex::new(BTreeMap::new()),
+        });
+
+        let env = FunctionEnv::new(store, debugger.clone());
+        let probe = Function::new_typed_with_env(store, &env, |env: FunctionEnvMut<Arc<Debugger>>| env.data().wait());
+        instance.set_table_function(store, DEBUG_PROBE_TABLE_EXPORT, probe)?;
+
+        Ok(debugger)
+    }
+
+    pub fn set_breakpoint(&self, breakpoint: &Breakpoint) -> anyhow::Result<()> {
+        let (function, position) = match breakpoint {
+            Breakpoint::Function(name) => {
+                let function = self
+                    .symbols
+                    .function_by_name(name)
+                    .ok_or_else(|| anyhow::anyhow!("no function named {}", name))?;
+
+                (function, 0)
+            }
+            Breakpoint::Offset(offset) => {
+                let (function, probe) = self
+                    .symbols
+                    .probe_at(*offset)
+                    .ok_or_else(|| anyhow::anyhow!("no instruction at offset {}", offset))?;
+
+                (function, probe.position)
+            }
+        };
+
+        let mut breakpoints = self.breakpoints.lock().unwrap();
+        let positions = breakpoints.entry(function).or_default();
+        positions.insert(position);
+
+        // The probes can only compare against one position, the others are filtered here.
+        let value = match positions.len() {
+            1 => position as i32 + 1,
+            _ => BREAK_AT_ANY_PROBE,
+        };
+        Self::store_i32(self.breaks[function as usize], value);
+
+        Ok(())
+    }
+
+    pub fn clear_breakpoints(&self) {
+        let mut breakpoints = self.breakpoints.lock().unwrap();
+        for function in breakpoints.keys() {
+            Self::store_i32(self.breaks[*function as usize], 0);
+        }
+
+        breakpoints.clear();
+    }
+
+    /// Where the contract is stopped, if it is. A probe that only hit because its function
+    /// has several breakpoints is resumed right away.
+    pub fn poll(&self) -> Option<DebugStop> {
+        if !*self.paused.lock().unwrap() {
+            return None;
+        }
+
+        let (function, position) = DebugInstrumentation::decode_location(Self::load_u64(self.location));
+        let stepping = Self::load_i32(self.stepping) != 0;
+        let at_breakpoint = self
+            .breakpoints
+            .lock()
+            .unwrap()
+            .get(&function)
+            .is_some_and(|positions| positions.contains(&position));
+
+        if !stepping && !at_breakpoint {
+            self.release();
+
+            return None;
+        }
+
+        Some(self.stop(function, position))
+    }
+
+    /// Stops at the next probe.
+    pub fn pause(&self) {
+        Self::store_i32(self.stepping, 1);
+    }
+
+    /// Runs to the next probe and stops there.
+    pub fn step(&self) {
+        Self::store_i32(self.stepping, 1);
+        self.release();
+    }
+
+    /// Runs to the next breakpoint.
+    pub fn resume(&self) {
+        Self::store_i32(self.stepping, 0);
+        self.release();
+    }
+
+    /// Drops every breakpoint and lets the contract run to completion.
+    pub fn detach(&self) {
+        self.clear_breakpoints();
+        self.resume();
+    }
+
+    /// Reads the linear memory of a stopped contract.
+    pub fn read_memory(&self, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+        if !*self.paused.lock().unwrap() {
+            return Err(anyhow::anyhow!("the contract is not stopped"));
+        }
+
+        // SAFETY: guaranteed by the caller of `attach`, and a stopped contract does not grow
+        // its memory.
+        let memory = unsafe { &*self.memory };
+
+        let end = offset.checked_add(length).filter(|end| *end <= memory.current_length as u64);
+        if end.is_none() {
+            return Err(anyhow::anyhow!(
+                "{} bytes at {} are out of the {} bytes of memory",
+                length,
+                offset,
+                memory.current_length
+            ));
+        }
+
+        // SAFETY: checked against the current length above.
+        let bytes = unsafe { std::slice::from_raw_parts(memory.base.add(offset as usize), length as usize) };
+
+        Ok(bytes.to_vec())
+    }
+
+    /// The host probe, runs on the thread of the call until the debugger releases it or the
+    /// watchdog drains the remaining points.
+    fn wait(&self) {
+        let mut paused = self.paused.lock().unwrap();
+        *paused = true;
+
+        while *paused && Self::load_u64(self.remaining_points.cast()) != 0 {
+            paused = self.resumed.wait_timeout(paused, PAUSED_PROBE_CHECK_INTERVAL).unwrap().0;
+        }
+        *paused = false;
+    }
+
+    fn release(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+
+    fn stop(&self, function_index: u32, position: u32) -> DebugStop {
+        let function = &self.symbols.functions[function_index as usize];
+
+        let locals = function
+            .locals
+            .iter()
+            .zip(&self.locals)
+            .enumerate()
+            .filter_map(|(index, (ty, value))| {
+                let raw = Self::load_u64(*value);
+                let value = match ty {
+                    ValType::I32 => Value::I32(raw as u32 as i32),
+                    ValType::I64 => Value::I64(raw as i64),
+                    ValType::F32 => Value::F32(f32::from_bits(raw as u32)),
+                    ValType::F64 => Value::F64(f64::from_bits(raw)),
+                    _ => return None,
+                };
+
+                Some((index as u32, value))
+            })
+            .collect();
+
+        let globals = self
+            .globals
+            .iter()
+            .filter_map(|global| {
+                let raw = Self::load_u64(global.value);
+                let value = match global.ty {
+                    Type::I32 => Value::I32(raw as u32 as i32),
+                    Type::I64 => Value::I64(raw as i64),
+                    Type::F32 => Value::F32(f32::from_bits(raw as u32)),
+                    Type::F64 => Value::F64(f64::from_bits(raw)),
+                    _ => return None,
+                };
+
+                Some((global.name.clone(), value))
+            })
+            .collect();
+
+        DebugStop {
+            function_index,
+            function: function.name.clone(),
+            position,
+            offset: function.instruction_offsets.get(position as usize).copied(),
+            event: ProbeEvent::from_code(Self::load_i32(self.event) as u32).unwrap_or(ProbeEvent::Block),
+            locals,
+            globals,
+        }
+    }
+
+    // Values are little endian at the start of the slot, as wasm code accesses them.
+    fn load_i32(value: *mut RawValue) -> i32 {
+        // SAFETY: guaranteed by the caller of `attach`, the slot is 16 byte aligned.
+        unsafe { AtomicI32::from_ptr(value.cast()) }.load(Ordering::Acquire)
+    }
+
+    fn store_i32(value: *mut RawValue, new: i32) {
+        // SAFETY: see `load_i32`.
+        unsafe { AtomicI32::from_ptr(value.cast()) }.store(new, Ordering::Release)
+    }
+
+    fn load_u64(value: *mut RawValue) -> u64 {
+        // SAFETY: see `load_i32`.
+        unsafe { AtomicU64::from_ptr(value.cast()) }.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::domain::runner::test_host::TestHost;
+    use crate::domain::runner::ContractRunner;
+
+    const SUM: &str = r#"(func $sum (export "sum") (param $n i32) (result i32)
+        (local $total i32)
+        (block
+            (loop
+                (br_if 1 (i32.eqz (local.get $n)))
+                (local.set $total (i32.add (local.get $total) (local.get $n)))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br 0)))
+        (local.get $total))"#;
+
+    fn wait_for_stop(debugger: &Debugger) -> DebugStop {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(stop) = debugger.poll() {
+                return stop;
+            }
+
+            assert!(Instant::now() < deadline, "the contract never stopped");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn stops_at_breakpoints_shows_locals_and_steps() {
+        let mut runner = TestHost::default().debug_runner(SUM);
+        let debugger = runner.debugger().unwrap();
+        debugger.set_breakpoint(&Breakpoint::Function("sum".to_string())).unwrap();
+
+        let call = thread::spawn(move || runner.call("sum", &[Value::I32(3)]).map(|result| result.to_vec()));
+
+        let stop = wait_for_stop(&debugger);
+        assert_eq!(stop.function, "sum");
+        assert_eq!(stop.event, ProbeEvent::Enter);
+        assert_eq!(stop.position, 0);
+        assert_eq!(stop.locals, vec![(0, Value::I32(3)), (1, Value::I32(0))]);
+        assert!(debugger.read_memory(0, 10).unwrap().starts_with(b"bc1qcallee"));
+
+        debugger.step();
+        let step = wait_for_stop(&debugger);
+        assert_eq!(step.function, "sum");
+        assert_eq!(step.event, ProbeEvent::Block);
+        assert!(step.position > stop.position);
+
+        debugger.detach();
+        assert_eq!(call.join().unwrap().unwrap(), vec![Value::I32(6)]);
+    }
+
+    #[test]
+    fn deadlines_end_a_call_nobody_resumes() {
+        let mut runner = TestHost::default().debug_runner(SUM);
+        let debugger = runner.debugger().unwrap();
+        debugger.set_breakpoint(&Breakpoint::Function("sum".to_string())).unwrap();
+        runner.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+
+        let call = thread::spawn(move || runner.call("sum", &[Value::I32(3)]).is_err());
+
+        wait_for_stop(&debugger);
+        assert!(call.join().unwrap());
+    }
+}
@@ -0,0 +1,295 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use wasmer::sys::{BaseTunables, EngineBuilder};
+use wasmer::{CompilerConfig, Engine, Module, Store};
+use wasmer_compiler_singlepass::Singlepass;
+use wasmer_middlewares::Metering;
+use wasmer_types::Target;
+
+use crate::domain::runner::constants::{MAX_CACHED_ENGINES, MAX_GAS_CONSTRUCTOR, MAX_GLOBALS, MAX_PAGES, STACK_SIZE};
+use crate::domain::runner::ExecutionLimits;
+use crate::domain::vm::{get_gas_cost, DebugInstrumentation, DebugSymbols, DeferredStart, GasProfiler, GlobalExports, LimitingTunables, MemoryGrowthTracker, ModuleLimits, ModuleScoped, GAS_SCHEDULE_VERSION};
+
+/// Bump whenever the middleware chain changes what compiled artifacts contain.
+pub const ARTIFACT_VERSION: u32 = 4;
+
+lazy_static! {
+    static ref ENGINE_REGISTRY: EngineRegistry = EngineRegistry::default();
+}
+
+/// Everything that changes the machine code or the runtime limits of a compiled module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EngineKey {
+    pub gas_schedule: u32,
+    pub gas_profiling: bool,
+    pub max_pages: u32,
+    pub stack_size: usize,
+    pub max_table_elements: u32,
+}
+
+impl Default for EngineKey {
+    fn default() -> Self {
+        Self {
+            gas_schedule: GAS_SCHEDULE_VERSION,
+            gas_profiling: false,
+            max_pages: MAX_PAGES,
+            stack_size: STACK_SIZE,
+            max_table_elements: ModuleLimits::default().max_table_size,
+        }
+    }
+}
+
+impl EngineKey {
+    pub fn with_gas_profiling(gas_profiling: bool) -> Self {
+        Self {
+            gas_profiling,
+            ..Default::default()
+        }
+    }
+
+    /// The runtime limits only matter to the store, modules compiled under the default
+    /// limits can be instantiated under any others.
+    pub fn with_execution_limits(self, limits: &ExecutionLimits) -> Self {
+        Self {
+            max_pages: limits.max_pages,
+            stack_size: limits.stack_size,
+            ..self
+        }
+    }
+
+    /// Like [`EngineKey::with_execution_limits`], tables may not grow past the size the
+    /// module limits let them declare.
+    pub fn with_module_limits(self, limits: &ModuleLimits) -> Self {
+        Self {
+            max_table_elements: limits.max_table_size,
+            ..self
+        }
+    }
+}
+
+/// Engines built for the most recently used keys, at most [`MAX_CACHED_ENGINES`].
+///
+/// Limits come from callers, so the keys are unbounded. Dropping an engine here only
+/// means the next user of its key builds another: modules and stores hold clones of
+/// theirs, and every runtime engine is a clone of the same headless one.
+struct EngineCache<K: PartialEq> {
+    engines: Vec<(K, Engine)>,
+}
+
+impl<K: PartialEq> EngineCache<K> {
+    fn new() -> Self {
+        Self { engines: Vec::new() }
+    }
+
+    fn get_or_insert_with(&mut self, key: K, create: impl FnOnce() -> Engine) -> Engine {
+        let entry = match self.engines.iter().position(|(cached, _)| *cached == key) {
+            Some(position) => self.engines.remove(position),
+            None => {
+                if self.engines.len() >= MAX_CACHED_ENGINES {
+                    self.engines.remove(0);
+                }
+
+                (key, create())
+            }
+        };
+
+        let engine = entry.1.clone();
+        self.engines.push(entry);
+
+        engine
+    }
+}
+
+/// Process-wide engines, built once per [`EngineKey`] while it stays in use.
+///
+/// Compiling engines are only used to produce `Module`s, which are moved into one
+/// headless engine right away. Every `Store` is created from a clone of that engine with
+/// the tunables of its limits: clones share the signature registry, so `call_indirect`
+/// between host functions and any module agrees on signatures, whatever compiled it.
+pub struct EngineRegistry {
+    compilers: Mutex<EngineCache<EngineKey>>,
+    headless: wasmer_compiler::Engine,
+    runtimes: Mutex<EngineCache<(u32, usize, u32)>>,
+}
+
+impl Default for EngineRegistry {
+    fn default() -> Self {
+        Self {
+            compilers: Mutex::new(EngineCache::new()),
+            headless: EngineBuilder::headless().set_features(None).engine(),
+            runtimes: Mutex::new(EngineCache::new()),
+        }
+    }
+}
+
+impl EngineRegistry {
+    pub fn global() -> &'static EngineRegistry {
+        &ENGINE_REGISTRY
+    }
+
+    pub fn compile(&self, key: &EngineKey, bytecode: &[u8]) -> anyhow::Result<Module> {
+        let engine = self.compiler(key);
+        let module = Module::from_binary(&engine, bytecode)?;
+
+        self.move_to_runtime(key, module)
+    }
+
+    /// Compiles a debug build of `bytecode`, see [`DebugInstrumentation`]. The probes depend
+    /// on the module itself, so the compiler is built for this module only.
+    pub fn compile_debug(&self, key: &EngineKey, bytecode: &[u8], symbols: &DebugSymbols) -> anyhow::Result<Module> {
+        let engine = Self::create_compiler(key, Some(DebugInstrumentation::new(symbols)));
+        let module = Module::from_binary(&engine, bytecode)?;
+
+        self.move_to_runtime(key, module)
+    }
+
+    /// Moves a module out of its compiling engine, whose signature registry no store uses.
+    fn move_to_runtime(&self, key: &EngineKey, module: Module) -> anyhow::Result<Module> {
+        let serialized = module.serialize()?;
+
+        // SAFETY: the artifact was serialized by this process from a module it compiled.
+        unsafe { self.deserialize(key, serialized) }
+    }
+
+    pub unsafe fn deserialize(&self, key: &EngineKey, serialized: Bytes) -> anyhow::Result<Module> {
+        let engine = self.runtime(key);
+        let module = Module::deserialize(&engine, serialized)?;
+
+        Ok(module)
+    }
+
+    pub fn create_store(&self, key: &EngineKey) -> Store {
+        Store::new(self.runtime(key))
+    }
+
+    fn compiler(&self, key: &EngineKey) -> Engine {
+        self.compilers
+            .lock()
+            .unwrap()
+            .get_or_insert_with(*key, || Self::create_compiler(key, None))
+    }
+
+    /// The headless engine every `Store` of the given limits is created from.
+    pub fn runtime(&self, key: &EngineKey) -> Engine {
+        self.runtimes
+            .lock()
+            .unwrap()
+            .get_or_insert_with((key.max_pages, key.stack_size, key.max_table_elements), || Self::create_tunable(self.headless.clone(), key))
+    }
+
+    fn create_compiler(key: &EngineKey, debug_instrumentation: Option<DebugInstrumentation>) -> Engine {
+        let mut compiler = Singlepass::default();
+        compiler.canonicalize_nans(true);
+        compiler.push_middleware(Arc::new(DeferredStart::new()));
+        compiler.push_middleware(Arc::new(GlobalExports::new()));
+        compiler.push_middleware(Arc::new(ModuleScoped::new(|| {
+            Metering::new(MAX_GAS_CONSTRUCTOR, get_gas_cost)
+        })));
+        if let Some(debug_instrumentation) = debug_instrumentation {
+            compiler.push_middleware(Arc::new(debug_instrumentation));
+        }
+        compiler.push_middleware(Arc::new(ModuleScoped::new(MemoryGrowthTracker::new)));
+        if key.gas_profiling {
+            compiler.push_middleware(Arc::new(ModuleScoped::new(GasProfiler::new)));
+        }
+        compiler.enable_verifier();
+
+        let engine = EngineBuilder::new(compiler).set_features(None).engine();
+
+        Self::create_tunable(engine, key)
+    }
+
+    fn create_tunable(mut engine: wasmer_compiler::Engine, key: &EngineKey) -> Engine {
+        let base = BaseTunables::for_target(&Target::default());
+        let tunables = LimitingTunables::new(base, key.max_pages, key.stack_size, key.max_table_elements, MAX_GLOBALS);
+
+        engine.set_tunables(tunables);
+
+        engine.into()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{imports, Function, Instance, Value};
+    use wasmer_middlewares::metering::set_remaining_points;
+
+    use super::*;
+
+    #[test]
+    fn calls_imported_functions_through_a_table() {
+        // The unused type comes first so the signature indexes of the compiling engine and
+        // of the store would differ if they did not share a registry.
+        let bytecode = wasmer::wat2wasm(
+            br#"(module
+                (type (func (param i64 i64 i64)))
+                (type $answer (func (result i32)))
+                (import "env" "answer" (func $answer (type $answer)))
+                (table 1 funcref)
+                (elem (i32.const 0) $answer)
+                (func (export "run") (result i32)
+                    (call_indirect (type $answer) (i32.const 0))))"#,
+        )
+        .unwrap();
+
+        let registry = EngineRegistry::global();
+        let key = EngineKey::default();
+        let module = registry.compile(&key, &bytecode).unwrap();
+
+        // Stores of other limits come from other engines, which must agree as well.
+        let limits = [ExecutionLimits::default(), ExecutionLimits { max_pages: 16, ..Default::default() }];
+        for limits in limits {
+            let mut store = registry.create_store(&key.with_execution_limits(&limits));
+            let answer = Function::new_typed(&mut store, || 42i32);
+            let imports = imports! { "env" => { "answer" => answer } };
+            let instance = Instance::new(&mut store, &module, &imports).unwrap();
+            set_remaining_points(&mut store, &instance, 1_000_000);
+
+            let result = instance.exports.get_function("run").unwrap().call(&mut store, &[]).unwrap();
+
+            assert_eq!(result[0], Value::I32(42));
+        }
+    }
+    #[test]
+    fn limits_tables_to_the_size_the_module_limits_allow() {
+        let bytecode = wasmer::wat2wasm(br#"(module (table (export "table") 1 funcref))"#).unwrap();
+
+        let registry = EngineRegistry::default();
+        let key = EngineKey::default();
+        let module = registry.compile(&key, &bytecode).unwrap();
+
+        let limits = ModuleLimits { max_table_size: 8, ..Default::default() };
+        let mut store = registry.create_store(&key.with_module_limits(&limits));
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        let table = instance.exports.get_table("table").unwrap();
+
+        assert_eq!(table.ty(&store).maximum, Some(8));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_engines_for_arbitrary_limits() {
+        let bytecode = wasmer::wat2wasm(br#"(module (func (export "run") (result i32) (i32.const 7)))"#).unwrap();
+
+        let registry = EngineRegistry::default();
+        let key = EngineKey::default();
+        let module = registry.compile(&key, &bytecode).unwrap();
+
+        for max_pages in 1..=MAX_CACHED_ENGINES as u32 * 2 {
+            let limits = ExecutionLimits { max_pages, ..Default::default() };
+            registry.create_store(&key.with_execution_limits(&limits));
+        }
+        assert_eq!(registry.runtimes.lock().unwrap().engines.len(), MAX_CACHED_ENGINES);
+
+        // The engine the module was moved into is gone from the cache, not from the module.
+        let mut store = registry.create_store(&key);
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        set_remaining_points(&mut store, &instance, 1_000_000);
+
+        let result = instance.exports.get_function("run").unwrap().call(&mut store, &[]).unwrap();
+
+        assert_eq!(result[0], Value::I32(7));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::domain::runner::constants::{MAX_PAGES, MAX_PAGES_LIMIT, MAX_STACK_SIZE_LIMIT, MIN_STACK_SIZE, PAGE_MEMORY_SIZE, STACK_SIZE};
+
+/// Memory and stack ceilings a single contract instance runs under.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecutionLimits {
+    /// Maximum linear memory, in 64 KiB pages.
+    pub max_pages: u32,
+    /// Size of the stack wasm code runs on, in bytes.
+    pub stack_size: usize,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            max_pages: MAX_PAGES,
+            stack_size: STACK_SIZE,
+        }
+    }
+}
+
+impl ExecutionLimits {
+    /// The highest limits a node accepts unless configured otherwise.
+    pub fn node_maxima() -> Self {
+        Self {
+            max_pages: MAX_PAGES_LIMIT,
+            stack_size: MAX_STACK_SIZE_LIMIT,
+        }
+    }
+
+    pub fn max_memory_size(&self) -> u64 {
+        self.max_pages as u64 * PAGE_MEMORY_SIZE
+    }
+
+    /// Rejects limits above `maxima`, or too small for any contract to run.
+    pub fn check(&self, maxima: &ExecutionLimits) -> anyhow::Result<()> {
+        if self.max_pages == 0 || self.max_pages > maxima.max_pages {
+            return Err(anyhow::anyhow!(
+                "memory limit of {} pages is outside of the allowed range 1..={}",
+                self.max_pages,
+                maxima.max_pages
+            ));
+        }
+
+        if self.stack_size < MIN_STACK_SIZE || self.stack_size > maxima.stack_size {
+            return Err(anyhow::anyhow!(
+                "stack size of {} bytes is outside of the allowed range {}..={}",
+                self.stack_size,
+                MIN_STACK_SIZE,
+                maxima.stack_size
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_fit_the_node_maxima() {
+        assert!(ExecutionLimits::default().check(&ExecutionLimits::node_maxima()).is_ok());
+        assert_eq!(ExecutionLimits::default().max_memory_size(), 32 * 1024 * 1024);
+    }
+
+    #[test]
+    fn rejects_limits_outside_of_the_maxima() {
+        let maxima = ExecutionLimits::default();
+
+        let too_much_memory = ExecutionLimits { max_pages: maxima.max_pages + 1, ..maxima };
+        let no_memory = ExecutionLimits { max_pages: 0, ..maxima };
+        let too_much_stack = ExecutionLimits { stack_size: maxima.stack_size + 1, ..maxima };
+        let tiny_stack = ExecutionLimits { stack_size: MIN_STACK_SIZE - 1, ..maxima };
+
+        assert!(too_much_memory.check(&maxima).is_err());
+        assert!(no_memory.check(&maxima).is_err());
+        assert!(too_much_stack.check(&maxima).is_err());
+        assert!(tiny_stack.check(&maxima).is_err());
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use wasmer::Value;
+
+use crate::domain::runner::STORAGE_POINTER_SIZE;
+
+const TRACE_MAGIC: &[u8; 4] = b"OPVT";
+const TRACE_VERSION: u8 = 3;
+
+/// A replayed call did something else than the recorded one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceDivergence {
+    /// Index of the import the executions disagree on, the number of imports for the
+    /// final state.
+    pub entry: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trace diverged at import {}: {}", self.entry, self.reason)
+    }
+}
+
+impl std::error::Error for TraceDivergence {}
+
+/// A buffer written into the instance with `write_buffer`, e.g. calldata.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceBuffer {
+    pub value: Vec<u8>,
+    pub id: i32,
+    pub align: u32,
+}
+
+/// An exported function called on the instance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceCall {
+    pub function: String,
+    pub params: Vec<Value>,
+    /// Gas the call started with.
+    pub max_gas: u64,
+}
+
+/// What the traced calls ran on and were given, so a replay runs the same calls on the
+/// same code.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceHeader {
+    pub code_hash: [u8; 32],
+    /// Address of the contract, when the host knows it. Only informative.
+    pub address: String,
+    /// Address prefix of the network the contract runs on.
+    pub network: String,
+    /// In the order they were written, interleaved with the calls as they came.
+    pub buffers: Vec<TraceBuffer>,
+    pub calls: Vec<TraceCall>,
+}
+
+/// One host import made by the call. `output` is what the import acted upon: the answer
+/// of the host, or the value computed by the VM for imports that never leave it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub import: String,
+    pub input: Vec<u8>,
+    pub output: Vec<u8>,
+    pub gas_before: u64,
+    pub gas_after: u64,
+}
+
+/// The inputs of the traced calls, every import they made in order and the storage they
+/// wrote, so two executions of the same calls can be compared entry by entry.
+///
+/// The binary format is little endian: the magic `OPVT` and a version byte, then the
+/// header: the 32 byte code hash, address and network prefixed by their u32 length, the
+/// number of buffers as u32 and every buffer as its id as i32, its alignment as u32 and
+/// its u32 prefixed value, the number of calls as u32 and every call as the function name
+/// prefixed by its u32 length, the number of parameters as u32, every parameter as a type
+/// byte (0 i32, 1 i64, 2 f32, 3 f64) and its bits as u64, and the gas it started with as
+/// u64. Then the number of entries as u32, every entry as the import name prefixed by its
+/// u32 length, input and output prefixed by their u32 length and the gas before and after
+/// as u64, then the number of written pointers as u32 and every 32 byte pointer followed
+/// by its u32 prefixed value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecutionTrace {
+    pub header: TraceHeader,
+    pub entries: Vec<TraceEntry>,
+    /// Last value written to every pointer.
+    pub state_diff: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl ExecutionTrace {
+    pub fn new(code_hash: [u8; 32], network: &str) -> Self {
+        Self {
+            header: TraceHeader {
+                code_hash,
+                network: network.to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn record_buffer(&mut self, buffer: TraceBuffer) {
+        self.header.buffers.push(buffer);
+    }
+
+    pub fn record_call(&mut self, call: TraceCall) {
+        self.header.calls.push(call);
+    }
+
+    pub fn begin(&mut self, import: &str, input: &[u8], gas_before: u64) {
+        self.entries.push(TraceEntry {
+            import: import.to_string(),
+            input: input.to_vec(),
+            gas_before,
+            ..Default::default()
+        });
+    }
+
+    pub fn set_output(&mut self, output: &[u8]) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.output = output.to_vec();
+        }
+    }
+
+    pub fn end(&mut self, gas_after: u64) {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.gas_after = gas_after;
+        }
+    }
+
+    /// Records a `store` request, the pointer followed by the value.
+    pub fn record_write(&mut self, request: &[u8]) {
+        record_write(&mut self.state_diff, request);
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(TRACE_MAGIC);
+        bytes.push(TRACE_VERSION);
+
+        let header = &self.header;
+        bytes.extend_from_slice(&header.code_hash);
+        write_bytes(&mut bytes, header.address.as_bytes());
+        write_bytes(&mut bytes, header.network.as_bytes());
+
+        bytes.extend_from_slice(&(header.buffers.len() as u32).to_le_bytes());
+        for buffer in &header.buffers {
+            bytes.extend_from_slice(&buffer.id.to_le_bytes());
+            bytes.extend_from_slice(&buffer.align.to_le_bytes());
+            write_bytes(&mut bytes, &buffer.value);
+        }
+
+        bytes.extend_from_slice(&(header.calls.len() as u32).to_le_bytes());
+        for call in &header.calls {
+            write_bytes(&mut bytes, call.function.as_bytes());
+            bytes.extend_from_slice(&(call.params.len() as u32).to_le_bytes());
+            for param in &call.params {
+                let (ty, bits) = match param {
+                    Value::I32(value) => (0, *value as u32 as u64),
+                    Value::I64(value) => (1, *value as u64),
+                    Value::F32(value) => (2, value.to_bits() as u64),
+                    Value::F64(value) => (3, value.to_bits()),
+                    // Calls only take numbers, see `record_call`.
+                    _ => (0, 0),
+                };
+                bytes.push(ty);
+                bytes.extend_from_slice(&bits.to_le_bytes());
+            }
+            bytes.extend_from_slice(&call.max_gas.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_le_bytes());
+        for entry in &self.entries {
+            write_bytes(&mut bytes, entry.import.as_bytes());
+            write_bytes(&mut bytes, &entry.input);
+            write_bytes(&mut bytes, &entry.output);
+            bytes.extend_from_slice(&entry.gas_before.to_le_bytes());
+            bytes.extend_from_slice(&entry.gas_after.to_le_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.state_diff.len() as u32).to_le_bytes());
+        for (pointer, value) in &self.state_diff {
+            bytes.extend_from_slice(pointer);
+            write_bytes(&mut bytes, value);
+        }
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(TRACE_MAGIC.len())? != TRACE_MAGIC {
+            return Err(anyhow::anyhow!("not an execution trace"));
+        }
+
+        let version = reader.take(1)?[0];
+        if version != TRACE_VERSION {
+            return Err(anyhow::anyhow!("unsupported execution trace version {}", version));
+        }
+
+        let mut trace = Self::default();
+
+        let header = &mut trace.header;
+        header.code_hash = reader.take(32)?.try_into()?;
+        header.address = String::from_utf8(reader.prefixed()?.to_vec())?;
+        header.network = String::from_utf8(reader.prefixed()?.to_vec())?;
+
+        for _ in 0..reader.u32()? {
+            let id = reader.u32()? as i32;
+            let align = reader.u32()?;
+            let value = reader.prefixed()?.to_vec();
+            header.buffers.push(TraceBuffer { value, id, align });
+        }
+
+        for _ in 0..reader.u32()? {
+            let function = String::from_utf8(reader.prefixed()?.to_vec())?;
+
+            let mut params = Vec::new();
+            for _ in 0..reader.u32()? {
+                let ty = reader.take(1)?[0];
+                let bits = reader.u64()?;
+                params.push(match ty {
+                    0 => Value::I32(bits as u32 as i32),
+                    1 => Value::I64(bits as i64),
+                    2 => Value::F32(f32::from_bits(bits as u32)),
+                    3 => Value::F64(f64::from_bits(bits)),
+                    ty => return Err(anyhow::anyhow!("unknown parameter type {}", ty)),
+                });
+            }
+
+            let max_gas = reader.u64()?;
+            header.calls.push(TraceCall { function, params, max_gas });
+        }
+
+        for _ in 0..reader.u32()? {
+            let import = String::from_utf8(reader.prefixed()?.to_vec())?;
+
+            trace.entries.push(TraceEntry {
+                import,
+                input: reader.prefixed()?.to_vec(),
+                output: reader.prefixed()?.to_vec(),
+                gas_before: reader.u64()?,
+                gas_after: reader.u64()?,
+            });
+        }
+
+        for _ in 0..reader.u32()? {
+            let pointer = reader.take(STORAGE_POINTER_SIZE)?.to_vec();
+            let value = reader.prefixed()?.to_vec();
+            trace.state_diff.insert(pointer, value);
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(anyhow::anyhow!("{} trailing bytes after the execution trace", reader.bytes.len()));
+        }
+
+        Ok(trace)
+    }
+}
+
+/// Feeds the imports of recorded calls back in order, checking the replayed calls get the
+/// same inputs and make the same imports with the same inputs and gas.
+#[derive(Debug, Default)]
+pub struct TraceReplay {
+    trace: ExecutionTrace,
+    next: usize,
+    next_buffer: usize,
+    next_call: usize,
+    state_diff: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl TraceReplay {
+    /// Fails unless the trace was recorded on the same code and network.
+    pub fn new(trace: ExecutionTrace, code_hash: [u8; 32], network: &str) -> anyhow::Result<Self> {
+        if trace.header.code_hash != code_hash {
+            return Err(anyhow::anyhow!(
+                "the trace was recorded on code {}, not {}",
+                hex::encode(trace.header.code_hash),
+                hex::encode(code_hash)
+            ));
+        }
+
+        if trace.header.network != network {
+            return Err(anyhow::anyhow!(
+                "the trace was recorded on network {}, not {}",
+                trace.header.network,
+                network
+            ));
+        }
+
+        Ok(Self {
+            trace,
+            ..Default::default()
+        })
+    }
+
+    pub fn check_buffer(&mut self, buffer: &TraceBuffer) -> Result<(), TraceDivergence> {
+        if self.trace.header.buffers.get(self.next_buffer) != Some(buffer) {
+            return Err(self.diverged(format!("buffer {} differs from the recorded one", self.next_buffer)));
+        }
+
+        self.next_buffer += 1;
+
+        Ok(())
+    }
+
+    pub fn check_call(&mut self, call: &TraceCall) -> Result<(), TraceDivergence> {
+        match self.trace.header.calls.get(self.next_call) {
+            Some(recorded) if recorded == call => {}
+            Some(recorded) => {
+                return Err(self.diverged(format!(
+                    "expected a call of {} with {:?} and {} gas, got {} with {:?} and {} gas",
+                    recorded.function, recorded.params, recorded.max_gas, call.function, call.params, call.max_gas
+                )))
+            }
+            None => return Err(self.diverged(format!("the call of {} was not recorded", call.function))),
+        }
+
+        self.next_call += 1;
+
+        Ok(())
+    }
+
+    pub fn begin(&mut self, import: &str, input: &[u8], gas_before: u64) -> Result<(), TraceDivergence> {
+        let entry = self
+            .trace
+            .entries
+            .get(self.next)
+            .ok_or_else(|| self.diverged(format!("{} was not recorded, the trace ended", import)))?;
+
+        if entry.import != import {
+            return Err(self.diverged(format!("expected {}, got {}", entry.import, import)));
+        }
+
+        if entry.input != input {
+            return Err(self.diverged(format!("input of {} differs", import)));
+        }
+
+        if entry.gas_before != gas_before {
+            return Err(self.diverged(format!("expected {} gas before {}, got {}", entry.gas_before, import, gas_before)));
+        }
+
+        Ok(())
+    }
+
+    /// Recorded output of the current import.
+    pub fn output(&self) -> Result<Vec<u8>, TraceDivergence> {
+        self.trace
+            .entries
+            .get(self.next)
+            .map(|entry| entry.output.clone())
+            .ok_or_else(|| self.diverged("no import is being replayed".to_string()))
+    }
+
+    pub fn end(&mut self, gas_after: u64) -> Result<(), TraceDivergence> {
+        let recorded = self.trace.entries.get(self.next).map(|entry| entry.gas_after);
+        if recorded != Some(gas_after) {
+            return Err(self.diverged(format!("expected {:?} gas after the import, got {}", recorded, gas_after)));
+        }
+
+        self.next += 1;
+
+        Ok(())
+    }
+
+    pub fn record_write(&mut self, request: &[u8]) {
+        record_write(&mut self.state_diff, request);
+    }
+
+    /// Checks every recorded call and import was replayed and the same storage was written.
+    pub fn finish(self) -> Result<(), TraceDivergence> {
+        if self.next_call != self.trace.header.calls.len() {
+            return Err(self.diverged(format!("{} recorded calls were not replayed", self.trace.header.calls.len() - self.next_call)));
+        }
+
+        if self.next != self.trace.entries.len() {
+            return Err(self.diverged(format!("{} recorded imports were not replayed", self.trace.entries.len() - self.next)));
+        }
+
+        if self.state_diff != self.trace.state_diff {
+            return Err(self.diverged("the final state differs".to_string()));
+        }
+
+        Ok(())
+    }
+
+    fn diverged(&self, reason: String) -> TraceDivergence {
+        TraceDivergence {
+            entry: self.next,
+            reason,
+        }
+    }
+}
+
+fn record_write(state_diff: &mut BTreeMap<Vec<u8>, Vec<u8>>, request: &[u8]) {
+    if request.len() >= STORAGE_POINTER_SIZE {
+        let (pointer, value) = request.split_at(STORAGE_POINTER_SIZE);
+        state_diff.insert(pointer.to_vec(), value.to_vec());
+    }
+}
+
+fn write_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(anyhow::anyhow!("execution trace is truncated"));
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn prefixed(&mut self) -> anyhow::Result<&'a [u8]> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE_HASH: [u8; 32] = [9; 32];
+
+    fn buffer() -> TraceBuffer {
+        TraceBuffer { value: vec![1, 2, 3], id: 13, align: 0 }
+    }
+
+    fn call() -> TraceCall {
+        TraceCall {
+            function: "execute".to_string(),
+            params: vec![Value::I32(-4), Value::F64(0.5)],
+            max_gas: 1_000,
+        }
+    }
+
+    fn replay() -> TraceReplay {
+        TraceReplay::new(recorded(), CODE_HASH, "bcrt").unwrap()
+    }
+
+    fn recorded() -> ExecutionTrace {
+        let mut trace = ExecutionTrace::new(CODE_HASH, "bcrt");
+        trace.record_buffer(buffer());
+        trace.record_call(call());
+        trace.begin("load", &[1; 32], 1_000);
+        trace.set_output(&[7; 32]);
+        trace.end(900);
+        trace.begin("store", &[[2; 32], [8; 32]].concat(), 900);
+        trace.record_write(&[[2; 32], [8; 32]].concat());
+        trace.end(600);
+
+        trace
+    }
+
+    #[test]
+    fn roundtrips_through_the_binary_format() {
+        let trace = recorded();
+        let bytes = trace.encode();
+
+        assert_eq!(ExecutionTrace::decode(&bytes).unwrap(), trace);
+        assert!(ExecutionTrace::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ExecutionTrace::decode(b"NOPE").is_err());
+    }
+
+    #[test]
+    fn keeps_names_longer_than_a_byte_can_count() {
+        let mut trace = recorded();
+        let function = "f".repeat(300);
+        trace.record_call(TraceCall { function: function.clone(), ..call() });
+        trace.begin(&"i".repeat(256), &[], 600);
+        trace.end(600);
+
+        let decoded = ExecutionTrace::decode(&trace.encode()).unwrap();
+
+        assert_eq!(decoded, trace);
+        assert_eq!(decoded.header.calls[1].function, function);
+    }
+
+    #[test]
+    fn replays_recorded_outputs() {
+        let mut replay = replay();
+
+        replay.check_buffer(&buffer()).unwrap();
+        replay.check_call(&call()).unwrap();
+        replay.begin("load", &[1; 32], 1_000).unwrap();
+        assert_eq!(replay.output().unwrap(), vec![7; 32]);
+        replay.end(900).unwrap();
+
+        replay.begin("store", &[[2; 32], [8; 32]].concat(), 900).unwrap();
+        replay.record_write(&[[2; 32], [8; 32]].concat());
+        replay.end(600).unwrap();
+
+        assert!(replay.finish().is_ok());
+    }
+
+    #[test]
+    fn reports_where_a_replay_diverges() {
+        let mut diverging = replay();
+        diverging.begin("load", &[1; 32], 1_000).unwrap();
+        diverging.end(900).unwrap();
+
+        let divergence = diverging.begin("load", &[2; 32], 900).unwrap_err();
+
+        assert_eq!(divergence.entry, 1);
+        assert!(replay().finish().is_err());
+        assert!(replay().check_call(&TraceCall { max_gas: 999, ..call() }).is_err());
+        assert!(replay().check_buffer(&TraceBuffer { value: vec![1], ..buffer() }).is_err());
+    }
+
+    #[test]
+    fn only_replays_on_the_recorded_code() {
+        assert!(TraceReplay::new(recorded(), [8; 32], "bcrt").is_err());
+        assert!(TraceReplay::new(recorded(), CODE_HASH, "bc").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GasProfileEntry {
+    pub name: String,
+    pub gas: u64,
+    pub calls: u64,
+}
+
+/// Gas attributed to the host imports of a contract, recorded by the import functions.
+#[derive(Default)]
+pub struct ImportGasProfile {
+    imports: BTreeMap<&'static str, (u64, u64)>,
+}
+
+impl ImportGasProfile {
+    pub fn record(&mut self, import: &'static str, gas: u64) {
+        let entry = self.imports.entry(import).or_default();
+        entry.0 = entry.0.saturating_add(gas);
+        entry.1 += 1;
+    }
+
+    pub fn entries(&self) -> Vec<GasProfileEntry> {
+        self.imports
+            .iter()
+            .map(|(name, (gas, calls))| GasProfileEntry {
+                name: name.to_string(),
+                gas: *gas,
+                calls: *calls,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GasProfile {
+    pub functions: Vec<GasProfileEntry>,
+    pub imports: Vec<GasProfileEntry>,
+}
+
+impl GasProfile {
+    pub fn total_gas(&self) -> u64 {
+        self.functions
+            .iter()
+            .chain(self.imports.iter())
+            .fold(0u64, |total, entry| total.saturating_add(entry.gas))
+    }
+
+    /// Renders the profile in the collapsed stack format understood by flamegraph tools,
+    /// one `frame;frame gas` line per function or import that consumed gas.
+    pub fn to_collapsed_stacks(&self, root: &str) -> String {
+        let mut output = String::new();
+
+        for entry in self.functions.iter().filter(|entry| entry.gas > 0) {
+            let _ = writeln!(output, "{};{} {}", root, entry.name, entry.gas);
+        }
+
+        for entry in self.imports.iter().filter(|entry| entry.gas > 0) {
+            let _ = writeln!(output, "{};host;{} {}", root, entry.name, entry.gas);
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_profile_accumulates_gas_and_calls() {
+        let mut profile = ImportGasProfile::default();
+        profile.record("load", 10);
+        profile.record("load", 15);
+        profile.record("sha256", 1);
+
+        let entries = profile.entries();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], GasProfileEntry { name: "load".to_string(), gas: 25, calls: 2 });
+        assert_eq!(entries[1], GasProfileEntry { name: "sha256".to_string(), gas: 1, calls: 1 });
+    }
+
+    #[test]
+    fn collapsed_stacks_skip_idle_frames() {
+        let profile = GasProfile {
+            functions: vec![
+                GasProfileEntry { name: "main".to_string(), gas: 100, calls: 1 },
+                GasProfileEntry { name: "unused".to_string(), gas: 0, calls: 0 },
+            ],
+            imports: vec![GasProfileEntry { name: "store".to_string(), gas: 50, calls: 1 }],
+        };
+
+        assert_eq!(profile.total_gas(), 150);
+        assert_eq!(profile.to_collapsed_stacks("contract"), "contract;main 100\ncontract;host;store 50\n");
+    }
+}
@@ -1,11 +1,10 @@
 use bech32::{segwit, Hrp};
 use ripemd::{Digest, Ripemd160};
 use sha2::Sha256;
-use tokio::runtime::Runtime;
-use wasmer::{FunctionEnvMut, RuntimeError, StoreMut};
+use wasmer::{FunctionEnvMut, RuntimeError};
 
 use crate::domain::assembly_script::AssemblyScript;
-use crate::domain::runner::{AbortData, CustomEnv, CALL_COST, DEPLOY_COST, ENCODE_ADDRESS_COST, LOAD_COST, SHA256_COST, STORE_COST};
+use crate::domain::runner::{AbortData, CustomEnv, LoadMany, CALL_COST, DEPLOY_COST, ENCODE_ADDRESS_COST, LOAD_COST, SHA256_COST, SIMULATION_CONFIRMATION, STATIC_CALL_CONFIRMATION, STORAGE_POINTER_SIZE, STORE_COST, WARM_CALL_COST, WARM_LOAD_COST, WARM_STORE_COST};
 use crate::interfaces::ExternalFunction;
 
 pub fn abort_import(
@@ -15,6 +14,18 @@ pub fn abort_import(
     line: u32,
     column: u32,
 ) -> Result<(), RuntimeError> {
+    if env.data().is_tracing() {
+        let (data, mut store) = env.data_and_store_mut();
+        let input = [message, file_name, line, column].map(u32::to_le_bytes).concat();
+        let gas = data
+            .instance
+            .clone()
+            .map_or(0, |instance| instance.get_remaining_gas(&mut store));
+
+        data.begin_import("abort", &input, gas)?;
+        data.end_import(gas)?;
+    }
+
     let data = env.data_mut();
     data.abort_data = Some(AbortData {
         message,
@@ -26,20 +37,211 @@ pub fn abort_import(
     Err(RuntimeError::new("Execution aborted"))
 }
 
+/// Records the import in the execution trace of the call, or checks it against the
+/// replayed trace. Imports without a trace run untouched.
+pub fn trace_import<R>(
+    import: &'static str,
+    mut context: FunctionEnvMut<CustomEnv>,
+    ptr: u32,
+    function: fn(FunctionEnvMut<CustomEnv>, u32) -> Result<R, RuntimeError>,
+) -> Result<R, RuntimeError> {
+    if !context.data().is_tracing() {
+        return function(context, ptr);
+    }
+
+    let (env, mut store) = context.data_and_store_mut();
+
+    let instance = env
+        .instance
+        .clone()
+        .ok_or(RuntimeError::new("Instance not found"))?;
+
+    let input = AssemblyScript::read_buffer(&store, &instance, ptr)
+        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
+
+    env.begin_import(import, &input, instance.get_remaining_gas(&mut store))?;
+
+    let result = function(context.as_mut(), ptr);
+
+    let (env, mut store) = context.data_and_store_mut();
+    let ended = env.end_import(instance.get_remaining_gas(&mut store));
+
+    result.and_then(|value| ended.map(|_| value))
+}
+
 pub fn storage_load_import(
     mut context: FunctionEnvMut<CustomEnv>,
     ptr: u32,
 ) -> Result<u32, RuntimeError> {
-    let (env, store) = context.data_and_store_mut();
-    external_import_with_param_and_return(env, store, &env.storage_load_external, ptr, LOAD_COST, &env.runtime)
+    let (env, mut store) = context.data_and_store_mut();
+
+    let instance = env
+        .instance
+        .clone()
+        .ok_or(RuntimeError::new("Instance not found"))?;
+
+    let data = AssemblyScript::read_buffer(&store, &instance, ptr)
+        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
+
+    let gas_cost = env.storage_key_price(&data, LOAD_COST, WARM_LOAD_COST)?;
+    instance.use_gas(&mut store, gas_cost);
+    env.record_import_gas("load", gas_cost);
+    env.record_read(&data);
+
+    let result = env.import_result(|| {
+        let overlaid = env
+            .storage_overlay
+            .as_ref()
+            .and_then(|overlay| overlay.lock().unwrap().load(&data));
+        if let Some(result) = overlaid {
+            return Ok(result);
+        }
+
+        env.record_host_call();
+        let cached = env.read_cache.lock().unwrap().load(&data);
+        match cached {
+            Some(result) => Ok(result),
+            None => {
+                let result = env
+                    .storage_load_external
+                    .execute(&data, &env.runtime, env.deadline, env.cancellation.as_ref())
+                    .map_err(|e| env.check_host_timeout(e))?;
+
+                env.read_cache.lock().unwrap().insert(&data, &result);
+                Ok(result)
+            }
+        }
+    })?;
+
+    let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
+        .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
+
+    Ok(value as u32)
+}
+
+/// Loads up to `MAX_LOAD_MANY_POINTERS` pointers at once, see [`LoadMany`] for the format.
+/// Every pointer costs as much as a single `load`, the saving is the roundtrips.
+pub fn storage_load_many_import(
+    mut context: FunctionEnvMut<CustomEnv>,
+    ptr: u32,
+) -> Result<u32, RuntimeError> {
+    let (env, mut store) = context.data_and_store_mut();
+
+    let instance = env
+        .instance
+        .clone()
+        .ok_or(RuntimeError::new("Instance not found"))?;
+
+    let data = AssemblyScript::read_buffer(&store, &instance, ptr)
+        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
+
+    let pointers = LoadMany::split_request(&data).map_err(RuntimeError::new)?;
+
+    let gas_cost = pointers.iter().try_fold(0u64, |gas_cost, pointer| {
+        env.storage_key_price(pointer, LOAD_COST, WARM_LOAD_COST)
+            .map(|price| gas_cost.saturating_add(price))
+    })?;
+    instance.use_gas(&mut store, gas_cost);
+    env.record_import_gas("loadMany", gas_cost);
+    pointers.iter().for_each(|pointer| env.record_read(pointer));
+
+    let result = env.import_result(|| {
+        // Pending writes of a simulated call first, then values the host already sent.
+        let mut values: Vec<Option<Vec<u8>>> = {
+            let overlay = env.storage_overlay.as_ref().map(|overlay| overlay.lock().unwrap());
+            let read_cache = env.read_cache.lock().unwrap();
+
+            pointers
+                .iter()
+                .map(|pointer| {
+                    overlay
+                        .as_ref()
+                        .and_then(|overlay| overlay.load(pointer))
+                        .or_else(|| read_cache.load(pointer))
+                })
+                .collect()
+        };
+
+        env.record_host_call();
+
+        let missing: Vec<&[u8]> = pointers
+            .iter()
+            .zip(&values)
+            .filter(|(_, value)| value.is_none())
+            .map(|(pointer, _)| *pointer)
+            .collect();
+
+        if !missing.is_empty() {
+            let response = env
+                .storage_load_many_external
+                .execute(&missing.concat(), &env.runtime, env.deadline, env.cancellation.as_ref())
+                .map_err(|e| env.check_host_timeout(e))?;
+            let loaded = LoadMany::unpack_response(&response, missing.len()).map_err(RuntimeError::new)?;
+
+            let mut read_cache = env.read_cache.lock().unwrap();
+            for (pointer, value) in missing.iter().zip(&loaded) {
+                read_cache.insert(pointer, value);
+            }
+
+            let mut loaded = loaded.into_iter();
+            for value in values.iter_mut().filter(|value| value.is_none()) {
+                *value = loaded.next();
+            }
+        }
+
+        let values: Vec<Vec<u8>> = values.into_iter().map(Option::unwrap_or_default).collect();
+        Ok(LoadMany::pack_response(&values))
+    })?;
+
+    let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
+        .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
+
+    Ok(value as u32)
 }
 
 pub fn storage_store_import(
     mut context: FunctionEnvMut<CustomEnv>,
     ptr: u32,
 ) -> Result<u32, RuntimeError> {
-    let (env, store) = context.data_and_store_mut();
-    external_import_with_param_and_return(env, store, &env.storage_store_external, ptr, STORE_COST, &env.runtime)
+    let (env, mut store) = context.data_and_store_mut();
+    env.check_static("store")?;
+
+    let instance = env
+        .instance
+        .clone()
+        .ok_or(RuntimeError::new("Instance not found"))?;
+
+    let data = AssemblyScript::read_buffer(&store, &instance, ptr)
+        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
+
+    let pointer = &data[..data.len().min(STORAGE_POINTER_SIZE)];
+    let gas_cost = env.storage_key_price(pointer, STORE_COST, WARM_STORE_COST)?;
+    instance.use_gas(&mut store, gas_cost);
+    env.record_import_gas("store", gas_cost);
+    env.record_write(&data);
+
+    let result = env.import_result(|| {
+        if let Some(overlay) = &env.storage_overlay {
+            overlay.lock().unwrap().store(&data).map_err(RuntimeError::new)?;
+
+            return Ok(Vec::new());
+        }
+
+        env.record_host_call();
+        let result = env
+            .storage_store_external
+            .execute(&data, &env.runtime, env.deadline, env.cancellation.as_ref())
+            .map_err(|e| env.check_host_timeout(e))?;
+
+        env.read_cache.lock().unwrap().record_store(&data);
+
+        Ok(result)
+    })?;
+
+    let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
+        .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
+
+    Ok(value as u32)
 }
 
 /*pub fn storage_store_import(
@@ -77,21 +279,58 @@ pub fn call_other_contract_import(
         .clone()
         .ok_or(RuntimeError::new("Instance not found"))?;
 
-    instance.use_gas(&mut store, CALL_COST);
-
     let data = AssemblyScript::read_buffer(&store, &instance, ptr)
         .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
 
-    let result = &env.call_other_contract_external.execute(&data, &env.runtime)?;
+    env.record_host_effect("call")?;
+    let call_cost = env.address_price(&data, CALL_COST, WARM_CALL_COST)?;
+    instance.use_gas(&mut store, call_cost);
 
-    let call_execution_cost_bytes = &result[0..8];
-    let response = &result[8..];
+    let result = &env.import_result(|| {
+        env.record_host_call();
+        let result = env
+            .call_other_contract_external
+            .execute_call(&data, env.request_mode(), &env.runtime, env.deadline, env.cancellation.as_ref())
+            .map_err(|e| env.check_host_timeout(e));
 
-    let value = AssemblyScript::write_buffer(&mut store, &instance, &response, 13, 0)
+        // The callee may have called back into this contract and changed its storage.
+        env.read_cache.lock().unwrap().clear();
+
+        result
+    })?;
+
+    // A host or a replayed trace may answer anything, the gas used by the callee comes first.
+    let Some((call_execution_cost_bytes, response)) = result.split_first_chunk::<8>() else {
+        return Err(RuntimeError::new(format!(
+            "Call response is {} bytes, shorter than the gas it used",
+            result.len()
+        )));
+    };
+
+    // The callee runs in another instance the host started, which must confirm it ran static.
+    let response = if env.is_static {
+        response
+            .strip_prefix(STATIC_CALL_CONFIRMATION)
+            .ok_or_else(|| RuntimeError::new("Call response does not confirm the nested call was static"))?
+    } else {
+        response
+    };
+
+    // Likewise for a call estimating its gas, the callee must not have committed anything.
+    let response = if env.simulation {
+        response
+            .strip_prefix(SIMULATION_CONFIRMATION)
+            .ok_or_else(|| RuntimeError::new("Call response does not confirm the nested call was simulated"))?
+    } else {
+        response
+    };
+
+    let value = AssemblyScript::write_buffer(&mut store, &instance, response, 13, 0)
         .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
 
-    let call_execution_cost = u64::from_le_bytes(call_execution_cost_bytes.try_into().unwrap());
+    let call_execution_cost = u64::from_le_bytes(*call_execution_cost_bytes);
     instance.use_gas(&mut store, call_execution_cost);
+    env.record_import_gas("call", call_cost.saturating_add(call_execution_cost));
 
     Ok(value as u32)
 }
@@ -100,15 +339,48 @@ pub fn deploy_from_address_import(
     mut context: FunctionEnvMut<CustomEnv>,
     ptr: u32,
 ) -> Result<u32, RuntimeError> {
-    let (env, store) = context.data_and_store_mut();
-    external_import_with_param_and_return(
-        env,
-        store,
-        &env.deploy_from_address_external,
-        ptr,
-        DEPLOY_COST,
-        &env.runtime,
-    )
+    let (env, mut store) = context.data_and_store_mut();
+    env.check_static("deployFromAddress")?;
+    env.record_host_effect("deployFromAddress")?;
+
+    let instance = env
+        .instance
+        .clone()
+        .ok_or(RuntimeError::new("Instance not found"))?;
+
+    let data = AssemblyScript::read_buffer(&store, &instance, ptr)
+        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
+
+    // Deploying is never discounted, declaring the source contract only matters to strict calls.
+    env.address_price(&data, DEPLOY_COST, DEPLOY_COST)?;
+    instance.use_gas(&mut store, DEPLOY_COST);
+    env.record_import_gas("deployFromAddress", DEPLOY_COST);
+
+    let result = env.import_result(|| {
+        env.record_host_call();
+        let result = env
+            .deploy_from_address_external
+            .execute_deploy(&data, env.request_mode(), &env.runtime, env.deadline, env.cancellation.as_ref())
+            .map_err(|e| env.check_host_timeout(e));
+
+        // The constructor of the new contract may have called back into this one.
+        env.read_cache.lock().unwrap().clear();
+
+        result
+    })?;
+
+    let address = if env.simulation {
+        result
+            .strip_prefix(SIMULATION_CONFIRMATION)
+            .ok_or_else(|| RuntimeError::new("Deployment response does not confirm the deployment was simulated"))?
+    } else {
+        &result
+    };
+
+    let value = AssemblyScript::write_buffer(&mut store, &instance, address, 13, 0)
+        .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
+
+    Ok(value as u32)
 }
 
 pub fn encode_address_import(
@@ -127,31 +399,36 @@ pub fn encode_address_import(
     let data = AssemblyScript::read_buffer(&store, &instance, ptr)
         .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
 
-    // skip 4 bytes for length
-    let data = data[4..].to_vec();
-    if data.len() != 32 {
-        return Err(RuntimeError::new(format!(
-            "Invalid data length. Expected 32, got {}",
-            data.len()
-        )));
-    }
+    let result = env.import_result(|| {
+        // skip 4 bytes for length
+        let data = data.get(4..).unwrap_or_default().to_vec();
+        if data.len() != 32 {
+            return Err(RuntimeError::new(format!(
+                "Invalid data length. Expected 32, got {}",
+                data.len()
+            )));
+        }
+
+        let mut ripemd = Ripemd160::new();
+        ripemd.update(&data);
+        let data = ripemd.finalize();
 
-    let mut ripemd = Ripemd160::new();
-    ripemd.update(&data);
-    let data = ripemd.finalize();
+        let hrp = Hrp::parse(&network.contract_address_prefix()).expect("Valid hrp");
+        let address = segwit::encode_v0(hrp, &data)
+            .map_err(|e| RuntimeError::new(format!("Failed to encode address: {:?}", e)))?;
 
-    let hrp = Hrp::parse(&network.contract_address_prefix()).expect("Valid hrp");
-    let address = segwit::encode_v0(hrp, &data)
-        .map_err(|e| RuntimeError::new(format!("Failed to encode address: {:?}", e)))?;
+        let mut result = address.as_bytes().to_vec();
 
-    let mut result = address.as_bytes().to_vec();
+        result.push(0);
 
-    result.push(0);
+        Ok(result)
+    })?;
 
     let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
         .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
 
     instance.use_gas(&mut store, ENCODE_ADDRESS_COST);
+    env.record_import_gas("encodeAddress", ENCODE_ADDRESS_COST);
 
     Ok(value as u32)
 }
@@ -170,12 +447,13 @@ pub fn sha256_import(
     let data = AssemblyScript::read_buffer(&store, &instance, ptr)
         .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
 
-    let result = sha256(&data)?;
+    let result = env.import_result(|| sha256(&data))?;
 
     let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
         .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
 
     instance.use_gas(&mut store, SHA256_COST);
+    env.record_import_gas("sha256", SHA256_COST);
 
     Ok(value as u32)
 }
@@ -199,38 +477,25 @@ pub fn console_log_import(
     let data = AssemblyScript::read_buffer(&store, &instance, ptr)
         .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
 
-    env.console_log_external.execute(&data)
-}
-
-fn external_import_with_param_and_return(
-    env: &CustomEnv,
-    mut store: StoreMut,
-    external_function: &impl ExternalFunction,
-    ptr: u32,
-    gas_cost: u64,
-    runtime: &Runtime,
-) -> Result<u32, RuntimeError> {
-    let instance = env
-        .instance
-        .clone()
-        .ok_or(RuntimeError::new("Instance not found"))?;
-
-    instance.use_gas(&mut store, gas_cost);
-
-    let data = AssemblyScript::read_buffer(&mut store, &instance, ptr)
-        .map_err(|_e| RuntimeError::new("Error lifting typed array"))?;
-
-    let result = external_function.execute(&data, runtime)?;
+    env.import_result(|| {
+        env.record_host_call();
+        env.console_log_external.execute(&data)?;
 
-    let value = AssemblyScript::write_buffer(&mut store, &instance, &result, 13, 0)
-        .map_err(|_e| RuntimeError::new("Error writing buffer"))?;
+        Ok(Vec::new())
+    })?;
 
-    Ok(value as u32)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use wasmer::Value;
+
     use super::*;
+    use crate::domain::runner::test_host::{key, TestHost};
+    use crate::domain::runner::ContractRunner;
 
     #[test]
     fn sha256_hashes_number_correctly() {
@@ -251,4 +516,67 @@ mod tests {
 
         assert_eq!(result, expected_hash);
     }
+
+    #[test]
+    fn reloads_storage_a_nested_call_may_have_changed() {
+        let host = TestHost::default();
+        host.set(1, &[1]);
+
+        // The callee calls back into the contract, which writes the key again.
+        let storage = host.storage.clone();
+        host.on_call(move |_, _| {
+            storage.lock().unwrap().insert(key(1), vec![2]);
+            Ok(TestHost::call_response(&[1], 0))
+        });
+
+        let mut runner = host.runner(
+            r#"(func (export "run") (result i32)
+                (drop (call $load_byte (i32.const 1)))
+                (drop (call $call_byte))
+                (call $load_byte (i32.const 1)))"#,
+        );
+
+        assert_eq!(runner.call("run", &[]).unwrap()[0], Value::I32(2));
+    }
+    #[test]
+    fn static_calls_stay_static_in_the_callee() {
+        let host = TestHost::default();
+        let callee = Arc::new(Mutex::new(host.runner(
+            r#"(func (export "write") (result i32)
+                (call $store_byte (i32.const 3) (i32.const 9))
+                (i32.const 0))"#,
+        )));
+
+        // Runs the callee the way it was asked to, as an honest host does.
+        host.on_call({
+            let callee = callee.clone();
+            move |_, mode| {
+                let mut callee = callee.lock().unwrap();
+                callee.set_static(mode.is_static);
+                callee.call("write", &[]).map_err(|e| RuntimeError::new(e.to_string()))?;
+
+                Ok(match mode.is_static {
+                    true => TestHost::static_call_response(&[1], 0),
+                    false => TestHost::call_response(&[1], 0),
+                })
+            }
+        });
+
+        let mut caller = host.runner(r#"(func (export "run") (result i32) (call $call_byte))"#);
+
+        caller.set_static(true);
+        let error = caller.call("run", &[]).unwrap_err();
+        assert!(error.to_string().contains("static call violation"), "{}", error);
+        assert_eq!(host.get(3), None);
+
+        caller.set_static(false);
+        assert_eq!(caller.call("run", &[]).unwrap()[0], Value::I32(1));
+        assert_eq!(host.get(3), Some(vec![9]));
+
+        // A host that ignores `isStatic` can not answer a static call.
+        host.on_call(|_, _| Ok(TestHost::call_response(&[1], 0)));
+        caller.set_static(true);
+        let error = caller.call("run", &[]).unwrap_err();
+        assert!(error.to_string().contains("does not confirm"), "{}", error);
+    }
 }
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::domain::runner::BitcoinNetwork;
+
+/// Everything the constructor of a pooled instance could have observed without asking
+/// the host: its code and, through `encodeAddress`, the network it ran on.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InstancePoolKey {
+    pub code_hash: String,
+    pub network: BitcoinNetwork,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InstancePoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub idle: usize,
+    pub discarded: u64,
+    /// Time spent instantiating contracts from scratch.
+    pub instantiation_time: Duration,
+    pub instantiations: u64,
+    /// Time spent resetting pooled instances instead.
+    pub reset_time: Duration,
+    pub resets: u64,
+}
+
+/// Idle, already constructed instances per compiled module, ready to be reset and reused.
+pub struct InstancePool<R> {
+    idle: HashMap<InstancePoolKey, Vec<R>>,
+    max_idle_per_module: usize,
+    stats: InstancePoolStats,
+}
+
+impl<R> InstancePool<R> {
+    pub fn new(max_idle_per_module: usize) -> Self {
+        Self {
+            idle: HashMap::new(),
+            max_idle_per_module,
+            stats: InstancePoolStats::default(),
+        }
+    }
+
+    pub fn acquire(&mut self, key: &InstancePoolKey) -> Option<R> {
+        match self.idle.get_mut(key).and_then(|instances| instances.pop()) {
+            Some(instance) => {
+                self.stats.hits += 1;
+                self.stats.idle -= 1;
+
+                Some(instance)
+            }
+            None => {
+                self.stats.misses += 1;
+
+                None
+            }
+        }
+    }
+
+    /// Keeps `instance` for later reuse, returns `false` and drops it when the pool of its
+    /// module is full.
+    pub fn release(&mut self, key: InstancePoolKey, instance: R) -> bool {
+        let instances = self.idle.entry(key).or_default();
+        if instances.len() >= self.max_idle_per_module {
+            self.stats.discarded += 1;
+
+            return false;
+        }
+
+        instances.push(instance);
+        self.stats.idle += 1;
+
+        true
+    }
+
+    /// Drops the idle instances of `code_hash`, whatever network they ran on.
+    pub fn remove(&mut self, code_hash: &str) {
+        let stats = &mut self.stats;
+        self.idle.retain(|key, instances| {
+            if key.code_hash != code_hash {
+                return true;
+            }
+
+            stats.idle -= instances.len();
+            false
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.idle.clear();
+        self.stats.idle = 0;
+    }
+
+    pub fn record_instantiation(&mut self, duration: Duration) {
+        self.stats.instantiation_time += duration;
+        self.stats.instantiations += 1;
+    }
+
+    pub fn record_reset(&mut self, duration: Duration) {
+        self.stats.reset_time += duration;
+        self.stats.resets += 1;
+    }
+
+    pub fn record_discard(&mut self) {
+        self.stats.discarded += 1;
+    }
+
+    pub fn stats(&self) -> InstancePoolStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code_hash: &str, network: BitcoinNetwork) -> InstancePoolKey {
+        InstancePoolKey { code_hash: code_hash.to_string(), network }
+    }
+
+    #[test]
+    fn reuses_released_instances_of_the_same_module() {
+        let mut pool = InstancePool::new(2);
+        let a = key("a", BitcoinNetwork::Regtest);
+
+        assert_eq!(pool.acquire(&a), None);
+        assert!(pool.release(a.clone(), 1));
+        assert!(pool.release(a.clone(), 2));
+        assert!(!pool.release(a.clone(), 3));
+
+        assert_eq!(pool.acquire(&key("b", BitcoinNetwork::Regtest)), None);
+        assert_eq!(pool.acquire(&a), Some(2));
+
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses, stats.idle, stats.discarded), (1, 2, 1, 1));
+    }
+
+    #[test]
+    fn never_hands_out_instances_constructed_on_another_network() {
+        let mut pool = InstancePool::new(2);
+        pool.release(key("a", BitcoinNetwork::Mainnet), 1);
+
+        assert_eq!(pool.acquire(&key("a", BitcoinNetwork::Testnet)), None);
+        assert_eq!(pool.acquire(&key("a", BitcoinNetwork::Mainnet)), Some(1));
+    }
+
+    #[test]
+    fn removing_a_module_drops_its_idle_instances() {
+        let mut pool = InstancePool::new(2);
+        pool.release(key("a", BitcoinNetwork::Mainnet), 1);
+        pool.release(key("a", BitcoinNetwork::Regtest), 2);
+        pool.release(key("b", BitcoinNetwork::Mainnet), 3);
+
+        pool.remove("a");
+
+        assert_eq!(pool.acquire(&key("a", BitcoinNetwork::Mainnet)), None);
+        assert_eq!(pool.stats().idle, 1);
+    }
+}
@@ -0,0 +1,341 @@
+use wasmer::Value;
+
+use crate::domain::runner::AbortData;
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"OPVMSNP2";
+
+const VALUE_I32: u8 = 0;
+const VALUE_I64: u8 = 1;
+const VALUE_F32: u8 = 2;
+const VALUE_F64: u8 = 3;
+
+/// State of the host side of a call, restored together with the instance.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CallState {
+    pub abort_data: Option<AbortData>,
+    pub host_calls: u64,
+    pub constructor_gas_used: u64,
+    /// Pending writes of a simulated call, `None` when writes go to the host.
+    pub storage_overlay: Option<Vec<(Vec<u8>, Vec<u8>)>>,
+}
+
+/// Linear memory, mutable globals, remaining gas and call state of an instance, along
+/// with the hash of the bytecode it was taken from.
+///
+/// Globals are identified by their export name rather than by handle, so a snapshot can
+/// be serialized and restored into any instance of the same module. Tables are not part
+/// of the snapshot: every table operator is rejected by the gas schedule, so they cannot
+/// change after instantiation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstanceSnapshot {
+    pub code_hash: [u8; 32],
+    pub memory: Vec<u8>,
+    pub globals: Vec<(String, Value)>,
+    pub remaining_gas: u64,
+    pub call_state: CallState,
+}
+
+impl InstanceSnapshot {
+    pub fn memory_size(&self) -> u64 {
+        self.memory.len() as u64
+    }
+
+    /// Memory can not shrink, an instance whose memory is larger than the snapshot can
+    /// not be brought back to the exact state it describes.
+    pub fn check_memory_size(&self, current_size: u64) -> anyhow::Result<()> {
+        if current_size > self.memory_size() {
+            return Err(anyhow::anyhow!(
+                "instance memory grew from {} to {} bytes and can not be restored",
+                self.memory_size(),
+                current_size
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Only ensures the snapshot was taken from an instance of the same bytecode, the
+    /// instance checks that everything else fits before restoring it.
+    pub fn check_code_hash(&self, code_hash: &[u8; 32]) -> anyhow::Result<()> {
+        if &self.code_hash != code_hash {
+            return Err(anyhow::anyhow!(
+                "snapshot was taken from bytecode {}, not {}",
+                hex::encode(self.code_hash),
+                hex::encode(code_hash)
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let mut writer = SnapshotWriter::default();
+        writer.bytes.extend_from_slice(SNAPSHOT_MAGIC);
+        writer.bytes.extend_from_slice(&self.code_hash);
+
+        writer.write_u64(self.remaining_gas);
+        writer.write_blob(&self.memory);
+
+        writer.write_u64(self.globals.len() as u64);
+        for (name, value) in &self.globals {
+            writer.write_blob(name.as_bytes());
+            writer.write_value(name, value)?;
+        }
+
+        let call_state = &self.call_state;
+        writer.write_u64(call_state.host_calls);
+        writer.write_u64(call_state.constructor_gas_used);
+
+        match &call_state.abort_data {
+            Some(abort_data) => {
+                writer.write_u8(1);
+                for field in [abort_data.message, abort_data.file_name, abort_data.line, abort_data.column] {
+                    writer.write_u32(field);
+                }
+            }
+            None => writer.write_u8(0),
+        }
+
+        match &call_state.storage_overlay {
+            Some(writes) => {
+                writer.write_u8(1);
+                writer.write_u64(writes.len() as u64);
+                for (pointer, value) in writes {
+                    writer.write_blob(pointer);
+                    writer.write_blob(value);
+                }
+            }
+            None => writer.write_u8(0),
+        }
+
+        Ok(writer.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = SnapshotReader { bytes, offset: 0 };
+
+        if reader.read(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(anyhow::anyhow!("not an instance snapshot"));
+        }
+
+        let code_hash = reader.read(32)?.try_into()?;
+        let remaining_gas = reader.read_u64()?;
+        let memory = reader.read_blob()?.to_vec();
+
+        let global_count = reader.read_u64()?;
+        let mut globals = Vec::new();
+        for _ in 0..global_count {
+            let name = String::from_utf8(reader.read_blob()?.to_vec())?;
+            globals.push((name, reader.read_value()?));
+        }
+
+        let host_calls = reader.read_u64()?;
+        let constructor_gas_used = reader.read_u64()?;
+
+        let abort_data = match reader.read_u8()? {
+            0 => None,
+            _ => Some(AbortData {
+                message: reader.read_u32()?,
+                file_name: reader.read_u32()?,
+                line: reader.read_u32()?,
+                column: reader.read_u32()?,
+            }),
+        };
+
+        let storage_overlay = match reader.read_u8()? {
+            0 => None,
+            _ => {
+                let write_count = reader.read_u64()?;
+                let mut writes = Vec::new();
+                for _ in 0..write_count {
+                    let pointer = reader.read_blob()?.to_vec();
+                    let value = reader.read_blob()?.to_vec();
+                    writes.push((pointer, value));
+                }
+
+                Some(writes)
+            }
+        };
+
+        if reader.offset != bytes.len() {
+            return Err(anyhow::anyhow!("trailing bytes after instance snapshot"));
+        }
+
+        Ok(Self {
+            code_hash,
+            memory,
+            globals,
+            remaining_gas,
+            call_state: CallState {
+                abort_data,
+                host_calls,
+                constructor_gas_used,
+                storage_overlay,
+            },
+        })
+    }
+}
+
+#[derive(Default)]
+struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_blob(&mut self, blob: &[u8]) {
+        self.write_u64(blob.len() as u64);
+        self.bytes.extend_from_slice(blob);
+    }
+
+    fn write_value(&mut self, name: &str, value: &Value) -> anyhow::Result<()> {
+        match value {
+            Value::I32(value) => {
+                self.write_u8(VALUE_I32);
+                self.write_u64(*value as u32 as u64);
+            }
+            Value::I64(value) => {
+                self.write_u8(VALUE_I64);
+                self.write_u64(*value as u64);
+            }
+            Value::F32(value) => {
+                self.write_u8(VALUE_F32);
+                self.write_u64(value.to_bits() as u64);
+            }
+            Value::F64(value) => {
+                self.write_u8(VALUE_F64);
+                self.write_u64(value.to_bits());
+            }
+            _ => return Err(anyhow::anyhow!("global {} of type {} can not be serialized", name, value.ty())),
+        }
+
+        Ok(())
+    }
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn read(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("truncated instance snapshot"))?;
+
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into()?))
+    }
+
+    fn read_u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.read(8)?.try_into()?))
+    }
+
+    fn read_blob(&mut self) -> anyhow::Result<&'a [u8]> {
+        let length = usize::try_from(self.read_u64()?)?;
+        self.read(length)
+    }
+
+    fn read_value(&mut self) -> anyhow::Result<Value> {
+        let kind = self.read_u8()?;
+        let bits = self.read_u64()?;
+
+        match kind {
+            VALUE_I32 => Ok(Value::I32(bits as u32 as i32)),
+            VALUE_I64 => Ok(Value::I64(bits as i64)),
+            VALUE_F32 => Ok(Value::F32(f32::from_bits(bits as u32))),
+            VALUE_F64 => Ok(Value::F64(f64::from_bits(bits))),
+            _ => Err(anyhow::anyhow!("unknown global value type {}", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> InstanceSnapshot {
+        InstanceSnapshot {
+            code_hash: [9; 32],
+            memory: vec![1, 2, 3, 4],
+            globals: vec![
+                ("opvm_global_0".to_string(), Value::I32(-7)),
+                ("opvm_global_1".to_string(), Value::I64(i64::MAX)),
+            ],
+            remaining_gas: 42,
+            call_state: CallState {
+                abort_data: Some(AbortData { message: 1, file_name: 2, line: 3, column: 4 }),
+                host_calls: 5,
+                constructor_gas_used: 6,
+                storage_overlay: Some(vec![(vec![7; 32], vec![8, 9])]),
+            },
+        }
+    }
+
+    #[test]
+    fn round_trips_through_bytes() {
+        let snapshot = snapshot();
+
+        assert_eq!(InstanceSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap(), snapshot);
+        assert_eq!(
+            InstanceSnapshot::from_bytes(&InstanceSnapshot::default().to_bytes().unwrap()).unwrap(),
+            InstanceSnapshot::default()
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_or_foreign_blobs() {
+        let bytes = snapshot().to_bytes().unwrap();
+
+        assert!(InstanceSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(InstanceSnapshot::from_bytes(b"not a snapshot").is_err());
+    }
+
+    #[test]
+    fn refuses_globals_it_can_not_serialize() {
+        let snapshot = InstanceSnapshot {
+            globals: vec![("opvm_global_0".to_string(), Value::V128(1))],
+            ..Default::default()
+        };
+
+        assert!(snapshot.to_bytes().is_err());
+        assert!(snapshot.check_code_hash(&[0; 32]).is_ok());
+        assert!(snapshot.check_code_hash(&[1; 32]).is_err());
+    }
+
+    #[test]
+    fn memory_can_grow_but_not_shrink() {
+        let snapshot = InstanceSnapshot {
+            memory: vec![0; 65536],
+            ..Default::default()
+        };
+
+        assert!(snapshot.check_memory_size(65536).is_ok());
+        assert!(snapshot.check_memory_size(0).is_ok());
+        assert!(snapshot.check_memory_size(131072).is_err());
+    }
+}
@@ -1,10 +1,15 @@
-use crate::domain::runner::MAX_MEMORY_SIZE;
+use crate::domain::runner::InstanceSnapshot;
+use crate::domain::vm::{GLOBAL_EXPORT_PREFIX, MEMORY_REFUSED_PAGES_EXPORT};
 use wasmer::{
-    AsStoreMut, AsStoreRef, ExportError, Function, Instance, Memory, MemoryAccessError,
-    Value,
+    AsStoreMut, AsStoreRef, ExportError, Extern, Function, Global, Instance, Memory, MemoryAccessError,
+    Mutability, Pages, Type, Value, WASM_PAGE_SIZE,
 };
+use wasmer_types::RawValue;
+use wasmer_vm::{LinearMemory, VMExtern, VMMemoryDefinition};
 use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
+const METERING_REMAINING_POINTS_EXPORT: &str = "wasmer_metering_remaining_points";
+
 #[derive(Clone)]
 pub struct InstanceWrapper {
     instance: Instance,
@@ -27,12 +32,33 @@ impl InstanceWrapper {
         Ok(result)
     }
 
-    pub fn is_out_of_memory(&self, store: &(impl AsStoreRef + ?Sized)) -> Result<bool, MemoryAccessError> {
-        let memory = Self::get_memory(&self.instance);
-        let view = memory.view(store);
-        let size = view.data_size();
+    pub fn memory_pages(&self, store: &(impl AsStoreRef + ?Sized)) -> u32 {
+        Self::get_memory(&self.instance).view(store).size().0
+    }
 
-        Ok(MAX_MEMORY_SIZE <= size)
+    /// The ceiling the memory can grow to, as set by the tunables.
+    pub fn max_memory_pages(&self, store: &impl AsStoreRef) -> Option<u32> {
+        Self::get_memory(&self.instance).ty(store).maximum.map(|pages| pages.0)
+    }
+
+    /// Pages the last `memory.grow` asked for, if it was refused.
+    pub fn get_refused_memory_growth(&self, store: &mut impl AsStoreMut) -> Option<u32> {
+        let global = self.instance.exports.get_global(MEMORY_REFUSED_PAGES_EXPORT).ok()?;
+
+        match global.get(store).i32()? {
+            0 => None,
+            pages => Some(pages as u32),
+        }
+    }
+
+    pub fn clear_refused_memory_growth(&self, store: &mut impl AsStoreMut) {
+        if let Ok(global) = self.instance.exports.get_global(MEMORY_REFUSED_PAGES_EXPORT) {
+            let _ = global.set(store, Value::I32(0));
+        }
+    }
+
+    pub fn memory_size(&self, store: &(impl AsStoreRef + ?Sized)) -> u64 {
+        Self::get_memory(&self.instance).view(store).data_size()
     }
 
     pub fn read_memory(
@@ -95,6 +121,161 @@ impl InstanceWrapper {
         set_remaining_points(store, &self.instance, gas);
     }
 
+    /// Address of the metering points counter, so another thread can drain it while the
+    /// instance runs. Valid for as long as the instance and its store are alive.
+    pub fn remaining_gas_ptr(&self, store: &mut impl AsStoreMut) -> Option<*mut u64> {
+        let value = self.global_ptr(store, METERING_REMAINING_POINTS_EXPORT)?;
+
+        // SAFETY: `global_ptr` points at the live value of the global.
+        Some(unsafe { std::ptr::addr_of_mut!((*value).u64) })
+    }
+
+    /// Puts `function` in the first slot of the exported funcref table `name`.
+    pub fn set_table_function(&self, store: &mut impl AsStoreMut, name: &str, function: Function) -> anyhow::Result<()> {
+        let table = self.instance.exports.get_table(name)?;
+        table.set(store, 0, Value::FuncRef(Some(function)))?;
+
+        Ok(())
+    }
+
+    /// Address of the value of an exported global, with the same validity as
+    /// [`InstanceWrapper::remaining_gas_ptr`].
+    pub fn global_ptr(&self, store: &mut impl AsStoreMut, name: &str) -> Option<*mut RawValue> {
+        let global = self.instance.exports.get_extern(name)?;
+
+        match global.to_vm_extern() {
+            VMExtern::Global(handle) => {
+                let definition = handle.get(store.objects_mut()).vmglobal();
+
+                // SAFETY: `vmglobal` points at the live definition of the global.
+                Some(unsafe { std::ptr::addr_of_mut!((*definition.as_ptr()).val) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Base and length of the linear memory, updated in place when it grows. Valid for as
+    /// long as the instance and its store are alive.
+    pub fn memory_definition_ptr(&self, store: &mut impl AsStoreMut) -> Option<*const VMMemoryDefinition> {
+        let memory = self.instance.exports.get_extern("memory")?;
+
+        match memory.to_vm_extern() {
+            VMExtern::Memory(handle) => Some(handle.get(store.objects_mut()).vmmemory().as_ptr().cast_const()),
+            _ => None,
+        }
+    }
+
+    /// Names and types of the exported globals starting with `prefix`, sorted by name.
+    pub fn global_exports(&self, store: &impl AsStoreRef, prefix: &str) -> Vec<(String, Type)> {
+        let mut globals: Vec<(String, Type)> = self
+            .instance
+            .exports
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .filter_map(|(name, export)| match export {
+                Extern::Global(global) => Some((name.clone(), global.ty(store).ty)),
+                _ => None,
+            })
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        globals
+    }
+
+    pub fn get_global_i64(&self, store: &mut impl AsStoreMut, name: &str) -> Option<i64> {
+        let global = self.instance.exports.get_global(name).ok()?;
+        global.get(store).i64()
+    }
+
+    /// Captures linear memory and the mutable globals exported by `GlobalExports`.
+    /// Gas, host state and the code hash are left to the caller.
+    pub fn snapshot(&self, store: &mut impl AsStoreMut) -> anyhow::Result<InstanceSnapshot> {
+        let memory = Self::get_memory(&self.instance).view(store).copy_to_vec()?;
+
+        let globals = self
+            .mutable_globals(store)
+            .into_iter()
+            .map(|(name, global)| (name, global.get(store)))
+            .collect();
+
+        Ok(InstanceSnapshot {
+            memory,
+            globals,
+            ..Default::default()
+        })
+    }
+
+    /// Writes the memory and globals of `snapshot` back. Memory smaller than the snapshot
+    /// is grown to its size, memory that already grew past it can not be restored.
+    ///
+    /// Everything is checked before anything is written, so a snapshot that does not fit
+    /// this instance leaves it untouched.
+    pub fn restore(&self, store: &mut impl AsStoreMut, snapshot: &InstanceSnapshot) -> anyhow::Result<()> {
+        let memory = Self::get_memory(&self.instance);
+        let current_size = memory.view(store).data_size();
+        snapshot.check_memory_size(current_size)?;
+
+        if !snapshot.memory_size().is_multiple_of(WASM_PAGE_SIZE as u64) {
+            return Err(anyhow::anyhow!("snapshot memory of {} bytes is not a whole number of pages", snapshot.memory_size()));
+        }
+
+        let snapshot_pages = snapshot.memory_size() / WASM_PAGE_SIZE as u64;
+        if self.max_memory_pages(store).is_some_and(|maximum| snapshot_pages > maximum as u64) {
+            return Err(anyhow::anyhow!("snapshot memory of {} pages exceeds the limit of this instance", snapshot_pages));
+        }
+
+        let globals = self.mutable_globals(store);
+        if globals.len() != snapshot.globals.len() {
+            return Err(anyhow::anyhow!(
+                "snapshot holds {} globals, this instance has {}",
+                snapshot.globals.len(),
+                globals.len()
+            ));
+        }
+
+        for ((name, global), (snapshot_name, value)) in globals.iter().zip(&snapshot.globals) {
+            if name != snapshot_name {
+                return Err(anyhow::anyhow!("snapshot global {} does not exist in this instance", snapshot_name));
+            }
+
+            if global.ty(store).ty != value.ty() {
+                return Err(anyhow::anyhow!("snapshot global {} is a {}, not a {}", name, value.ty(), global.ty(store).ty));
+            }
+        }
+
+        if current_size < snapshot.memory_size() {
+            let missing_pages = (snapshot.memory_size() - current_size) / WASM_PAGE_SIZE as u64;
+            memory.grow(store, Pages(missing_pages as u32))?;
+        }
+
+        memory.view(store).write(0, &snapshot.memory)?;
+
+        for ((_, global), (_, value)) in globals.iter().zip(&snapshot.globals) {
+            global.set(store, value.clone())?;
+        }
+
+        Ok(())
+    }
+
+    /// The mutable globals exported by `GlobalExports`, sorted by name.
+    fn mutable_globals(&self, store: &impl AsStoreRef) -> Vec<(String, Global)> {
+        let mut globals: Vec<(String, Global)> = self
+            .instance
+            .exports
+            .iter()
+            .filter(|(name, _)| name.starts_with(GLOBAL_EXPORT_PREFIX))
+            .filter_map(|(name, export)| match export {
+                Extern::Global(global) if global.ty(store).mutability == Mutability::Var => {
+                    Some((name.clone(), global.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        globals
+    }
+
     fn get_memory(instance: &Instance) -> &Memory {
         instance.exports.get_memory("memory").unwrap()
     }
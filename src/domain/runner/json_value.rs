@@ -0,0 +1,437 @@
+use std::fmt;
+
+/// Just enough JSON for the messages of the [`DebugAdapter`](crate::domain::runner::DebugAdapter).
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Keys in insertion order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+/// Nesting past this depth is rejected rather than parsed recursively.
+const MAX_JSON_DEPTH: usize = 64;
+
+impl JsonValue {
+    pub fn object<const N: usize>(entries: [(&str, JsonValue); N]) -> Self {
+        Self::Object(entries.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    pub fn string(value: impl Into<String>) -> Self {
+        Self::String(value.into())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Self::Number(value) if value.fract() == 0.0 => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser { bytes: text.as_bytes(), position: 0 };
+        let value = parser.value(0)?;
+
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(format!("unexpected data at {}", parser.position));
+        }
+
+        Ok(value)
+    }
+
+    fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+        f.write_str("\"")?;
+        for character in value.chars() {
+            match character {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                character if (character as u32) < 0x20 => write!(f, "\\u{:04x}", character as u32)?,
+                character => write!(f, "{}", character)?,
+            }
+        }
+
+        f.write_str("\"")
+    }
+}
+
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(value) => Self::write_string(f, value),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(entries) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(",")?;
+                    }
+                    Self::write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for JsonValue {
+    fn from(value: i64) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl JsonParser<'_> {
+    fn value(&mut self, depth: usize) -> Result<JsonValue, String> {
+        if depth > MAX_JSON_DEPTH {
+            return Err("nested too deeply".to_string());
+        }
+
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string().map(JsonValue::String),
+            Some(b't') => self.literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.literal("null", JsonValue::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(format!("unexpected data at {}", self.position)),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.position += 1;
+        let mut entries = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            entries.push((key, self.value(depth + 1)?));
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b'}') => return Ok(JsonValue::Object(entries)),
+                _ => return Err(format!("expected , or }} at {}", self.position)),
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<JsonValue, String> {
+        self.position += 1;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+
+        loop {
+            values.push(self.value(depth + 1)?);
+
+            self.skip_whitespace();
+            match self.next() {
+                Some(b',') => continue,
+                Some(b']') => return Ok(JsonValue::Array(values)),
+                _ => return Err(format!("expected , or ] at {}", self.position)),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut value = String::new();
+
+        loop {
+            let start = self.position;
+            while let Some(byte) = self.peek() {
+                if byte == b'"' || byte == b'\\' {
+                    break;
+                }
+                if byte < 0x20 {
+                    return Err(format!("unescaped control character at {}", self.position));
+                }
+                self.position += 1;
+            }
+            // The input is a `str` and both stops are ASCII, so this stays on char boundaries.
+            value.push_str(std::str::from_utf8(&self.bytes[start..self.position]).unwrap());
+
+            match self.next() {
+                Some(b'"') => return Ok(value),
+                Some(b'\\') => {
+                    let escaped = match self.next() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.escaped_char()?,
+                        _ => return Err(format!("invalid escape at {}", self.position)),
+                    };
+                    value.push(escaped);
+                }
+                _ => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn escaped_char(&mut self) -> Result<char, String> {
+        let high = self.hex_code_unit()?;
+        let code = if (0xd800..0xdc00).contains(&high) {
+            self.expect(b'\\')?;
+            self.expect(b'u')?;
+            let low = self.hex_code_unit()?;
+            if !(0xdc00..0xe000).contains(&low) {
+                return Err(format!("unpaired surrogate at {}", self.position));
+            }
+            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+        } else {
+            high
+        };
+
+        char::from_u32(code).ok_or_else(|| format!("invalid character at {}", self.position))
+    }
+
+    fn hex_code_unit(&mut self) -> Result<u32, String> {
+        let digits = self
+            .bytes
+            .get(self.position..self.position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| format!("invalid escape at {}", self.position))?;
+        self.position += 4;
+
+        Ok(digits)
+    }
+
+    /// `-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?`, Rust's own parser would also take `1.` or `01`.
+    fn number(&mut self) -> Result<JsonValue, String> {
+        let start = self.position;
+        let invalid = || format!("invalid number at {}", start);
+
+        self.skip_if(|byte| byte == b'-');
+        match self.next() {
+            Some(b'0') => {}
+            Some(b'1'..=b'9') => {
+                self.skip_digits();
+            }
+            _ => return Err(invalid()),
+        }
+        if self.skip_if(|byte| byte == b'.') && !self.skip_digits() {
+            return Err(invalid());
+        }
+        if self.skip_if(|byte| byte == b'e' || byte == b'E') {
+            self.skip_if(|byte| byte == b'+' || byte == b'-');
+            if !self.skip_digits() {
+                return Err(invalid());
+            }
+        }
+
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(JsonValue::Number)
+            .ok_or_else(invalid)
+    }
+
+    /// Returns whether there was at least one digit.
+    fn skip_digits(&mut self) -> bool {
+        let start = self.position;
+        while self.skip_if(|byte| byte.is_ascii_digit()) {}
+
+        self.position > start
+    }
+
+    fn skip_if(&mut self, accept: impl Fn(u8) -> bool) -> bool {
+        match self.peek() {
+            Some(byte) if accept(byte) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            return Err(format!("unexpected data at {}", self.position));
+        }
+        self.position += literal.len();
+
+        Ok(value)
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        match self.next() {
+            Some(next) if next == byte => Ok(()),
+            _ => Err(format!("expected {} at {}", byte as char, self.position)),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_messages() {
+        let text = r#"{"seq":1,"type":"request","arguments":{"names":["a\"b","é"],"ok":true,"none":null,"n":-1.5}}"#;
+        let value = JsonValue::parse(text).unwrap();
+
+        assert_eq!(value.get("seq").and_then(JsonValue::as_i64), Some(1));
+        let names = value.get("arguments").and_then(|arguments| arguments.get("names")).unwrap();
+        assert_eq!(names.as_array().unwrap()[1].as_str(), Some("é"));
+        assert_eq!(JsonValue::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn parses_the_requests_of_the_adapter() {
+        let text = r#"{ "command" : "setBreakpoints", "arguments" : { "source" : { "path" : "C:\\contracts\\token.wasm" }, "breakpoints" : [ { "name" : "execute" }, { "name" : "a\u0020\"b\"" } ] }, "seq" : 7 }"#;
+        let request = JsonValue::parse(text).unwrap();
+        let arguments = request.get("arguments").unwrap();
+
+        assert_eq!(request.get("seq").and_then(JsonValue::as_i64), Some(7));
+        let path = arguments.get("source").and_then(|source| source.get("path"));
+        assert_eq!(path.and_then(JsonValue::as_str), Some(r"C:\contracts\token.wasm"));
+        let breakpoints = arguments.get("breakpoints").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(breakpoints[1].get("name").and_then(JsonValue::as_str), Some("a \"b\""));
+
+        let read = JsonValue::parse(r#"{"command":"readMemory","arguments":{"memoryReference":"0x400","offset":-16,"count":1e2}}"#).unwrap();
+        let arguments = read.get("arguments").unwrap();
+        assert_eq!(arguments.get("offset").and_then(JsonValue::as_i64), Some(-16));
+        assert_eq!(arguments.get("count").and_then(JsonValue::as_i64), Some(100));
+    }
+
+    #[test]
+    fn decodes_and_prints_escapes() {
+        let value = JsonValue::parse(r#""\"\\\/\b\f\n\r\t\u0041\u00E9\ud83d\ude00""#).unwrap();
+
+        assert_eq!(value.as_str(), Some("\"\\/\u{8}\u{c}\n\r\tAé😀"));
+        assert_eq!(JsonValue::string("a\u{1}\"").to_string(), r#""a\u0001\"""#);
+        assert_eq!(JsonValue::parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn rejects_broken_escapes_and_surrogates() {
+        for text in [r#""\x""#, r#""\u12""#, r#""\u+123""#, r#""\ud83d""#, r#""\ud83d\u0041""#, r#""\ude00""#, "\"a\u{1}\""] {
+            assert!(JsonValue::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn follows_the_number_grammar() {
+        for (text, number) in [("0", 0.0), ("-0.5", -0.5), ("12", 12.0), ("1.25e2", 125.0), ("2E-1", 0.2)] {
+            assert_eq!(JsonValue::parse(text), Ok(JsonValue::Number(number)), "{}", text);
+        }
+
+        for text in ["01", "1.", ".5", "-", "+1", "1e", "1e+", "--1", "0x10", "1.5.2"] {
+            assert!(JsonValue::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(JsonValue::parse("{\"a\":1").is_err());
+        assert!(JsonValue::parse("[1] 2").is_err());
+        assert!(JsonValue::parse("{\"a\" 1}").is_err());
+        assert!(JsonValue::parse("[1,]").is_err());
+        assert!(JsonValue::parse("tru").is_err());
+    }
+
+    #[test]
+    fn limits_the_nesting_depth() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+
+        assert!(JsonValue::parse(&nested(MAX_JSON_DEPTH + 1)).is_ok());
+        assert!(JsonValue::parse(&nested(MAX_JSON_DEPTH + 2)).is_err());
+        assert!(JsonValue::parse(&"{\"a\":".repeat(MAX_JSON_DEPTH + 2)).is_err());
+    }
+}
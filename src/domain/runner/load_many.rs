@@ -0,0 +1,95 @@
+use crate::domain::runner::STORAGE_POINTER_SIZE;
+
+/// Most pointers a single `loadMany` may ask for.
+pub const MAX_LOAD_MANY_POINTERS: usize = 256;
+
+/// Wire format of the `loadMany` import.
+///
+/// The request is a concatenation of 32 byte storage pointers. The response holds one
+/// value per pointer, in request order, each prefixed by its length as a little endian
+/// u32. The host answers a batch in the same format.
+pub struct LoadMany;
+
+impl LoadMany {
+    pub fn split_request(request: &[u8]) -> Result<Vec<&[u8]>, String> {
+        if request.is_empty() || !request.len().is_multiple_of(STORAGE_POINTER_SIZE) {
+            return Err(format!(
+                "Invalid loadMany request. Expected a multiple of {} bytes, got {}",
+                STORAGE_POINTER_SIZE,
+                request.len()
+            ));
+        }
+
+        let pointers: Vec<&[u8]> = request.chunks(STORAGE_POINTER_SIZE).collect();
+        if pointers.len() > MAX_LOAD_MANY_POINTERS {
+            return Err(format!(
+                "Invalid loadMany request. At most {} pointers can be loaded at once, got {}",
+                MAX_LOAD_MANY_POINTERS,
+                pointers.len()
+            ));
+        }
+
+        Ok(pointers)
+    }
+
+    pub fn pack_response(values: &[Vec<u8>]) -> Vec<u8> {
+        let mut response = Vec::with_capacity(values.iter().map(|value| 4 + value.len()).sum());
+        for value in values {
+            response.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            response.extend_from_slice(value);
+        }
+
+        response
+    }
+
+    pub fn unpack_response(response: &[u8], count: usize) -> Result<Vec<Vec<u8>>, String> {
+        let mut values = Vec::with_capacity(count);
+        let mut rest = response;
+
+        for _ in 0..count {
+            let (length, tail) = rest
+                .split_first_chunk::<4>()
+                .ok_or_else(|| "Truncated loadMany response".to_string())?;
+            let length = u32::from_le_bytes(*length) as usize;
+
+            if tail.len() < length {
+                return Err("Truncated loadMany response".to_string());
+            }
+
+            let (value, tail) = tail.split_at(length);
+            values.push(value.to_vec());
+            rest = tail;
+        }
+
+        if !rest.is_empty() {
+            return Err(format!("loadMany response has {} trailing bytes", rest.len()));
+        }
+
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_and_unpacks_values_in_order() {
+        let values = vec![vec![1, 2, 3], vec![], vec![4]];
+        let response = LoadMany::pack_response(&values);
+
+        assert_eq!(LoadMany::unpack_response(&response, 3).unwrap(), values);
+        assert!(LoadMany::unpack_response(&response, 2).is_err());
+        assert!(LoadMany::unpack_response(&response[..response.len() - 1], 3).is_err());
+    }
+
+    #[test]
+    fn splits_requests_into_pointers() {
+        let request = [[1u8; STORAGE_POINTER_SIZE], [2u8; STORAGE_POINTER_SIZE]].concat();
+
+        assert_eq!(LoadMany::split_request(&request).unwrap().len(), 2);
+        assert!(LoadMany::split_request(&request[1..]).is_err());
+        assert!(LoadMany::split_request(&[]).is_err());
+        assert!(LoadMany::split_request(&vec![0u8; STORAGE_POINTER_SIZE * (MAX_LOAD_MANY_POINTERS + 1)]).is_err());
+    }
+}
@@ -1,13 +1,39 @@
 pub use self::{
-    abort_data::*, bitcoin_network::*, constants::*, contract_runner::*, custom_env::*,
-    import_functions::*, instance_wrapper::*, wasmer_runner::*,
+    abort_data::*, access_list::*, bitcoin_network::*, call_options::*, cancellation_token::*, constants::*, contract_cache::*,
+    contract_runner::*, custom_env::*, debug_adapter::*, debugger::*, engine_registry::*, execution_limits::*, execution_trace::*, gas_profile::*, import_functions::*,
+    instance_pool::*, instance_snapshot::*, instance_wrapper::*, json_value::*, load_many::*, module_cache::*, out_of_memory::*,
+    static_call_violation::*, storage_access_log::*, storage_overlay::*, storage_read_cache::*, wasmer_runner::*, watchdog::*,
 };
 
 mod abort_data;
+mod access_list;
+mod call_options;
+mod cancellation_token;
+mod contract_cache;
 mod contract_runner;
 mod custom_env;
+mod debug_adapter;
+mod debugger;
+mod engine_registry;
+mod execution_limits;
+mod execution_trace;
+mod gas_profile;
 mod import_functions;
+mod instance_pool;
+mod instance_snapshot;
 mod instance_wrapper;
+mod json_value;
+mod load_many;
+mod module_cache;
+mod out_of_memory;
+mod probestack;
+mod static_call_violation;
+#[cfg(test)]
+pub(crate) mod test_host;
+mod storage_access_log;
+mod storage_overlay;
+mod storage_read_cache;
 mod wasmer_runner;
+mod watchdog;
 mod bitcoin_network;
 mod constants;
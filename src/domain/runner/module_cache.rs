@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use sha2::{Digest, Sha256};
+use wasmer::{AsEngineRef, DeserializeError, Module, SerializeError};
+use wasmer_cache::{Cache, Hash};
+
+use crate::domain::runner::{EngineKey, ARTIFACT_VERSION};
+
+const MODULE_CACHE_MAGIC: &[u8; 8] = b"OPVMMOD1";
+const MODULE_CACHE_CHECKSUM_SIZE: usize = 32;
+const MODULE_CACHE_HEADER_SIZE: usize = MODULE_CACHE_MAGIC.len() + MODULE_CACHE_CHECKSUM_SIZE;
+const MODULE_CACHE_DIRECTORY_PREFIX: &str = "opvm-";
+const MODULE_CACHE_TEMP_EXTENSION: &str = "tmp";
+const MODULE_CACHE_ACCESS_LOG: &str = "access.log";
+const MODULE_CACHE_ACCESS_LOG_MIN_LINES: usize = 1024;
+
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Filesystem-backed cache of compiled modules, keyed by the bytecode hash.
+///
+/// Artifacts live in a directory named after the VM, wasmer and gas schedule versions
+/// and the engine limits, so upgrading any of them invalidates every entry; directories
+/// of other versions are removed on open. Entries are written atomically, carry a
+/// checksum that is verified before deserialization, and the least recently used ones
+/// are evicted once the cache grows past `max_size` bytes. The order they were used in
+/// survives restarts through an [`AccessLog`].
+pub struct ModuleCache {
+    directory: PathBuf,
+    index: Mutex<LruIndex>,
+    access_log: Mutex<AccessLog>,
+}
+
+impl ModuleCache {
+    pub fn open(root: impl AsRef<Path>, max_size: u64, key: &EngineKey) -> io::Result<Self> {
+        let root = root.as_ref();
+        let version = Self::version_tag(key);
+        let directory = root.join(&version);
+
+        fs::create_dir_all(&directory)?;
+        Self::remove_stale_versions(root, &version)?;
+
+        let access_log_path = directory.join(MODULE_CACHE_ACCESS_LOG);
+        let last_used = AccessLog::read(&access_log_path)?;
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path == access_log_path {
+                continue;
+            }
+
+            let hash = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<Hash>().ok());
+
+            match hash {
+                Some(hash) => {
+                    let metadata = entry.metadata()?;
+                    files.push((hash, metadata.len(), metadata.modified()?));
+                }
+                // Leftovers of interrupted writes.
+                None => fs::remove_file(&path)?,
+            }
+        }
+
+        // Least recently used first, so the most recently used files end up with the highest
+        // ticks. Files the log does not know of, e.g. written right before a crash, come first.
+        files.sort_by_key(|(hash, _, modified)| (last_used.get(hash).copied(), *modified));
+
+        let mut index = LruIndex::new(max_size);
+        for (hash, size, _) in files {
+            index.insert(hash, size);
+        }
+
+        let access_log = AccessLog::create(access_log_path, &index.order())?;
+        let cache = Self {
+            directory,
+            index: Mutex::new(index),
+            access_log: Mutex::new(access_log),
+        };
+        cache.evict()?;
+
+        Ok(cache)
+    }
+
+    pub fn version_tag(key: &EngineKey) -> String {
+        format!(
+            "{}{}-artifact-{}-wasmer-{}-singlepass-schedule-{}-pages-{}-stack-{}",
+            MODULE_CACHE_DIRECTORY_PREFIX,
+            env!("CARGO_PKG_VERSION"),
+            ARTIFACT_VERSION,
+            wasmer::VERSION,
+            key.gas_schedule,
+            key.max_pages,
+            key.stack_size
+        )
+    }
+
+    pub fn contains(&self, key: &Hash) -> bool {
+        self.index.lock().unwrap().contains(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().total_size()
+    }
+
+    fn path(&self, key: &Hash) -> PathBuf {
+        self.directory.join(key.to_string())
+    }
+
+    fn remove(&self, key: &Hash) {
+        self.index.lock().unwrap().remove(key);
+        let _ = fs::remove_file(self.path(key));
+    }
+
+    fn evict(&self) -> io::Result<()> {
+        let evicted = self.index.lock().unwrap().evict();
+        for key in evicted {
+            match fs::remove_file(self.path(&key)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that `key` was used, compacting the log once it mostly holds superseded
+    /// accesses. The order is only a hint for eviction, failing to record it is ignored.
+    fn record_access(&self, key: &Hash) {
+        let mut access_log = self.access_log.lock().unwrap();
+        if access_log.append(key).is_err() {
+            return;
+        }
+
+        let order = {
+            let index = self.index.lock().unwrap();
+            if access_log.lines <= MODULE_CACHE_ACCESS_LOG_MIN_LINES.max(index.len() * 4) {
+                return;
+            }
+
+            index.order()
+        };
+
+        if let Ok(compacted) = AccessLog::create(access_log.path.clone(), &order) {
+            *access_log = compacted;
+        }
+    }
+
+    /// Removes the directories other versions of the cache left in `root`. Anything not
+    /// named like [`ModuleCache::version_tag`] is not the cache's to remove.
+    fn remove_stale_versions(root: &Path, version: &str) -> io::Result<()> {
+        for entry in fs::read_dir(root)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+
+            if entry.file_type()?.is_dir() && Self::is_version_tag(&name) && name != version {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `name` follows the naming scheme of [`ModuleCache::version_tag`], with or
+    /// without the artifact version older tags lacked.
+    fn is_version_tag(name: &str) -> bool {
+        let Some(rest) = name.strip_prefix(MODULE_CACHE_DIRECTORY_PREFIX) else {
+            return false;
+        };
+
+        let mut rest = rest;
+        for separator in ["-wasmer-", "-singlepass-schedule-", "-pages-", "-stack-"] {
+            match rest.split_once(separator) {
+                Some((before, after)) if !before.is_empty() => rest = after,
+                _ => return false,
+            }
+        }
+
+        !rest.is_empty() && rest.bytes().all(|byte| byte.is_ascii_digit())
+    }
+
+    fn write_atomically(&self, key: &Hash, contents: &[u8]) -> io::Result<()> {
+        let temp_path = self.directory.join(format!(
+            "{}.{}.{}.{}",
+            key,
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            MODULE_CACHE_TEMP_EXTENSION
+        ));
+
+        let result = (|| {
+            let mut file = fs::File::create(&temp_path)?;
+            file.write_all(contents)?;
+            file.sync_all()?;
+
+            fs::rename(&temp_path, self.path(key))
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+}
+
+impl Cache for ModuleCache {
+    type SerializeError = SerializeError;
+    type DeserializeError = DeserializeError;
+
+    unsafe fn load(&self, engine: &impl AsEngineRef, key: Hash) -> Result<Module, Self::DeserializeError> {
+        let contents = fs::read(self.path(&key)).map_err(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                self.index.lock().unwrap().remove(&key);
+            }
+
+            DeserializeError::Io(e)
+        })?;
+
+        let module = decode_entry(&contents)
+            .and_then(|artifact| Module::deserialize(engine, artifact.to_vec()));
+
+        match module {
+            Ok(module) => {
+                self.index.lock().unwrap().touch(&key);
+                self.record_access(&key);
+
+                Ok(module)
+            }
+            Err(e) => {
+                // A corrupted or incompatible entry is never trusted again.
+                self.remove(&key);
+
+                Err(e)
+            }
+        }
+    }
+
+    fn store(&mut self, key: Hash, module: &Module) -> Result<(), Self::SerializeError> {
+        let artifact = module.serialize()?;
+        let contents = encode_entry(&artifact);
+
+        if !self.index.lock().unwrap().fits(contents.len() as u64) {
+            return Ok(());
+        }
+
+        self.write_atomically(&key, &contents)?;
+        self.index.lock().unwrap().insert(key, contents.len() as u64);
+        self.record_access(&key);
+        self.evict()?;
+
+        Ok(())
+    }
+}
+
+/// Prefixes the serialized artifact with a magic and its SHA-256 checksum.
+fn encode_entry(artifact: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(MODULE_CACHE_HEADER_SIZE + artifact.len());
+    contents.extend_from_slice(MODULE_CACHE_MAGIC);
+    contents.extend_from_slice(&Sha256::digest(artifact));
+    contents.extend_from_slice(artifact);
+
+    contents
+}
+
+fn decode_entry(contents: &[u8]) -> Result<&[u8], DeserializeError> {
+    if contents.len() < MODULE_CACHE_HEADER_SIZE || &contents[..MODULE_CACHE_MAGIC.len()] != MODULE_CACHE_MAGIC {
+        return Err(DeserializeError::Incompatible("not a module cache entry".to_string()));
+    }
+
+    let (checksum, artifact) = contents[MODULE_CACHE_MAGIC.len()..].split_at(MODULE_CACHE_CHECKSUM_SIZE);
+    if Sha256::digest(artifact).as_slice() != checksum {
+        return Err(DeserializeError::CorruptedBinary("module cache entry checksum mismatch".to_string()));
+    }
+
+    Ok(artifact)
+}
+
+/// Append-only record of the entries used, one hash per line. The modification time of
+/// an entry only tells when it was written, loading it again would not make it recent.
+struct AccessLog {
+    path: PathBuf,
+    file: fs::File,
+    lines: usize,
+}
+
+impl AccessLog {
+    /// The line each entry was last used on, later lines being more recent.
+    fn read(path: &Path) -> io::Result<HashMap<Hash, usize>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(e),
+        };
+
+        Ok(contents
+            .lines()
+            .enumerate()
+            .filter_map(|(line, hash)| Some((hash.parse::<Hash>().ok()?, line)))
+            .collect())
+    }
+
+    /// Atomically replaces the log at `path` with `order`, least recently used first.
+    fn create(path: PathBuf, order: &[Hash]) -> io::Result<Self> {
+        let contents: String = order.iter().map(|hash| format!("{}\n", hash)).collect();
+        let temp_path = path.with_extension(format!(
+            "{}.{}.{}",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+            MODULE_CACHE_TEMP_EXTENSION
+        ));
+
+        let result = (|| {
+            fs::write(&temp_path, contents)?;
+            fs::rename(&temp_path, &path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+
+        let file = fs::OpenOptions::new().append(true).open(&path)?;
+
+        Ok(Self { path, file, lines: order.len() })
+    }
+
+    fn append(&mut self, key: &Hash) -> io::Result<()> {
+        self.file.write_all(format!("{}\n", key).as_bytes())?;
+        self.lines += 1;
+
+        Ok(())
+    }
+}
+
+struct LruEntry {
+    size: u64,
+    last_used: u64,
+}
+
+/// Tracks the size and recency of cache entries, deciding what to evict.
+struct LruIndex {
+    entries: HashMap<Hash, LruEntry>,
+    max_size: u64,
+    total_size: u64,
+    clock: u64,
+}
+
+impl LruIndex {
+    fn new(max_size: u64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_size,
+            total_size: 0,
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn contains(&self, key: &Hash) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    fn fits(&self, size: u64) -> bool {
+        size <= self.max_size
+    }
+
+    fn insert(&mut self, key: Hash, size: u64) {
+        let last_used = self.tick();
+        if let Some(previous) = self.entries.insert(key, LruEntry { size, last_used }) {
+            self.total_size -= previous.size;
+        }
+
+        self.total_size += size;
+    }
+
+    fn touch(&mut self, key: &Hash) {
+        let last_used = self.tick();
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = last_used;
+        }
+    }
+
+    fn remove(&mut self, key: &Hash) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_size -= entry.size;
+        }
+    }
+
+    /// Every key, least recently used first.
+    fn order(&self) -> Vec<Hash> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.last_used);
+
+        entries.into_iter().map(|(key, _)| *key).collect()
+    }
+
+    /// Drops least recently used entries until the cache fits, returning their keys.
+    fn evict(&mut self) -> Vec<Hash> {
+        let mut evicted = Vec::new();
+
+        while self.total_size > self.max_size {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| *key);
+
+            match oldest {
+                Some(key) => {
+                    self.remove(&key);
+                    evicted.push(key);
+                }
+                None => break,
+            }
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::runner::EngineRegistry;
+
+    fn hash(byte: u8) -> Hash {
+        Hash::new([byte; 32])
+    }
+
+    /// An empty directory of its own for each test.
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("module-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        root
+    }
+
+    #[test]
+    fn entries_round_trip_and_detect_corruption() {
+        let mut contents = encode_entry(&[1, 2, 3, 4]);
+
+        assert_eq!(decode_entry(&contents).unwrap(), &[1, 2, 3, 4]);
+
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        assert!(matches!(decode_entry(&contents), Err(DeserializeError::CorruptedBinary(_))));
+        assert!(matches!(decode_entry(&[0u8; 4]), Err(DeserializeError::Incompatible(_))));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries_first() {
+        let mut index = LruIndex::new(100);
+        index.insert(hash(1), 40);
+        index.insert(hash(2), 40);
+        index.touch(&hash(1));
+        index.insert(hash(3), 40);
+
+        assert_eq!(index.evict(), vec![hash(2)]);
+        assert!(index.contains(&hash(1)));
+        assert!(index.contains(&hash(3)));
+        assert_eq!(index.total_size(), 80);
+    }
+
+    #[test]
+    fn replacing_an_entry_updates_the_total_size() {
+        let mut index = LruIndex::new(100);
+        index.insert(hash(1), 60);
+        index.insert(hash(1), 30);
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.total_size(), 30);
+        assert!(!index.fits(101));
+    }
+
+    #[test]
+    fn keeps_the_access_order_across_restarts() {
+        let root = test_root("access-order");
+        let key = EngineKey::default();
+        let engine = EngineRegistry::global().runtime(&key);
+        let bytecode = wasmer::wat2wasm(br#"(module (func (export "run")))"#).unwrap();
+        let module = EngineRegistry::global().compile(&key, &bytecode).unwrap();
+
+        let mut cache = ModuleCache::open(&root, u64::MAX, &key).unwrap();
+        cache.store(hash(1), &module).unwrap();
+        cache.store(hash(2), &module).unwrap();
+        unsafe { cache.load(&engine, hash(1)).unwrap() };
+        let entry_size = cache.size() / 2;
+        drop(cache);
+
+        // Only one entry fits, the one loaded last was used more recently than written.
+        let cache = ModuleCache::open(&root, entry_size, &key).unwrap();
+        assert!(cache.contains(&hash(1)));
+        assert!(!cache.contains(&hash(2)));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn removes_only_other_versions_of_the_cache() {
+        let root = test_root("stale-versions");
+        let key = EngineKey::default();
+        let stale = "opvm-0.0.1-wasmer-4.0.0-singlepass-schedule-1-pages-16-stack-1024";
+        for name in [stale, "opvm-data", "other"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+        }
+
+        ModuleCache::open(&root, u64::MAX, &key).unwrap();
+
+        assert!(!root.join(stale).exists());
+        assert!(root.join("opvm-data").exists());
+        assert!(root.join("other").exists());
+        assert!(root.join(ModuleCache::version_tag(&key)).exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn version_tag_changes_with_the_engine() {
+        let key = EngineKey::default();
+        let other = EngineKey { gas_schedule: key.gas_schedule + 1, ..key };
+
+        assert!(ModuleCache::is_version_tag(&ModuleCache::version_tag(&key)));
+        assert_ne!(ModuleCache::version_tag(&key), ModuleCache::version_tag(&other));
+    }
+}
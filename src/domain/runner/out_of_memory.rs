@@ -0,0 +1,48 @@
+use std::fmt;
+
+/// AssemblyScript runtime messages of allocations that can never be satisfied.
+pub const ASSEMBLY_SCRIPT_OUT_OF_MEMORY_MESSAGES: [&str; 2] = ["Allocation too large", "Out of memory"];
+
+/// A call failed because the contract could not get the memory it asked for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfMemory {
+    /// Total pages the instance would have had after the refused `memory.grow`, `None`
+    /// when the allocator gave up before growing the memory.
+    pub requested_pages: Option<u32>,
+    pub available_pages: u32,
+}
+
+impl fmt::Display for OutOfMemory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.requested_pages {
+            Some(requested_pages) => write!(
+                f,
+                "out of memory: requested {} pages, {} available",
+                requested_pages, self.available_pages
+            ),
+            None => write!(f, "out of memory: allocation exceeds the {} available pages", self.available_pages),
+        }
+    }
+}
+
+impl std::error::Error for OutOfMemory {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_requested_and_available_pages() {
+        let refused_growth = OutOfMemory {
+            requested_pages: Some(513),
+            available_pages: 512,
+        };
+        let aborted = OutOfMemory {
+            requested_pages: None,
+            available_pages: 512,
+        };
+
+        assert_eq!(refused_growth.to_string(), "out of memory: requested 513 pages, 512 available");
+        assert_eq!(aborted.to_string(), "out of memory: allocation exceeds the 512 available pages");
+    }
+}
@@ -0,0 +1,29 @@
+//! `wasmer-vm` links compiled code against `__rust_probestack`, which recent toolchains no
+//! longer export from `compiler_builtins`. Without it no instance can be created, so this
+//! provides the same stack probe, as a weak symbol so a toolchain that still ships one wins.
+
+#[cfg(all(target_arch = "x86_64", not(target_os = "windows"), not(target_os = "macos")))]
+std::arch::global_asm!(
+    ".text",
+    ".weak __rust_probestack",
+    ".type __rust_probestack, @function",
+    "__rust_probestack:",
+    "    push rbp",
+    "    mov rbp, rsp",
+    "    mov r11, rax",
+    "    cmp r11, 0x1000",
+    "    jna 3f",
+    "2:",
+    "    sub rsp, 0x1000",
+    "    test qword ptr [rsp + 8], rsp",
+    "    sub r11, 0x1000",
+    "    cmp r11, 0x1000",
+    "    ja 2b",
+    "3:",
+    "    sub rsp, r11",
+    "    test qword ptr [rsp + 8], rsp",
+    "    add rsp, rax",
+    "    leave",
+    "    ret",
+    ".size __rust_probestack, . - __rust_probestack",
+);
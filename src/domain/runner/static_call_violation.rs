@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// A static call tried to change state: write storage, deploy a contract or emit an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaticCallViolation {
+    pub import: &'static str,
+}
+
+impl fmt::Display for StaticCallViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "static call violation: {} is not allowed in a static call", self.import)
+    }
+}
+
+impl std::error::Error for StaticCallViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_the_refused_import() {
+        let violation = StaticCallViolation { import: "store" };
+
+        assert_eq!(violation.to_string(), "static call violation: store is not allowed in a static call");
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::domain::runner::STORAGE_POINTER_SIZE;
+
+/// A speculative call needed the host for more than its own storage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpeculationAborted {
+    pub import: &'static str,
+}
+
+impl fmt::Display for SpeculationAborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "speculation aborted: {} needs the host", self.import)
+    }
+}
+
+impl std::error::Error for SpeculationAborted {}
+
+/// The storage a call read from the host and wrote, so calls of a block can be checked
+/// for conflicts.
+#[derive(Clone, Debug, Default)]
+pub struct StorageAccessLog {
+    reads: HashSet<Vec<u8>>,
+    writes: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The call reached state the log can not see, e.g. through a nested call.
+    escaped: bool,
+}
+
+impl StorageAccessLog {
+    /// Records a load that was not served by the call's own writes.
+    pub fn record_read(&mut self, pointer: &[u8]) {
+        if !self.writes.contains_key(pointer) {
+            self.reads.insert(pointer.to_vec());
+        }
+    }
+
+    /// Records a `store` request, the pointer followed by the value.
+    pub fn record_write(&mut self, request: &[u8]) {
+        if request.len() >= STORAGE_POINTER_SIZE {
+            let (pointer, value) = request.split_at(STORAGE_POINTER_SIZE);
+            self.writes.insert(pointer.to_vec(), value.to_vec());
+        }
+    }
+
+    pub fn mark_escaped(&mut self) {
+        self.escaped = true;
+    }
+
+    pub fn reads(&self) -> &HashSet<Vec<u8>> {
+        &self.reads
+    }
+
+    /// Last value written to every pointer, sorted by pointer.
+    pub fn writes(&self) -> &BTreeMap<Vec<u8>, Vec<u8>> {
+        &self.writes
+    }
+
+    pub fn is_escaped(&self) -> bool {
+        self.escaped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_reads_of_own_writes() {
+        let mut log = StorageAccessLog::default();
+        log.record_read(&[1; 32]);
+        log.record_write(&[[2; 32], [7; 32]].concat());
+        log.record_read(&[2; 32]);
+
+        assert_eq!(log.reads().len(), 1);
+        assert!(log.reads().contains(&vec![1; 32]));
+        assert_eq!(log.writes().get(&vec![2; 32]), Some(&vec![7; 32]));
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+/// Storage pointers are 32 bytes. A `store` request is the pointer followed by the value,
+/// a `load` request is the pointer alone.
+pub const STORAGE_POINTER_SIZE: usize = 32;
+
+/// A throwaway storage layer used when a call must not commit anything to the host.
+///
+/// Writes are kept in memory and served back to subsequent loads of the same pointer;
+/// pointers that were never written are still read from the host.
+#[derive(Default)]
+pub struct StorageOverlay {
+    writes: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl StorageOverlay {
+    pub fn load(&self, request: &[u8]) -> Option<Vec<u8>> {
+        self.writes.get(request).cloned()
+    }
+
+    pub fn store(&mut self, request: &[u8]) -> Result<(), String> {
+        if request.len() < STORAGE_POINTER_SIZE {
+            return Err(format!(
+                "Invalid storage write. Expected at least {} bytes, got {}",
+                STORAGE_POINTER_SIZE,
+                request.len()
+            ));
+        }
+
+        let (pointer, value) = request.split_at(STORAGE_POINTER_SIZE);
+        self.writes.insert(pointer.to_vec(), value.to_vec());
+
+        Ok(())
+    }
+
+    /// Pending writes sorted by pointer, so equal overlays always serialize the same way.
+    pub fn entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut entries: Vec<_> = self.writes.iter().map(|(pointer, value)| (pointer.clone(), value.clone())).collect();
+        entries.sort();
+
+        entries
+    }
+
+    pub fn from_entries(entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            writes: entries.into_iter().collect(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_written_values_back() {
+        let mut overlay = StorageOverlay::default();
+        let pointer = [7u8; STORAGE_POINTER_SIZE];
+        let mut request = pointer.to_vec();
+        request.extend_from_slice(&[1, 2, 3]);
+
+        overlay.store(&request).unwrap();
+
+        assert_eq!(overlay.load(&pointer), Some(vec![1, 2, 3]));
+        assert_eq!(overlay.load(&[8u8; STORAGE_POINTER_SIZE]), None);
+        assert_eq!(overlay.len(), 1);
+    }
+
+    #[test]
+    fn entries_round_trip_in_pointer_order() {
+        let overlay = StorageOverlay::from_entries(vec![(vec![2; 32], vec![2]), (vec![1; 32], vec![1])]);
+
+        assert_eq!(overlay.entries(), vec![(vec![1; 32], vec![1]), (vec![2; 32], vec![2])]);
+        assert_eq!(StorageOverlay::from_entries(overlay.entries()).entries(), overlay.entries());
+    }
+
+    #[test]
+    fn rejects_short_writes() {
+        let mut overlay = StorageOverlay::default();
+
+        assert!(overlay.store(&[0u8; 4]).is_err());
+        assert!(overlay.is_empty());
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use crate::domain::runner::STORAGE_POINTER_SIZE;
+
+/// Storage values known for the duration of a call, so `load` and `loadMany` can skip the
+/// roundtrip to the host. A load served from the cache costs the same gas as one that is
+/// not, the cache never changes the result of a call.
+///
+/// A nested call or deployment may call back into the contract and write its storage, so
+/// the cache is cleared whenever one returns.
+#[derive(Default)]
+pub struct StorageReadCache {
+    values: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl StorageReadCache {
+    pub fn from_entries(entries: impl IntoIterator<Item = (Vec<u8>, Vec<u8>)>) -> Self {
+        Self {
+            values: entries.into_iter().collect(),
+        }
+    }
+
+    pub fn load(&self, pointer: &[u8]) -> Option<Vec<u8>> {
+        self.values.get(pointer).cloned()
+    }
+
+    pub fn insert(&mut self, pointer: &[u8], value: &[u8]) {
+        self.values.insert(pointer.to_vec(), value.to_vec());
+    }
+
+    /// Keeps the cache in line with a `store` request sent to the host.
+    pub fn record_store(&mut self, request: &[u8]) {
+        if request.len() >= STORAGE_POINTER_SIZE {
+            let (pointer, value) = request.split_at(STORAGE_POINTER_SIZE);
+            self.insert(pointer, value);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_stores_sent_to_the_host() {
+        let pointer = [7u8; STORAGE_POINTER_SIZE];
+        let mut cache = StorageReadCache::from_entries(vec![(pointer.to_vec(), vec![1])]);
+
+        let mut request = pointer.to_vec();
+        request.extend_from_slice(&[2, 3]);
+        cache.record_store(&request);
+
+        assert_eq!(cache.load(&pointer), Some(vec![2, 3]));
+        assert_eq!(cache.load(&[8u8; STORAGE_POINTER_SIZE]), None);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use wasmer::RuntimeError;
+
+use crate::domain::runner::{BitcoinNetwork, CustomEnv, ExecutionLimits, WasmerRunner, SIMULATION_CONFIRMATION, STATIC_CALL_CONFIRMATION, STORAGE_POINTER_SIZE};
+use crate::domain::vm::ModuleLimits;
+use crate::interfaces::{
+    CallOtherContractExternalFunction, ConsoleLogExternalFunction, DeployFromAddressExternalFunction, HostRequestMode, HostRuntime,
+    NativeHostFunction, SaturationPolicy, StorageLoadExternalFunction, StorageLoadManyExternalFunction,
+    StorageStoreExternalFunction,
+};
+
+pub const TEST_MAX_GAS: u64 = 100_000_000_000;
+
+pub fn key(byte: u8) -> Vec<u8> {
+    vec![byte; STORAGE_POINTER_SIZE]
+}
+
+/// A module speaking enough of the AssemblyScript ABI for the host imports, with
+/// `functions` spliced in. Helpers for them:
+///
+/// - `$load_byte (key) -> value`: first byte stored under `key(key)`, -1 if empty.
+/// - `$store_byte (key, value)`: stores one byte under `key(key)`.
+/// - `$call_byte () -> value`: calls `bc1qcallee`, first byte of the answer or -1.
+/// - `$deploy ()`: deploys from `bc1qcallee`.
+/// - `$calldata (pointer) -> byte`: first byte of a buffer written with `write_buffer`.
+pub fn test_contract(functions: &str) -> Vec<u8> {
+    let wat = format!(
+        r#"(module
+            (import "env" "abort" (func $abort (param i32 i32 i32 i32)))
+            (import "env" "load" (func $load (param i32) (result i32)))
+            (import "env" "store" (func $store (param i32) (result i32)))
+            (import "env" "call" (func $call (param i32) (result i32)))
+            (import "env" "deployFromAddress" (func $deployFromAddress (param i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "bc1qcallee\00\01\02\03\04")
+            (global $heap (mut i32) (i32.const 1024))
+
+            (func $new (param $size i32) (param $id i32) (result i32)
+                (local $pointer i32)
+                (local.set $pointer (global.get $heap))
+                (global.set $heap
+                    (i32.and (i32.add (i32.add (global.get $heap) (local.get $size)) (i32.const 15)) (i32.const -16)))
+                (local.get $pointer))
+            (func (export "__new") (param i32 i32) (result i32) (call $new (local.get 0) (local.get 1)))
+            (func (export "__pin") (param i32) (result i32) (local.get 0))
+            (func (export "__unpin") (param i32))
+
+            (func $bytes (param $data i32) (param $length i32) (result i32)
+                (local $header i32)
+                (local.set $header (call $new (i32.const 12) (i32.const 13)))
+                (i32.store (local.get $header) (local.get $data))
+                (i32.store offset=4 (local.get $header) (local.get $data))
+                (i32.store offset=8 (local.get $header) (local.get $length))
+                (local.get $header))
+            (func $first_byte (param $buffer i32) (result i32)
+                (if (result i32) (i32.load offset=8 (local.get $buffer))
+                    (then (i32.load8_u (i32.load offset=4 (local.get $buffer))))
+                    (else (i32.const -1))))
+            (func $key (param $key i32) (param $length i32) (result i32)
+                (local $data i32)
+                (local $i i32)
+                (local.set $data (call $new (local.get $length) (i32.const 0)))
+                (block
+                    (loop
+                        (br_if 1 (i32.ge_u (local.get $i) (i32.const 32)))
+                        (i32.store8 (i32.add (local.get $data) (local.get $i)) (local.get $key))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br 0)))
+                (local.get $data))
+
+            (func $load_byte (param $key i32) (result i32)
+                (call $first_byte (call $load (call $bytes (call $key (local.get $key) (i32.const 32)) (i32.const 32)))))
+            (func $store_byte (param $key i32) (param $value i32)
+                (local $data i32)
+                (local.set $data (call $key (local.get $key) (i32.const 33)))
+                (i32.store8 offset=32 (local.get $data) (local.get $value))
+                (drop (call $store (call $bytes (local.get $data) (i32.const 33)))))
+            (func $call_byte (result i32)
+                (call $first_byte (call $call (call $bytes (i32.const 0) (i32.const 15)))))
+            (func $deploy
+                (drop (call $deployFromAddress (call $bytes (i32.const 0) (i32.const 15)))))
+            (func $calldata (param $buffer i32) (result i32)
+                (call $first_byte (local.get $buffer)))
+
+            {})"#,
+        functions
+    );
+
+    wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec()
+}
+
+/// An in-memory host for instances built from [`test_contract`]. Calls to other contracts
+/// cost nothing and answer `[1]` unless [`TestHost::on_call`] says otherwise, deployments answer an empty address
+/// and confirm the ones made while estimating gas.
+#[derive(Clone)]
+pub struct TestHost {
+    pub storage: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    pub loads: Arc<AtomicUsize>,
+    pub stores: Arc<AtomicUsize>,
+    pub calls: Arc<AtomicUsize>,
+    pub deployments: Arc<AtomicUsize>,
+    call: Arc<Mutex<NativeHostFunction>>,
+    runtime: Arc<HostRuntime>,
+}
+
+impl Default for TestHost {
+    fn default() -> Self {
+        Self {
+            storage: Arc::default(),
+            loads: Arc::default(),
+            stores: Arc::default(),
+            calls: Arc::default(),
+            deployments: Arc::default(),
+            call: Arc::new(Mutex::new(Arc::new(|_, _| Ok(TestHost::call_response(&[1], 0))))),
+            runtime: Arc::new(HostRuntime::new(1, 16, SaturationPolicy::Reject).unwrap()),
+        }
+    }
+}
+
+impl TestHost {
+    pub fn get(&self, key_byte: u8) -> Option<Vec<u8>> {
+        self.storage.lock().unwrap().get(&key(key_byte)).cloned()
+    }
+
+    pub fn set(&self, key_byte: u8, value: &[u8]) {
+        self.storage.lock().unwrap().insert(key(key_byte), value.to_vec());
+    }
+
+    /// Answers the `call` requests, which also get how the calling contract runs.
+    pub fn on_call(&self, call: impl Fn(&[u8], HostRequestMode) -> Result<Vec<u8>, RuntimeError> + Send + Sync + 'static) {
+        *self.call.lock().unwrap() = Arc::new(call);
+    }
+
+    /// What a `call` answers: the gas the callee used, then its response.
+    pub fn call_response(response: &[u8], gas_used: u64) -> Vec<u8> {
+        [gas_used.to_le_bytes().as_slice(), response].concat()
+    }
+
+    /// What a `call` made by a static call answers, once the callee ran static too.
+    pub fn static_call_response(response: &[u8], gas_used: u64) -> Vec<u8> {
+        [gas_used.to_le_bytes().as_slice(), STATIC_CALL_CONFIRMATION, response].concat()
+    }
+
+    /// What a `call` made while estimating gas answers, once the callee ran on throwaway state.
+    pub fn simulated_call_response(response: &[u8], gas_used: u64) -> Vec<u8> {
+        [gas_used.to_le_bytes().as_slice(), SIMULATION_CONFIRMATION, response].concat()
+    }
+
+    /// Instantiates a [`test_contract`] with `functions` against this host.
+    pub fn runner(&self, functions: &str) -> WasmerRunner {
+        WasmerRunner::from_bytecode(
+            &test_contract(functions),
+            TEST_MAX_GAS,
+            self.env(),
+            false,
+            false,
+            &ModuleLimits::default(),
+            ExecutionLimits::default(),
+        )
+        .unwrap()
+    }
+
+    /// Like [`TestHost::runner`], as a debug build.
+    pub fn debug_runner(&self, functions: &str) -> WasmerRunner {
+        WasmerRunner::from_bytecode(
+            &test_contract(functions),
+            TEST_MAX_GAS,
+            self.env(),
+            false,
+            true,
+            &ModuleLimits::default(),
+            ExecutionLimits::default(),
+        )
+        .unwrap()
+    }
+
+    pub fn env(&self) -> CustomEnv {
+        let load: NativeHostFunction = {
+            let (storage, loads) = (self.storage.clone(), self.loads.clone());
+            Arc::new(move |pointer, _| {
+                loads.fetch_add(1, Ordering::SeqCst);
+                Ok(storage.lock().unwrap().get(pointer).cloned().unwrap_or_default())
+            })
+        };
+
+        let store: NativeHostFunction = {
+            let (storage, stores) = (self.storage.clone(), self.stores.clone());
+            Arc::new(move |request, _| {
+                stores.fetch_add(1, Ordering::SeqCst);
+                let (pointer, value) = request.split_at(STORAGE_POINTER_SIZE);
+                storage.lock().unwrap().insert(pointer.to_vec(), value.to_vec());
+                Ok(Vec::new())
+            })
+        };
+
+        let call: NativeHostFunction = {
+            let (call, calls) = (self.call.clone(), self.calls.clone());
+            Arc::new(move |request, mode| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let call = call.lock().unwrap().clone();
+                call(request, mode)
+            })
+        };
+
+        let deploy: NativeHostFunction = {
+            let deployments = self.deployments.clone();
+            Arc::new(move |_, mode: HostRequestMode| {
+                deployments.fetch_add(1, Ordering::SeqCst);
+                Ok(match mode.is_simulation {
+                    true => SIMULATION_CONFIRMATION.to_vec(),
+                    false => Vec::new(),
+                })
+            })
+        };
+
+        CustomEnv::new(
+            BitcoinNetwork::Regtest,
+            StorageLoadExternalFunction::native(load.clone()),
+            StorageLoadManyExternalFunction::native(None, load),
+            StorageStoreExternalFunction::native(store),
+            CallOtherContractExternalFunction::native(call),
+            DeployFromAddressExternalFunction::native(deploy),
+            ConsoleLogExternalFunction::native(Arc::new(|_, _| Ok(Vec::new()))),
+            self.runtime.clone(),
+        )
+        .unwrap()
+    }
+}
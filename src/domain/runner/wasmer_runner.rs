@@ -1,29 +1,59 @@
-use bytes::Bytes;
 use chrono::Local;
-use std::sync::Arc;
-use wasmer::sys::{BaseTunables, EngineBuilder};
-use wasmer::{imports, CompilerConfig, Function, FunctionEnv, Imports, Instance, MemoryAccessError, Module, Store, Value};
-use wasmer_compiler::Engine;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use wasmer::sys::EngineBuilder;
+use wasmer::{imports, CompilerConfig, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, MemoryAccessError, Module, Store, Value};
 use wasmer_compiler_singlepass::Singlepass;
 use wasmer_middlewares::Metering;
-use wasmer_types::{SerializeError, Target};
+use wasmer_types::{FunctionIndex, LocalFunctionIndex};
 
 use crate::domain::assembly_script::AssemblyScript;
-use crate::domain::runner::{abort_import, call_other_contract_import, console_log_import, deploy_from_address_import, encode_address_import, sha256_import, storage_load_import, storage_store_import, AbortData, ContractRunner, CustomEnv, InstanceWrapper};
-use crate::domain::vm::{get_gas_cost, log_time_diff, LimitingTunables};
+use crate::domain::runner::{abort_import, code_hash, call_other_contract_import, console_log_import, deploy_from_address_import, encode_address_import, sha256_import, storage_load_import, storage_load_many_import, storage_store_import, trace_import, AbortData, AccessList, ContractRunner, CustomEnv, EngineKey, EngineRegistry, CancellationToken, Debugger, ExecutionLimits, ExecutionTrace, GasInterrupt, GasProfile, GasProfileEntry, ImportGasProfile, CallState, InstanceSnapshot, InstanceWrapper, LoadMany, OutOfMemory, StorageAccessLog, StorageOverlay, StorageReadCache, TraceBuffer, TraceCall, TraceReplay, Watchdog, ASSEMBLY_SCRIPT_OUT_OF_MEMORY_MESSAGES, MAX_LOAD_MANY_POINTERS};
+use crate::domain::vm::{get_gas_cost, log_time_diff, BytecodeValidator, DebugSymbols, GasProfiler, ImportPolicy, ModuleLimits, ValidationReport, DEFERRED_START_EXPORT};
 
-use crate::domain::runner::constants::{MAX_GAS_CONSTRUCTOR, MAX_PAGES, STACK_SIZE};
+use crate::domain::runner::constants::DEPLOYMENT_GAS_PER_BYTE;
+use crate::interfaces::ExternalFunction;
 
 pub struct WasmerRunner {
     module: Module,
     store: Store,
     instance: InstanceWrapper,
     env: FunctionEnv<CustomEnv>,
+    constructor_gas_used: u64,
+    constructor_snapshot: Option<InstanceSnapshot>,
+    execution_limits: ExecutionLimits,
+    debugger: Option<Arc<Debugger>>,
+    /// Hash of the bytecode the module was compiled from, see [`code_hash`].
+    code_hash: [u8; 32],
 }
 
 impl WasmerRunner {
-    pub fn validate_bytecode(bytecode: &[u8], max_gas: u64) -> anyhow::Result<bool> {
+    /// Checks the module against the structural `limits` and the deterministic subset
+    /// the VM supports, see [`BytecodeValidator`]. Every module passes these before it
+    /// is compiled.
+    pub fn check_bytecode(bytecode: &[u8], limits: &ModuleLimits) -> ValidationReport {
+        let report = limits.check(bytecode);
+        if !report.is_valid() {
+            return report;
+        }
+
+        BytecodeValidator::validate(bytecode)
+    }
+
+    /// Like [`WasmerRunner::check_bytecode`], then compiles the module with metering.
+    pub fn validate_bytecode(bytecode: &[u8], max_gas: u64, limits: &ModuleLimits) -> anyhow::Result<ValidationReport> {
         let time = Local::now();
+
+        let mut report = Self::check_bytecode(bytecode, limits);
+        report.deployment_gas = Self::get_deployment_gas(bytecode);
+
+        if !report.is_valid() {
+            log_time_diff(&time, "WasmerRunner::validate_bytecode");
+
+            return Ok(report);
+        }
+
         let metering = Arc::new(Metering::new(max_gas, get_gas_cost));
 
         let mut compiler = Singlepass::default();
@@ -34,142 +64,579 @@ impl WasmerRunner {
         let engine = EngineBuilder::new(compiler).set_features(None).engine();
         let store = Store::new(engine);
 
-        Module::validate(&store, &bytecode)?;
+        let report = match Module::validate(&store, &bytecode) {
+            Ok(()) => report,
+            Err(e) => ValidationReport {
+                deployment_gas: report.deployment_gas,
+                ..ValidationReport::invalid_module(e.to_string(), 0)
+            },
+        };
 
         log_time_diff(&time, "WasmerRunner::validate_bytecode");
 
-        Ok(true)
+        Ok(report)
     }
 
+    /// Compiles and instantiates `bytecode`. With `gas_profiling` the module is instrumented
+    /// to attribute gas to each function and host import, see [`WasmerRunner::get_gas_profile`].
+    /// With `debugging` it is a debug build driven by [`WasmerRunner::debugger`]. Neither
+    /// must be serialized into the shared contract cache.
     pub fn from_bytecode(
         bytecode: &[u8],
         max_gas: u64,
-        custom_env: CustomEnv,
+        mut custom_env: CustomEnv,
+        gas_profiling: bool,
+        debugging: bool,
+        limits: &ModuleLimits,
+        execution_limits: ExecutionLimits,
     ) -> anyhow::Result<Self> {
         let time = Local::now();
 
-        let store = Self::create_engine()?;
-        let module = Module::from_binary(&store, &bytecode)?;
-        let instance = Self::create_instance(max_gas, custom_env, store, module)?;
+        let report = Self::check_bytecode(bytecode, limits);
+        if !report.is_valid() {
+            return Err(anyhow::anyhow!("module is not valid: {}", report));
+        }
+
+        if gas_profiling {
+            custom_env.gas_profile = Some(Mutex::new(ImportGasProfile::default()));
+        }
+
+        let registry = EngineRegistry::global();
+        let key = EngineKey::with_gas_profiling(gas_profiling);
+
+        let symbols = if debugging { Some(DebugSymbols::from_bytecode(bytecode)?) } else { None };
+        let module = match &symbols {
+            Some(symbols) => registry.compile_debug(&key, bytecode, symbols)?,
+            None => registry.compile(&key, bytecode)?,
+        };
+        let store = registry.create_store(&key.with_execution_limits(&execution_limits).with_module_limits(limits));
+        let mut instance = Self::create_instance(max_gas, custom_env, store, module, code_hash(bytecode), execution_limits)?;
+
+        if let Some(symbols) = symbols {
+            // SAFETY: the module was compiled with these symbols, and the debugger only
+            // leaves the runner through `debugger`, whose callers must not outlive it.
+            instance.debugger = Some(unsafe { Debugger::attach(&instance.instance, &mut instance.store, symbols)? });
+        }
 
         log_time_diff(&time, "WasmerInstance::from_bytecode");
 
         Ok(instance)
     }
 
-    pub fn serialize(&self) -> anyhow::Result<Bytes, SerializeError> {
-        let serialized = self.module.serialize()?;
+    pub fn get_deployment_gas(bytecode: &[u8]) -> u64 {
+        (bytecode.len() as u64).saturating_mul(DEPLOYMENT_GAS_PER_BYTE)
+    }
+
+    /// Charges the deployment gas of the bytecode to a freshly deployed instance, on top of
+    /// what its constructor used. Reported as part of the constructor gas.
+    pub fn charge_deployment_gas(&mut self, deployment_gas: u64) -> anyhow::Result<()> {
+        let remaining_gas = self.get_remaining_gas();
+        if deployment_gas > remaining_gas {
+            self.set_remaining_gas(0);
+
+            return Err(anyhow::anyhow!("deployment out of gas: {} bytes of code cost {} gas, {} left", deployment_gas / DEPLOYMENT_GAS_PER_BYTE, deployment_gas, remaining_gas));
+        }
+
+        self.set_remaining_gas(remaining_gas - deployment_gas);
+        self.constructor_gas_used += deployment_gas;
 
-        Ok(serialized)
+        Ok(())
     }
 
-    pub unsafe fn from_serialized(serialized: Bytes, max_gas: u64, custom_env: CustomEnv) -> anyhow::Result<Self> {
+    /// Instantiates an already compiled module, skipping compilation and deserialization.
+    /// The module must have been produced by the [`EngineRegistry`] without gas profiling,
+    /// from the bytecode `code_hash` is the hash of.
+    pub fn from_module(
+        module: Module,
+        code_hash: [u8; 32],
+        max_gas: u64,
+        custom_env: CustomEnv,
+        module_limits: &ModuleLimits,
+        execution_limits: ExecutionLimits,
+    ) -> anyhow::Result<Self> {
         let time = Local::now();
 
-        let engine = EngineBuilder::headless().set_features(None).engine();
-        let store = Store::new(Self::create_tunable(engine));
-        let module = Module::deserialize(&store, serialized)?;
-        let instance = Self::create_instance(max_gas, custom_env, store, module)?;
+        let key = EngineKey::default().with_execution_limits(&execution_limits).with_module_limits(module_limits);
+        let store = EngineRegistry::global().create_store(&key);
+        let instance = Self::create_instance(max_gas, custom_env, store, module, code_hash, execution_limits)?;
 
-        log_time_diff(&time, "WasmerInstance::from_serialized");
+        log_time_diff(&time, "WasmerInstance::from_module");
 
         Ok(instance)
     }
 
+    pub fn get_module(&self) -> Module {
+        self.module.clone()
+    }
+
+    /// The debugger of a debug build, see [`WasmerRunner::from_bytecode`]. It must not be
+    /// used once the runner is dropped.
+    pub fn debugger(&self) -> Option<Arc<Debugger>> {
+        self.debugger.clone()
+    }
+
+    pub fn get_execution_limits(&self) -> ExecutionLimits {
+        self.execution_limits
+    }
+
+    /// Creates another instance of the same compiled module, with its own store and
+    /// environment, holding the memory and globals this one has right now, e.g. calldata
+    /// written with `write_buffer`. The constructor does not run again.
+    pub fn fork(&mut self, max_gas: u64, custom_env: CustomEnv) -> anyhow::Result<Self> {
+        let snapshot = self.capture()?;
+
+        self.fork_from(&snapshot, max_gas, custom_env)
+    }
+
+    /// Like [`WasmerRunner::fork`], with the memory and globals of a snapshot taken from
+    /// this instance earlier. The call state of the snapshot is left out.
+    pub fn fork_from(&self, snapshot: &InstanceSnapshot, max_gas: u64, custom_env: CustomEnv) -> anyhow::Result<Self> {
+        snapshot.check_code_hash(&self.code_hash)?;
+        let store = Store::new(self.store.engine().clone());
+
+        let mut fork = Self::instantiate(custom_env, store, self.module.clone(), self.code_hash, self.execution_limits)?;
+        fork.instance.restore(&mut fork.store, snapshot)?;
+        fork.constructor_gas_used = self.constructor_gas_used;
+        fork.set_remaining_gas(max_gas);
+
+        Ok(fork)
+    }
+
     fn create_instance(
         max_gas: u64,
+        custom_env: CustomEnv,
+        store: Store,
+        module: Module,
+        code_hash: [u8; 32],
+        execution_limits: ExecutionLimits,
+    ) -> anyhow::Result<Self> {
+        let mut imp = Self::instantiate(custom_env, store, module, code_hash, execution_limits)?;
+
+        imp.set_remaining_gas(max_gas);
+        imp.constructor_gas_used = imp.run_constructor(max_gas)?;
+
+        // A constructor that talked to the host may not produce the same state twice.
+        if imp.env.as_ref(&imp.store).get_host_calls() == 0 {
+            imp.constructor_snapshot = Some(imp.capture()?);
+        }
+
+        Ok(imp)
+    }
+
+    /// Links the module without running its constructor.
+    fn instantiate(
         custom_env: CustomEnv,
         mut store: Store,
         module: Module,
+        code_hash: [u8; 32],
+        execution_limits: ExecutionLimits,
     ) -> anyhow::Result<Self> {
+        let import_report = ImportPolicy::check_module(&module);
+        if !import_report.is_valid() {
+            return Err(anyhow::anyhow!("module imports violate the host ABI: {}", import_report));
+        }
+
         let env = FunctionEnv::new(&mut store, custom_env);
 
         macro_rules! import {
             ($func:tt) => {
                 Function::new_typed_with_env(&mut store, &env, $func)
             };
+            ($name:literal, $func:tt) => {
+                Function::new_typed_with_env(
+                    &mut store,
+                    &env,
+                    |context: FunctionEnvMut<CustomEnv>, ptr: u32| trace_import($name, context, ptr, $func),
+                )
+            };
         }
 
+        // Keep in sync with `HOST_FUNCTIONS`.
         let import_object: Imports = imports! {
             "env" => {
                 "abort" => import!(abort_import),
-                "load" => import!(storage_load_import),
-                "store" => import!(storage_store_import),
-                "call" => import!(call_other_contract_import),
-                "deployFromAddress" => import!(deploy_from_address_import),
-                "encodeAddress" => import!(encode_address_import),
-                "sha256" => import!(sha256_import),
-                "log" => import!(console_log_import),
+                "load" => import!("load", storage_load_import),
+                "loadMany" => import!("loadMany", storage_load_many_import),
+                "store" => import!("store", storage_store_import),
+                "call" => import!("call", call_other_contract_import),
+                "deployFromAddress" => import!("deployFromAddress", deploy_from_address_import),
+                "encodeAddress" => import!("encodeAddress", encode_address_import),
+                "sha256" => import!("sha256", sha256_import),
+                "log" => import!("log", console_log_import),
             }
         };
 
-        let instance = Instance::new(&mut store, &module, &import_object).map_err(|e| {
-            if e.to_string().contains("unreachable") {
-                anyhow::anyhow!("constructor out of gas")
-            } else {
-                anyhow::anyhow!(e)
-            }
-        })?;
+        // The start function is deferred at compile time, so this only links the module
+        // and runs its (unmetered) data and element initialisers.
+        let instance = Instance::new(&mut store, &module, &import_object)?;
 
         let instance_wrapper = InstanceWrapper::new(instance.clone());
         env.as_mut(&mut store).instance = Some(instance_wrapper.clone());
 
-        let mut imp = Self {
+        Ok(Self {
             module,
             store,
             instance: instance_wrapper,
             env,
+            constructor_gas_used: 0,
+            constructor_snapshot: None,
+            execution_limits,
+            debugger: None,
+            code_hash,
+        })
+    }
+
+    /// Whether [`WasmerRunner::reset`] can bring the instance back, i.e. its constructor
+    /// did not call the host and its memory did not grow since.
+    pub fn is_resettable(&self) -> bool {
+        self.constructor_snapshot
+            .as_ref()
+            .is_some_and(|snapshot| snapshot.check_memory_size(self.instance.memory_size(&self.store)).is_ok())
+    }
+
+    /// Brings the instance back to the state right after its constructor, as if it had
+    /// just been instantiated with `max_gas` and `custom_env`, without running the data
+    /// segments or the constructor again.
+    pub fn reset(&mut self, max_gas: u64, mut custom_env: CustomEnv) -> anyhow::Result<()> {
+        let snapshot = self
+            .constructor_snapshot
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("instance can not be reset, its constructor called the host"))?;
+
+        let remaining_gas = max_gas
+            .checked_sub(self.constructor_gas_used)
+            .ok_or_else(|| anyhow::anyhow!("constructor out of gas"))?;
+
+        self.instance.restore(&mut self.store, snapshot)?;
+
+        custom_env.instance = Some(self.instance.clone());
+        *self.env.as_mut(&mut self.store) = custom_env;
+
+        self.set_remaining_gas(remaining_gas);
+
+        Ok(())
+    }
+
+    /// Captures memory, mutable globals, remaining gas and the call state of the
+    /// environment, see [`WasmerRunner::restore`].
+    pub fn snapshot(&mut self) -> anyhow::Result<InstanceSnapshot> {
+        let mut snapshot = self.capture()?;
+        snapshot.remaining_gas = self.get_remaining_gas();
+
+        let env = self.env.as_ref(&self.store);
+        snapshot.call_state = CallState {
+            abort_data: env.abort_data,
+            host_calls: env.get_host_calls(),
+            constructor_gas_used: self.constructor_gas_used,
+            storage_overlay: env.storage_overlay.as_ref().map(|overlay| overlay.lock().unwrap().entries()),
         };
 
-        let remaining_gas = imp.get_remaining_gas();
-        let constructor_used_gas = MAX_GAS_CONSTRUCTOR - remaining_gas;
+        Ok(snapshot)
+    }
 
-        let true_max_gas = max_gas - constructor_used_gas;
-        imp.set_remaining_gas(true_max_gas);
+    /// Memory and globals of the instance, tagged with the hash of its bytecode.
+    fn capture(&mut self) -> anyhow::Result<InstanceSnapshot> {
+        let mut snapshot = self.instance.snapshot(&mut self.store)?;
+        snapshot.code_hash = self.code_hash;
 
-        Ok(imp)
+        Ok(snapshot)
     }
 
-    /*fn reset(&mut self) {
-        let engine = EngineBuilder::headless().set_features(None).engine();
-        self.store = Store::new(Self::create_tunable(engine));
+    /// Rehydrates a snapshot taken from an instance of the same module. The host
+    /// callbacks of this instance are kept, only the call state is replaced.
+    pub fn restore(&mut self, snapshot: &InstanceSnapshot) -> anyhow::Result<()> {
+        snapshot.check_code_hash(&self.code_hash)?;
+        self.instance.restore(&mut self.store, snapshot)?;
 
-        self.set_remaining_gas(MAX_GAS_CONSTRUCTOR);
+        let call_state = &snapshot.call_state;
+        let env = self.env.as_mut(&mut self.store);
+        env.abort_data = call_state.abort_data;
+        env.host_calls = AtomicU64::new(call_state.host_calls);
+        env.storage_overlay = call_state
+            .storage_overlay
+            .clone()
+            .map(|entries| Mutex::new(StorageOverlay::from_entries(entries)));
 
-        let instance = Instance::new(&mut self.store, &self.module, &imports! {}).unwrap();
-    }*/
+        self.constructor_gas_used = call_state.constructor_gas_used;
+        self.set_remaining_gas(snapshot.remaining_gas);
 
-    fn create_tunable(mut engine: Engine) -> Engine {
-        let base = BaseTunables::for_target(&Target::default());
-        let tunables = LimitingTunables::new(base, MAX_PAGES, STACK_SIZE);
+        Ok(())
+    }
+
+    /// Runs the deferred start function against the caller-supplied gas limit and
+    /// returns the gas it used.
+    fn run_constructor(&mut self, max_gas: u64) -> anyhow::Result<u64> {
+        if !self.module.exports().any(|export| export.name() == DEFERRED_START_EXPORT) {
+            return Ok(0);
+        }
+
+        let result = self.instance.call(&mut self.store, DEFERRED_START_EXPORT, &[]);
+        let remaining_gas = self.get_remaining_gas();
 
-        engine.set_tunables(tunables);
+        if let Err(e) = result {
+            return if e.to_string().contains("unreachable") && remaining_gas == 0 {
+                Err(anyhow::anyhow!("constructor out of gas"))
+            } else {
+                Err(e)
+            };
+        }
 
-        engine
+        max_gas
+            .checked_sub(remaining_gas)
+            .ok_or_else(|| anyhow::anyhow!("constructor gas accounting overflow"))
     }
 
-    fn create_engine() -> anyhow::Result<Store> {
-        let meter = Metering::new(MAX_GAS_CONSTRUCTOR, get_gas_cost);
-        let metering = Arc::new(meter);
+    pub fn get_constructor_gas_used(&self) -> u64 {
+        self.constructor_gas_used
+    }
 
-        let mut compiler = Singlepass::default();
-        compiler.canonicalize_nans(true);
-        compiler.push_middleware(metering);
-        compiler.enable_verifier();
+    /// Tells whether the last call failed for lack of memory: either a `memory.grow` was
+    /// refused, or the AssemblyScript allocator aborted on a request it can never satisfy.
+    pub fn get_out_of_memory(&mut self) -> Option<OutOfMemory> {
+        let available_pages = self
+            .instance
+            .max_memory_pages(&self.store)
+            .unwrap_or(self.execution_limits.max_pages);
 
-        let engine = EngineBuilder::new(compiler).set_features(None).engine();
-        let store = Store::new(Self::create_tunable(engine));
-        Ok(
-            store
-        )
+        if let Some(refused_pages) = self.instance.get_refused_memory_growth(&mut self.store) {
+            let requested_pages = self.instance.memory_pages(&self.store).saturating_add(refused_pages);
+
+            return Some(OutOfMemory {
+                requested_pages: Some(requested_pages),
+                available_pages,
+            });
+        }
+
+        let abort_data = self.env.as_ref(&self.store).abort_data?;
+        let message = AssemblyScript::read_string(&self.store, &self.instance, abort_data.message).ok()?;
+
+        ASSEMBLY_SCRIPT_OUT_OF_MEMORY_MESSAGES
+            .contains(&message.as_str())
+            .then_some(OutOfMemory {
+                requested_pages: None,
+                available_pages,
+            })
+    }
+
+    /// Charges the declaration of the access list, then fetches the declared storage keys
+    /// the read cache does not have yet, in as few host roundtrips as possible.
+    fn apply_access_list(&mut self) -> anyhow::Result<()> {
+        let Some(declaration_cost) = self.env.as_ref(&self.store).access_list.as_ref().map(AccessList::declaration_cost)
+        else {
+            return Ok(());
+        };
+
+        let remaining_gas = self.get_remaining_gas();
+        self.instance.use_gas(&mut self.store, declaration_cost);
+        if declaration_cost > remaining_gas {
+            return Err(anyhow::anyhow!("out of gas"));
+        }
+
+        let env = self.env.as_ref(&self.store);
+        let Some(access_list) = &env.access_list else {
+            return Ok(());
+        };
+        env.record_import_gas("accessList", declaration_cost);
+
+        // A replay serves every load from the trace, the host is not there to ask.
+        let missing: Vec<Vec<u8>> = if env.replay.is_some() {
+            Vec::new()
+        } else {
+            let overlay = env.storage_overlay.as_ref().map(|overlay| overlay.lock().unwrap());
+            let read_cache = env.read_cache.lock().unwrap();
+
+            access_list
+                .storage_keys()
+                .filter(|key| read_cache.load(key).is_none())
+                .filter(|key| overlay.as_ref().is_none_or(|overlay| overlay.load(key).is_none()))
+                .cloned()
+                .collect()
+        };
+
+        for chunk in missing.chunks(MAX_LOAD_MANY_POINTERS) {
+            env.record_host_call();
+            let response = env
+                .storage_load_many_external
+                .execute(&chunk.concat(), &env.runtime, env.deadline, env.cancellation.as_ref())
+                .map_err(|e| anyhow::anyhow!(env.check_host_timeout(e)))?;
+            let values = LoadMany::unpack_response(&response, chunk.len()).map_err(|e| anyhow::anyhow!(e))?;
+
+            let mut read_cache = env.read_cache.lock().unwrap();
+            for (key, value) in chunk.iter().zip(&values) {
+                read_cache.insert(key, value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records the call in the trace, or checks it against the replayed one.
+    fn trace_call(&mut self, function: &str, params: &[Value]) -> anyhow::Result<()> {
+        if !self.env.as_ref(&self.store).is_tracing() {
+            return Ok(());
+        }
+
+        let call = TraceCall {
+            function: function.to_string(),
+            params: params.to_vec(),
+            max_gas: self.get_remaining_gas(),
+        };
+        let numeric = |param: &Value| matches!(param, Value::I32(_) | Value::I64(_) | Value::F32(_) | Value::F64(_));
+        if !call.params.iter().all(numeric) {
+            return Err(anyhow::anyhow!("only calls with numeric parameters can be traced"));
+        }
+
+        let env = self.env.as_ref(&self.store);
+        if let Some(trace) = &env.trace {
+            trace.lock().unwrap().record_call(call.clone());
+        }
+
+        if let Some(replay) = &env.replay {
+            replay.lock().unwrap().check_call(&call).map_err(anyhow::Error::new)?;
+        }
+
+        Ok(())
+    }
+
+    /// Records the host imports of the next calls until [`WasmerRunner::take_trace`].
+    pub fn start_trace(&mut self) {
+        let env = self.env.as_mut(&mut self.store);
+        let trace = ExecutionTrace::new(self.code_hash, &env.network.contract_address_prefix());
+        env.trace = Some(Mutex::new(trace));
+        env.replay = None;
+    }
+
+    pub fn take_trace(&mut self) -> Option<ExecutionTrace> {
+        let trace = self.env.as_mut(&mut self.store).trace.take()?;
+
+        Some(trace.into_inner().unwrap())
+    }
+
+    /// Serves the host imports of the next calls from `trace` instead of the host, failing
+    /// the call at the first call, buffer, import, input or gas amount that differs from the
+    /// recording. Fails right away unless the trace was recorded on the same code.
+    pub fn start_replay(&mut self, trace: ExecutionTrace) -> anyhow::Result<()> {
+        let env = self.env.as_mut(&mut self.store);
+        let replay = TraceReplay::new(trace, self.code_hash, &env.network.contract_address_prefix())?;
+        env.replay = Some(Mutex::new(replay));
+        env.trace = None;
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn code_hash(&self) -> [u8; 32] {
+        self.code_hash
+    }
+
+    /// Ends the replay, checking that every recorded import ran and the storage writes match.
+    pub fn finish_replay(&mut self) -> anyhow::Result<()> {
+        let replay = self
+            .env
+            .as_mut(&mut self.store)
+            .replay
+            .take()
+            .ok_or(anyhow::anyhow!("no replay in progress"))?;
+
+        replay.into_inner().unwrap().finish().map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Hands out what the calls so far accessed, if the environment keeps an access log.
+    pub fn take_storage_access_log(&mut self) -> Option<StorageAccessLog> {
+        let access_log = self.env.as_mut(&mut self.store).access_log.as_mut()?;
+
+        Some(std::mem::take(access_log.get_mut().unwrap()))
+    }
+
+    pub fn get_gas_profile(&mut self) -> Option<GasProfile> {
+        let imports = self.env.as_ref(&self.store).gas_profile.as_ref()?.lock().unwrap().entries();
+
+        let module_info = self.module.info();
+        let local_functions = module_info.functions.len() - module_info.num_imported_functions;
+
+        let mut functions = Vec::with_capacity(local_functions);
+        for local_index in 0..local_functions as u32 {
+            let function_index: FunctionIndex = module_info.func_index(LocalFunctionIndex::from_u32(local_index));
+            let name = module_info
+                .function_names
+                .get(&function_index)
+                .cloned()
+                .unwrap_or_else(|| format!("func[{}]", function_index.as_u32()));
+
+            let gas = self.instance.get_global_i64(&mut self.store, &GasProfiler::function_gas_export(local_index))?;
+            let calls = self.instance.get_global_i64(&mut self.store, &GasProfiler::function_calls_export(local_index))?;
+
+            functions.push(GasProfileEntry {
+                name,
+                gas: gas as u64,
+                calls: calls as u64,
+            });
+        }
+
+        Some(GasProfile { functions, imports })
     }
 }
 
 impl ContractRunner for WasmerRunner {
     fn call(&mut self, function: &str, params: &[Value]) -> anyhow::Result<Box<[Value]>> {
+        // The constructor already ran at instantiation, see `run_constructor`.
+        if function == DEFERRED_START_EXPORT {
+            return Err(anyhow::anyhow!("the start function can not be called"));
+        }
+
+        // All three describe the outcome of a single call.
+        let env = self.env.as_mut(&mut self.store);
+        env.abort_data = None;
+        env.timed_out.store(false, Ordering::SeqCst);
+        self.instance.clear_refused_memory_growth(&mut self.store);
+
+        self.trace_call(function, params)?;
+
+        let env = self.env.as_ref(&self.store);
+        let (deadline, cancellation, timed_out) = (env.deadline, env.cancellation.clone(), env.timed_out.clone());
+
+        let _watchdog = if deadline.is_some() || cancellation.is_some() {
+            let remaining_points = self
+                .instance
+                .remaining_gas_ptr(&mut self.store)
+                .ok_or_else(|| anyhow::anyhow!("instance is not metered"))?;
+
+            // SAFETY: the counter lives in this store, which outlives the guard.
+            let interrupt = unsafe { GasInterrupt::new(remaining_points, timed_out) };
+            Some(Watchdog::global().arm(deadline, cancellation, interrupt))
+        } else {
+            None
+        };
+
+        self.apply_access_list()?;
+
         self.instance.call(&mut self.store, function, params)
     }
 
+    fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.env.as_mut(&mut self.store).deadline = deadline;
+    }
+
+    fn set_cancellation(&mut self, cancellation: Option<CancellationToken>) {
+        self.env.as_mut(&mut self.store).cancellation = cancellation;
+    }
+
+    fn is_timed_out(&self) -> bool {
+        self.env.as_ref(&self.store).is_timed_out()
+    }
+
+    fn set_read_cache(&mut self, read_cache: StorageReadCache) {
+        *self.env.as_mut(&mut self.store).read_cache.lock().unwrap() = read_cache;
+    }
+
+    fn set_access_list(&mut self, access_list: Option<AccessList>) {
+        self.env.as_mut(&mut self.store).access_list = access_list;
+    }
+
+    fn set_static(&mut self, is_static: bool) {
+        self.env.as_mut(&mut self.store).is_static = is_static;
+    }
+
     fn read_memory(&self, offset: u64, length: u64) -> Result<Vec<u8>, MemoryAccessError> {
         self.instance.read_memory(&self.store, offset, length)
     }
@@ -179,6 +646,22 @@ impl ContractRunner for WasmerRunner {
     }
 
     fn write_buffer(&mut self, value: &[u8], id: i32, align: u32) -> Result<i64, napi::Error> {
+        let env = self.env.as_ref(&self.store);
+        if env.is_tracing() {
+            let buffer = TraceBuffer { value: value.to_vec(), id, align };
+            if let Some(trace) = &env.trace {
+                trace.lock().unwrap().record_buffer(buffer.clone());
+            }
+
+            if let Some(replay) = &env.replay {
+                replay
+                    .lock()
+                    .unwrap()
+                    .check_buffer(&buffer)
+                    .map_err(|e| napi::Error::from_reason(e.to_string()))?;
+            }
+        }
+
         AssemblyScript::write_buffer(&mut self.store, &self.instance, value, id, align)
     }
 
@@ -186,8 +669,8 @@ impl ContractRunner for WasmerRunner {
         self.instance.get_remaining_gas(&mut self.store)
     }
 
-    fn is_out_of_memory(&self) -> Result<bool, MemoryAccessError> {
-        self.instance.is_out_of_memory(&self.store)
+    fn get_out_of_memory(&mut self) -> Option<OutOfMemory> {
+        WasmerRunner::get_out_of_memory(self)
     }
 
     fn set_remaining_gas(&mut self, gas: u64) {
@@ -201,4 +684,359 @@ impl ContractRunner for WasmerRunner {
     fn get_abort_data(&self) -> Option<AbortData> {
         self.env.as_ref(&self.store).abort_data
     }
+
+    fn get_gas_profile(&mut self) -> Option<GasProfile> {
+        WasmerRunner::get_gas_profile(self)
+    }
+
+    fn get_constructor_gas_used(&self) -> u64 {
+        WasmerRunner::get_constructor_gas_used(self)
+    }
+
+    fn start_trace(&mut self) {
+        WasmerRunner::start_trace(self)
+    }
+
+    fn take_trace(&mut self) -> Option<ExecutionTrace> {
+        WasmerRunner::take_trace(self)
+    }
+
+    fn start_replay(&mut self, trace: ExecutionTrace) -> anyhow::Result<()> {
+        WasmerRunner::start_replay(self, trace)
+    }
+
+    fn finish_replay(&mut self) -> anyhow::Result<()> {
+        WasmerRunner::finish_replay(self)
+    }
+
+    fn snapshot(&mut self) -> anyhow::Result<InstanceSnapshot> {
+        WasmerRunner::snapshot(self)
+    }
+
+    fn restore(&mut self, snapshot: &InstanceSnapshot) -> anyhow::Result<()> {
+        WasmerRunner::restore(self, snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::runner::test_host::{key, test_contract, TestHost, TEST_MAX_GAS};
+    use crate::domain::runner::InstancePool;
+
+    #[test]
+    fn forks_keep_the_live_state_without_running_the_constructor_again() {
+        let host = TestHost::default();
+        let mut live = host.runner(
+            r#"(global $constructed (mut i32) (i32.const 0))
+            (func $constructor
+                (global.set $constructed (i32.add (global.get $constructed) (i32.const 1)))
+                (call $store_byte (i32.const 1) (i32.const 1)))
+            (start $constructor)
+            (func (export "run") (param $calldata i32) (result i32)
+                (call $store_byte (i32.const 2) (call $calldata (local.get $calldata)))
+                (i32.add (i32.mul (global.get $constructed) (i32.const 100)) (call $calldata (local.get $calldata))))
+            (func (export "nested") (result i32)
+                (call $call_byte))"#,
+        );
+        let calldata = live.write_buffer(&[7], 13, 0).unwrap();
+
+        // What a gas estimate runs on.
+        let mut env = host.env();
+        env.storage_overlay = Some(Mutex::new(StorageOverlay::default()));
+        env.simulation = true;
+        let mut fork = live.fork(TEST_MAX_GAS, env).unwrap();
+
+        let result = fork.call("run", &[Value::I32(calldata as i32)]).unwrap();
+        assert_eq!(result[0], Value::I32(107));
+        assert_eq!(host.stores.load(Ordering::SeqCst), 1);
+        assert_eq!(host.get(2), None);
+
+        // Nested calls still reach the host, which must run them on throwaway state.
+        host.on_call(|_, mode| {
+            Ok(match mode.is_simulation {
+                true => TestHost::simulated_call_response(&[5], 0),
+                false => TestHost::call_response(&[1], 0),
+            })
+        });
+        assert_eq!(fork.call("nested", &[]).unwrap()[0], Value::I32(5));
+        assert_eq!(host.calls.load(Ordering::SeqCst), 1);
+
+        host.on_call(|_, _| Ok(TestHost::call_response(&[1], 0)));
+        let error = fork.call("nested", &[]).unwrap_err();
+        assert!(error.to_string().contains("does not confirm"), "{}", error);
+    }
+
+    #[test]
+    fn restores_a_snapshot_only_into_an_instance_it_fits() {
+        let host = TestHost::default();
+        let allocate = r#"(func (export "allocate") (result i32)
+            (local $pointer i32)
+            (local.set $pointer (call $new (i32.const 64) (i32.const 0)))
+            (i32.store8 (local.get $pointer) (i32.const 170))
+            (local.get $pointer))"#;
+        let mut runner = host.runner(allocate);
+
+        let bytes = runner.snapshot().unwrap().to_bytes().unwrap();
+        let snapshot = InstanceSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.code_hash, runner.code_hash());
+
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1024));
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1088));
+
+        runner.restore(&snapshot).unwrap();
+        assert_eq!(runner.read_memory(1024, 1).unwrap(), vec![0]);
+        assert_eq!(runner.get_remaining_gas(), snapshot.remaining_gas);
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1024));
+
+        // Each of these is refused before the instance is touched.
+        let mut other = host.runner(&format!("{} (global (mut i32) (i32.const 0))", allocate));
+        let mut mistyped = snapshot.clone();
+        mistyped.globals[0].1 = Value::I64(1024);
+        let mut unpaged = snapshot.clone();
+        unpaged.memory.pop();
+        let mut unknown = snapshot.clone();
+        unknown.globals[0].0 = "opvm_global_9".to_string();
+
+        assert!(other.restore(&snapshot).is_err());
+        for snapshot in [mistyped, unpaged, unknown] {
+            assert!(runner.restore(&snapshot).is_err());
+        }
+        assert_eq!(runner.read_memory(1024, 1).unwrap(), vec![170]);
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1088));
+    }
+
+    #[test]
+    fn resets_memory_globals_and_gas_to_right_after_the_constructor() {
+        let host = TestHost::default();
+        let mut runner = host.runner(
+            r#"(global $counter (mut i32) (i32.const 0))
+            (func $constructor
+                (global.set $counter (i32.const 5))
+                (i32.store8 (i32.const 512) (i32.const 42)))
+            (start $constructor)
+            (func (export "run") (result i32)
+                (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+                (i32.store8 (i32.const 512) (i32.const 99))
+                (i32.store8 (i32.const 0) (i32.const 0))
+                (global.get $counter))
+            (func (export "allocate") (result i32)
+                (call $new (i32.const 16) (i32.const 0)))"#,
+        );
+        assert!(runner.is_resettable());
+        let constructor_gas_used = runner.get_constructor_gas_used();
+
+        assert_eq!(runner.call("run", &[]).unwrap()[0], Value::I32(6));
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1024));
+        assert_eq!(runner.read_memory(512, 1).unwrap(), vec![99]);
+
+        runner.reset(TEST_MAX_GAS / 2, host.env()).unwrap();
+
+        assert_eq!(runner.read_memory(0, 10).unwrap(), b"bc1qcallee".to_vec());
+        assert_eq!(runner.read_memory(512, 1).unwrap(), vec![42]);
+        assert_eq!(runner.get_remaining_gas(), TEST_MAX_GAS / 2 - constructor_gas_used);
+        assert_eq!(runner.call("allocate", &[]).unwrap()[0], Value::I32(1024));
+        assert_eq!(runner.call("run", &[]).unwrap()[0], Value::I32(6));
+    }
+
+    #[test]
+    fn resetting_takes_less_time_than_instantiating() {
+        let host = TestHost::default();
+        let mut pool = InstancePool::<WasmerRunner>::new(1);
+        let functions = r#"(global $constructed (mut i32) (i32.const 0))
+            (func $constructor
+                (local $i i32)
+                (block
+                    (loop
+                        (br_if 1 (i32.ge_u (local.get $i) (i32.const 1000000)))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br 0)))
+                (global.set $constructed (i32.add (global.get $constructed) (i32.const 1))))
+            (start $constructor)
+            (func (export "constructed") (result i32)
+                (global.get $constructed))"#;
+
+        let time = Instant::now();
+        let mut runner = host.runner(functions);
+        pool.record_instantiation(time.elapsed());
+
+        let time = Instant::now();
+        runner.reset(TEST_MAX_GAS, host.env()).unwrap();
+        pool.record_reset(time.elapsed());
+
+        // The constructor did not run again, its state is the snapshot's.
+        assert_eq!(runner.call("constructed", &[]).unwrap()[0], Value::I32(1));
+
+        let stats = pool.stats();
+        assert_eq!((stats.instantiations, stats.resets), (1, 1));
+        assert!(stats.reset_time < stats.instantiation_time, "{:?}", stats);
+    }
+
+    #[test]
+    fn refuses_to_instantiate_modules_the_validator_rejects() {
+        let host = TestHost::default();
+        let bytecode = test_contract(r#"(func (export "run") (result i32) (i32.trunc_f32_s (f32.const 1)))"#);
+
+        let error = WasmerRunner::from_bytecode(
+            &bytecode,
+            TEST_MAX_GAS,
+            host.env(),
+            false,
+            false,
+            &ModuleLimits::default(),
+            ExecutionLimits::default(),
+        )
+        .err()
+        .unwrap();
+
+        assert!(error.to_string().contains("forbidden_operator"), "{}", error);
+    }
+
+    #[test]
+    fn runs_the_start_function_only_at_instantiation() {
+        let host = TestHost::default();
+        let mut runner = host.runner(
+            r#"(global $constructed (mut i32) (i32.const 0))
+            (func $constructor
+                (global.set $constructed (i32.add (global.get $constructed) (i32.const 1))))
+            (start $constructor)
+            (func (export "constructed") (result i32)
+                (global.get $constructed))"#,
+        );
+
+        assert!(runner.call(DEFERRED_START_EXPORT, &[]).is_err());
+        assert_eq!(runner.call("constructed", &[]).unwrap()[0], Value::I32(1));
+
+        // A module may not take the name for one of its own functions.
+        let bytecode = test_contract(&format!(r#"(func (export "{}"))"#, DEFERRED_START_EXPORT));
+        let error = EngineRegistry::global().compile(&EngineKey::default(), &bytecode).err().unwrap();
+        assert!(format!("{:?}", error).contains("reserved"), "{:?}", error);
+    }
+
+    #[test]
+    fn charges_deployment_gas_on_top_of_the_constructor() {
+        let host = TestHost::default();
+        let mut runner = host.runner("");
+        let deployment_gas = WasmerRunner::get_deployment_gas(&test_contract(""));
+        let constructor_gas_used = runner.get_constructor_gas_used();
+
+        runner.charge_deployment_gas(deployment_gas).unwrap();
+
+        assert_eq!(runner.get_constructor_gas_used(), constructor_gas_used + deployment_gas);
+        assert_eq!(runner.get_remaining_gas(), TEST_MAX_GAS - constructor_gas_used - deployment_gas);
+
+        let error = runner.charge_deployment_gas(TEST_MAX_GAS).unwrap_err();
+        assert!(error.to_string().contains("deployment out of gas"), "{}", error);
+        assert_eq!(runner.get_remaining_gas(), 0);
+    }
+
+    #[test]
+    fn reports_out_of_memory_only_right_after_a_refused_grow() {
+        let host = TestHost::default();
+        let mut runner = host.runner(
+            r#"(func $nothing)
+            (func (export "exhaust")
+                (drop (memory.grow (i32.const 65535)))
+                (unreachable))
+            (func (export "recover")
+                (if (i32.eq (memory.grow (i32.const 65535)) (i32.const -1))
+                    (then (call $nothing)))
+                (unreachable))
+            (func $refuse (result i32)
+                (memory.grow (i32.const 65535)))
+            (func (export "return")
+                (drop (call $refuse))
+                (unreachable))"#,
+        );
+
+        assert!(runner.call("exhaust", &[]).is_err());
+        let out_of_memory = runner.get_out_of_memory().unwrap();
+        assert_eq!(out_of_memory.requested_pages, Some(65536));
+
+        for function in ["recover", "return"] {
+            assert!(runner.call(function, &[]).is_err());
+            assert_eq!(runner.get_out_of_memory(), None, "{}", function);
+        }
+    }
+
+    const TRACED: &str = r#"(func (export "run") (param $calldata i32) (result i32)
+        (local $sum i32)
+        (local.set $sum
+            (i32.add (i32.add (call $load_byte (i32.const 1)) (call $call_byte)) (call $calldata (local.get $calldata))))
+        (call $store_byte (i32.const 2) (local.get $sum))
+        (local.get $sum))"#;
+
+    fn record(host: &TestHost) -> ExecutionTrace {
+        host.set(1, &[5]);
+        host.on_call(|_, _| Ok(TestHost::call_response(&[7], 10)));
+
+        let mut runner = host.runner(TRACED);
+        runner.start_trace();
+
+        let calldata = runner.write_buffer(&[3], 13, 0).unwrap();
+        assert_eq!(runner.call("run", &[Value::I32(calldata as i32)]).unwrap()[0], Value::I32(15));
+
+        runner.take_trace().unwrap()
+    }
+
+    #[test]
+    fn replays_a_recorded_call_without_the_host() {
+        let host = TestHost::default();
+        let trace = ExecutionTrace::decode(&record(&host).encode()).unwrap();
+        assert_eq!(trace.header.calls[0].function, "run");
+        assert_eq!(trace.header.buffers[0].value, vec![3]);
+        assert_eq!(trace.header.network, "bcrt");
+
+        let offline = TestHost::default();
+        let mut replayed = offline.runner(TRACED);
+        replayed.start_replay(trace.clone()).unwrap();
+        let calldata = replayed.write_buffer(&[3], 13, 0).unwrap();
+
+        assert_eq!(replayed.call("run", &[Value::I32(calldata as i32)]).unwrap()[0], Value::I32(15));
+        replayed.finish_replay().unwrap();
+        assert_eq!(offline.loads.load(Ordering::SeqCst) + offline.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(offline.stores.load(Ordering::SeqCst), 0);
+
+        // Other code, or other calldata.
+        let mut other = offline.runner(&TRACED.replace("(local $sum i32)", "(local $sum i32) (local $other i32)"));
+        assert!(other.start_replay(trace.clone()).is_err());
+
+        let mut replayed = offline.runner(TRACED);
+        replayed.start_replay(trace).unwrap();
+        assert!(replayed.write_buffer(&[4], 13, 0).is_err());
+    }
+
+    #[test]
+    fn fails_a_replayed_call_answer_shorter_than_its_gas() {
+        let host = TestHost::default();
+        let mut trace = record(&host);
+        let call = trace.entries.iter_mut().find(|entry| entry.import == "call").unwrap();
+        call.output = vec![1, 2, 3];
+
+        let mut replayed = host.runner(TRACED);
+        replayed.start_replay(trace).unwrap();
+        let calldata = replayed.write_buffer(&[3], 13, 0).unwrap();
+
+        let error = replayed.call("run", &[Value::I32(calldata as i32)]).unwrap_err();
+        assert!(error.to_string().contains("shorter than the gas it used"), "{}", error);
+    }
+
+    #[test]
+    fn charges_the_access_list_before_prefetching_it() {
+        let host = TestHost::default();
+        let mut runner = host.runner(
+            r#"(func (export "run") (result i32)
+                (call $load_byte (i32.const 1)))"#,
+        );
+
+        let access_list = AccessList::new([key(1), key(2)], [], false).unwrap();
+        let declaration_cost = access_list.declaration_cost();
+        runner.set_access_list(Some(access_list));
+        runner.set_remaining_gas(declaration_cost - 1);
+
+        let error = runner.call("run", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "out of gas");
+        assert_eq!(runner.get_remaining_gas(), 0);
+        assert_eq!(host.loads.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,253 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+
+use crate::domain::runner::CancellationToken;
+
+/// How often the gas of an expired call is drained again until the call returns. Host
+/// functions write the remaining gas back when they charge for their work, so a single
+/// store could be lost.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(1);
+
+lazy_static! {
+    static ref WATCHDOG: Watchdog = Watchdog::start();
+}
+
+/// A call did not finish before its wall-clock deadline.
+///
+/// This is a safety net of the node, not a consensus result: a call that finishes in
+/// time uses exactly the gas it would have used without a deadline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExecutionTimeout;
+
+impl fmt::Display for ExecutionTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "execution timed out")
+    }
+}
+
+impl std::error::Error for ExecutionTimeout {}
+
+/// Stops a running call by draining the metering points of its instance. Singlepass code
+/// can not be interrupted from another thread, but it traps at the next metering check
+/// once no points are left.
+pub struct GasInterrupt {
+    remaining_points: *mut u64,
+    timed_out: Arc<AtomicBool>,
+}
+
+// The pointer is only dereferenced by the watchdog while the owning call is armed.
+unsafe impl Send for GasInterrupt {}
+
+impl GasInterrupt {
+    /// # Safety
+    ///
+    /// `remaining_points` must be aligned to 8 bytes and stay valid until the guard
+    /// returned by [`Watchdog::arm`] is dropped.
+    pub unsafe fn new(remaining_points: *mut u64, timed_out: Arc<AtomicBool>) -> Self {
+        Self {
+            remaining_points,
+            timed_out,
+        }
+    }
+
+    fn time_out(&self) {
+        self.timed_out.store(true, Ordering::SeqCst);
+        self.drain();
+    }
+
+    fn drain(&self) {
+        // SAFETY: guaranteed by the caller of `new`. Wasm code accesses the counter with
+        // aligned 8 byte loads and stores, which do not tear on any supported target.
+        unsafe { AtomicU64::from_ptr(self.remaining_points) }.store(0, Ordering::Relaxed);
+    }
+}
+
+struct ArmedCall {
+    id: u64,
+    deadline: Option<Instant>,
+    cancellation: Option<CancellationToken>,
+    interrupt: GasInterrupt,
+}
+
+#[derive(Default)]
+struct WatchdogState {
+    next_id: u64,
+    armed: Vec<ArmedCall>,
+}
+
+/// One thread that interrupts every call still running past its deadline or cancelled.
+pub struct Watchdog {
+    state: Mutex<WatchdogState>,
+    wakeup: Condvar,
+}
+
+impl Watchdog {
+    pub fn global() -> &'static Watchdog {
+        &WATCHDOG
+    }
+
+    fn start() -> Self {
+        thread::Builder::new()
+            .name("opvm-watchdog".to_string())
+            .spawn(|| WATCHDOG.run())
+            .expect("failed to spawn the watchdog thread");
+
+        Self {
+            state: Mutex::new(WatchdogState::default()),
+            wakeup: Condvar::new(),
+        }
+    }
+
+    /// Watches a call until the returned guard is dropped.
+    pub fn arm(
+        &'static self,
+        deadline: Option<Instant>,
+        cancellation: Option<CancellationToken>,
+        interrupt: GasInterrupt,
+    ) -> WatchdogGuard {
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.armed.push(ArmedCall {
+            id,
+            deadline,
+            cancellation,
+            interrupt,
+        });
+
+        self.wakeup.notify_one();
+
+        WatchdogGuard { watchdog: self, id }
+    }
+
+    /// Makes the watchdog look at the armed calls again, e.g. after a cancellation.
+    pub fn wake(&self) {
+        // Taken so the wakeup can not fall between the check and the wait of `run`.
+        let _state = self.state.lock().unwrap();
+        self.wakeup.notify_one();
+    }
+
+    fn disarm(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.armed.retain(|call| call.id != id);
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            let now = Instant::now();
+            let mut next_wakeup: Option<Instant> = None;
+
+            for call in &state.armed {
+                let expired = call.deadline.is_some_and(|deadline| deadline <= now);
+                let cancelled = call.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled);
+
+                let wakeup = if expired {
+                    call.interrupt.time_out();
+                    Some(now + DRAIN_INTERVAL)
+                } else if cancelled {
+                    call.interrupt.drain();
+                    Some(now + DRAIN_INTERVAL)
+                } else {
+                    call.deadline
+                };
+
+                if let Some(wakeup) = wakeup {
+                    next_wakeup = Some(next_wakeup.map_or(wakeup, |next| next.min(wakeup)));
+                }
+            }
+
+            state = match next_wakeup {
+                Some(wakeup) => {
+                    self.wakeup
+                        .wait_timeout(state, wakeup.saturating_duration_since(now))
+                        .unwrap()
+                        .0
+                }
+                None => self.wakeup.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+/// Disarms the call on drop. The watchdog never touches the gas counter afterwards.
+#[must_use]
+pub struct WatchdogGuard {
+    watchdog: &'static Watchdog,
+    id: u64,
+}
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        self.watchdog.disarm(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arm(
+        remaining_points: &AtomicU64,
+        timeout: Option<Duration>,
+        cancellation: Option<CancellationToken>,
+    ) -> (WatchdogGuard, Arc<AtomicBool>) {
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let interrupt = unsafe { GasInterrupt::new(remaining_points.as_ptr(), timed_out.clone()) };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        (Watchdog::global().arm(deadline, cancellation, interrupt), timed_out)
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let give_up = Instant::now() + Duration::from_secs(5);
+        while !condition() && Instant::now() < give_up {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn drains_gas_of_expired_calls() {
+        let remaining_points = AtomicU64::new(1_000);
+        let (guard, timed_out) = arm(&remaining_points, Some(Duration::from_millis(5)), None);
+
+        wait_for(|| timed_out.load(Ordering::SeqCst));
+        drop(guard);
+
+        assert!(timed_out.load(Ordering::SeqCst));
+        assert_eq!(remaining_points.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn leaves_disarmed_calls_alone() {
+        let remaining_points = AtomicU64::new(1_000);
+        let (guard, timed_out) = arm(&remaining_points, Some(Duration::from_millis(20)), None);
+        drop(guard);
+
+        thread::sleep(Duration::from_millis(60));
+
+        assert!(!timed_out.load(Ordering::SeqCst));
+        assert_eq!(remaining_points.load(Ordering::Relaxed), 1_000);
+    }
+
+    #[test]
+    fn drains_gas_of_cancelled_calls() {
+        let remaining_points = AtomicU64::new(1_000);
+        let cancellation = CancellationToken::new();
+        let (guard, timed_out) = arm(&remaining_points, None, Some(cancellation.clone()));
+
+        cancellation.cancel();
+        wait_for(|| remaining_points.load(Ordering::Relaxed) == 0);
+        drop(guard);
+
+        assert!(!timed_out.load(Ordering::SeqCst));
+        assert_eq!(remaining_points.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,495 @@
+use std::fmt;
+
+use wasmer::wasmparser::{
+    CompositeType, FuncType, FunctionBody, MemoryType, Operator, Parser, Payload, TypeRef, ValType,
+};
+use wasmer::Type;
+
+use crate::domain::vm::{get_gas_cost, ImportPolicy, DEFERRED_START_EXPORT};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    InvalidModule,
+    ForbiddenOperator,
+    ForbiddenType,
+    ForbiddenImport,
+    InvalidStartFunction,
+    ReservedExport,
+    LimitExceeded,
+}
+
+impl ViolationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationKind::InvalidModule => "invalid_module",
+            ViolationKind::ForbiddenOperator => "forbidden_operator",
+            ViolationKind::ForbiddenType => "forbidden_type",
+            ViolationKind::ForbiddenImport => "forbidden_import",
+            ViolationKind::InvalidStartFunction => "invalid_start_function",
+            ViolationKind::ReservedExport => "reserved_export",
+            ViolationKind::LimitExceeded => "limit_exceeded",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ValidationViolation {
+    pub kind: ViolationKind,
+    pub message: String,
+    pub opcode: Option<String>,
+    pub function_index: Option<u32>,
+    /// Byte offset in the module binary.
+    pub offset: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub violations: Vec<ValidationViolation>,
+    /// Gas the host charges for deploying the module, proportional to its size.
+    pub deployment_gas: u64,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    pub fn invalid_module(message: String, offset: usize) -> Self {
+        let mut report = Self::default();
+        report.push(ViolationKind::InvalidModule, message, offset);
+        report
+    }
+
+    pub fn push(&mut self, kind: ViolationKind, message: String, offset: usize) {
+        self.violations.push(ValidationViolation {
+            kind,
+            message,
+            opcode: None,
+            function_index: None,
+            offset,
+        });
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{} at offset {}: {}", violation.kind.as_str(), violation.offset, violation.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Statically checks that a module only uses the deterministic subset the VM executes.
+///
+/// Anything `get_gas_cost` prices at `u64::MAX` (floats, SIMD, atomics, reference types,
+/// exceptions, ...) would only fail once the offending path runs; this rejects it up front.
+/// Imports are checked against the host ABI, see [`ImportPolicy`].
+/// Every violation is collected so the deployer gets the complete list at once.
+pub struct BytecodeValidator {
+    report: ValidationReport,
+    types: Vec<Option<FuncType>>,
+    function_types: Vec<u32>,
+    imported_functions: u32,
+    next_function_index: u32,
+    memories: usize,
+}
+
+impl BytecodeValidator {
+    pub fn validate(bytecode: &[u8]) -> ValidationReport {
+        let mut validator = Self {
+            report: ValidationReport::default(),
+            types: Vec::new(),
+            function_types: Vec::new(),
+            imported_functions: 0,
+            next_function_index: 0,
+            memories: 0,
+        };
+
+        match validator.walk(bytecode) {
+            Ok(()) => {
+                if let Err(message) = ImportPolicy::check_memory_count(validator.memories) {
+                    validator.report.push(ViolationKind::ForbiddenImport, message, 0);
+                }
+            }
+            Err(e) => {
+                validator.report.push(ViolationKind::InvalidModule, e.message().to_string(), e.offset());
+            }
+        }
+
+        validator.report
+    }
+
+    fn walk(&mut self, bytecode: &[u8]) -> Result<(), wasmer::wasmparser::BinaryReaderError> {
+        for payload in Parser::new(0).parse_all(bytecode) {
+            match payload? {
+                Payload::TypeSection(reader) => {
+                    for rec_group in reader.into_iter_with_offsets() {
+                        let (offset, rec_group) = rec_group?;
+                        for sub_type in rec_group.into_types() {
+                            match sub_type.composite_type {
+                                CompositeType::Func(func_type) => {
+                                    self.check_func_type(&func_type, offset);
+                                    self.types.push(Some(func_type));
+                                }
+                                _ => {
+                                    self.report.push(
+                                        ViolationKind::ForbiddenType,
+                                        "GC types are not supported".to_string(),
+                                        offset,
+                                    );
+                                    self.types.push(None);
+                                }
+                            }
+                        }
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader.into_iter_with_offsets() {
+                        let (offset, import) = import?;
+                        self.check_import_type(import.module, import.name, &import.ty, offset);
+
+                        if let TypeRef::Func(type_index) = import.ty {
+                            self.function_types.push(type_index);
+                            self.imported_functions += 1;
+                        }
+                    }
+
+                    self.next_function_index = self.imported_functions;
+                }
+                Payload::MemorySection(reader) => {
+                    for memory in reader.into_iter_with_offsets() {
+                        let (offset, memory) = memory?;
+                        self.check_memory_type(&memory, offset);
+                        self.memories += 1;
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for type_index in reader {
+                        self.function_types.push(type_index?);
+                    }
+                }
+                Payload::GlobalSection(reader) => {
+                    for global in reader.into_iter_with_offsets() {
+                        let (offset, global) = global?;
+                        self.check_val_type(global.ty.content_type, "global", offset);
+                    }
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader.into_iter_with_offsets() {
+                        let (offset, table) = table?;
+                        if !table.ty.element_type.is_func_ref() {
+                            self.report.push(
+                                ViolationKind::ForbiddenType,
+                                format!("table of {:?} is not supported", table.ty.element_type),
+                                offset,
+                            );
+                        }
+                    }
+                }
+                Payload::TagSection(reader) => {
+                    self.report.push(
+                        ViolationKind::ForbiddenType,
+                        "exception tags are not supported".to_string(),
+                        reader.range().start,
+                    );
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader.into_iter_with_offsets() {
+                        let (offset, export) = export?;
+                        if export.name == DEFERRED_START_EXPORT {
+                            self.report.push(
+                                ViolationKind::ReservedExport,
+                                format!("export {} is reserved for the start function", export.name),
+                                offset,
+                            );
+                        }
+                    }
+                }
+                Payload::StartSection { func, range } => self.check_start_function(func, range.start),
+                Payload::CodeSectionEntry(body) => {
+                    let function_index = self.next_function_index;
+                    self.next_function_index += 1;
+
+                    self.check_function_body(function_index, &body)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_function_body(&mut self, function_index: u32, body: &FunctionBody) -> Result<(), wasmer::wasmparser::BinaryReaderError> {
+        let mut locals = body.get_locals_reader()?;
+        for _ in 0..locals.get_count() {
+            let offset = locals.original_position();
+            let (_, val_type) = locals.read()?;
+            self.check_val_type(val_type, "local", offset);
+        }
+
+        let mut operators = body.get_operators_reader()?;
+        while !operators.eof() {
+            let (operator, offset) = operators.read_with_offset()?;
+
+            if Self::is_forbidden_operator(&operator) {
+                let opcode = Self::opcode_name(&operator);
+                self.report.violations.push(ValidationViolation {
+                    kind: ViolationKind::ForbiddenOperator,
+                    message: format!("operator {} is not allowed", opcode),
+                    opcode: Some(opcode),
+                    function_index: Some(function_index),
+                    offset,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_start_function(&mut self, function_index: u32, offset: usize) {
+        if function_index < self.imported_functions {
+            self.report.push(
+                ViolationKind::InvalidStartFunction,
+                format!("start function {} must not be an import", function_index),
+                offset,
+            );
+            return;
+        }
+
+        let func_type = self
+            .function_types
+            .get(function_index as usize)
+            .and_then(|type_index| self.types.get(*type_index as usize))
+            .and_then(|func_type| func_type.as_ref());
+
+        match func_type {
+            Some(func_type) if func_type.params().is_empty() && func_type.results().is_empty() => {}
+            _ => self.report.push(
+                ViolationKind::InvalidStartFunction,
+                format!("start function {} must take no parameters and return nothing", function_index),
+                offset,
+            ),
+        }
+    }
+
+    fn check_import_type(&mut self, module: &str, name: &str, ty: &TypeRef, offset: usize) {
+        let result = match ty {
+            TypeRef::Func(type_index) => {
+                let func_type = self.types.get(*type_index as usize).and_then(|func_type| func_type.as_ref());
+                match func_type.map(|func_type| (Self::to_types(func_type.params()), Self::to_types(func_type.results()))) {
+                    Some((Some(params), Some(results))) => ImportPolicy::check_function(module, name, &params, &results),
+                    // Unsupported value types are already reported by the type section.
+                    _ => Ok(()),
+                }
+            }
+            TypeRef::Table(_) => ImportPolicy::check_non_function(module, name, "table"),
+            TypeRef::Global(_) => ImportPolicy::check_non_function(module, name, "global"),
+            TypeRef::Tag(_) => ImportPolicy::check_non_function(module, name, "tag"),
+            TypeRef::Memory(memory) => {
+                self.check_memory_type(memory, offset);
+                self.memories += 1;
+                ImportPolicy::check_non_function(module, name, "memory")
+            }
+        };
+
+        if let Err(message) = result {
+            self.report.push(ViolationKind::ForbiddenImport, message, offset);
+        }
+    }
+
+    fn to_types(val_types: &[ValType]) -> Option<Vec<Type>> {
+        val_types
+            .iter()
+            .map(|val_type| match val_type {
+                ValType::I32 => Some(Type::I32),
+                ValType::I64 => Some(Type::I64),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn check_func_type(&mut self, func_type: &FuncType, offset: usize) {
+        for val_type in func_type.params() {
+            self.check_val_type(*val_type, "parameter", offset);
+        }
+
+        for val_type in func_type.results() {
+            self.check_val_type(*val_type, "result", offset);
+        }
+    }
+
+    /// Shared memories only serve threads and 64-bit memories change every address, whether
+    /// the module defines or imports them.
+    fn check_memory_type(&mut self, memory: &MemoryType, offset: usize) {
+        if memory.shared {
+            self.report.push(ViolationKind::ForbiddenType, "shared memories are not supported".to_string(), offset);
+        }
+
+        if memory.memory64 {
+            self.report.push(ViolationKind::ForbiddenType, "64-bit memories are not supported".to_string(), offset);
+        }
+    }
+
+    fn check_val_type(&mut self, val_type: ValType, context: &str, offset: usize) {
+        if !matches!(val_type, ValType::I32 | ValType::I64) {
+            self.report.push(
+                ViolationKind::ForbiddenType,
+                format!("{} of type {} is not supported", context, val_type),
+                offset,
+            );
+        }
+    }
+
+    fn is_forbidden_operator(operator: &Operator) -> bool {
+        get_gas_cost(operator) == u64::MAX
+    }
+
+    /// The variant name of the operator, without its immediates.
+    fn opcode_name(operator: &Operator) -> String {
+        let debug = format!("{:?}", operator);
+        let end = debug
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(debug.len());
+
+        debug[..end].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(wat: &str) -> ValidationReport {
+        let bytecode = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        BytecodeValidator::validate(&bytecode)
+    }
+
+    #[test]
+    fn accepts_integer_only_modules() {
+        let report = validate(
+            r#"(module
+                (memory (export "memory") 1)
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add))"#,
+        );
+
+        assert!(report.is_valid(), "{:?}", report);
+    }
+
+    #[test]
+    fn reports_float_operators_with_location() {
+        let report = validate(
+            r#"(module
+                (func $integer (result i32) i32.const 1)
+                (func $float (result i32)
+                    f32.const 1
+                    i32.trunc_f32_s))"#,
+        );
+
+        let operators: Vec<&ValidationViolation> = report
+            .violations
+            .iter()
+            .filter(|violation| violation.kind == ViolationKind::ForbiddenOperator)
+            .collect();
+
+        assert_eq!(operators.len(), 2);
+        assert_eq!(operators[0].opcode.as_deref(), Some("F32Const"));
+        assert_eq!(operators[0].function_index, Some(1));
+        assert_eq!(operators[1].opcode.as_deref(), Some("I32TruncF32S"));
+        assert!(operators[1].offset > operators[0].offset);
+    }
+
+    #[test]
+    fn reports_float_types() {
+        let report = validate(r#"(module (func (param f64)) (global f32 (f32.const 0)))"#);
+
+        let types = report
+            .violations
+            .iter()
+            .filter(|violation| violation.kind == ViolationKind::ForbiddenType)
+            .count();
+
+        assert_eq!(types, 2);
+    }
+
+    #[test]
+    fn rejects_imported_start_function() {
+        let report = validate(r#"(module (import "env" "init" (func $init)) (start $init))"#);
+
+        let start = report
+            .violations
+            .iter()
+            .filter(|violation| violation.kind == ViolationKind::InvalidStartFunction)
+            .count();
+
+        assert_eq!(start, 1);
+    }
+
+    #[test]
+    fn checks_imports_against_the_host_abi() {
+        let report = validate(
+            r#"(module
+                (import "env" "load" (func (param i32) (result i32)))
+                (import "env" "log" (func (param i32 i32)))
+                (import "env" "random" (func (result i32)))
+                (import "env" "table" (table 1 funcref))
+                (import "env" "counter" (global i32))
+                (memory 1))"#,
+        );
+
+        let imports: Vec<&str> = report
+            .violations
+            .iter()
+            .filter(|violation| violation.kind == ViolationKind::ForbiddenImport)
+            .map(|violation| violation.message.as_str())
+            .collect();
+
+        assert_eq!(imports.len(), 4, "{:?}", imports);
+        assert!(imports[0].contains("env.log"));
+        assert!(imports[1].contains("env.random"));
+        assert!(imports[2].contains("table"));
+        assert!(imports[3].contains("global"));
+    }
+
+    #[test]
+    fn rejects_multiple_memories() {
+        let report = validate(r#"(module (import "env" "memory" (memory 1)) (memory 1))"#);
+
+        let messages: Vec<&str> = report.violations.iter().map(|violation| violation.message.as_str()).collect();
+
+        assert!(messages.iter().any(|message| message.contains("2 memories")), "{:?}", messages);
+    }
+
+    #[test]
+    fn rejects_shared_and_64_bit_memories_the_module_defines() {
+        for wat in ["(module (memory 1 1 shared))", "(module (memory i64 1))"] {
+            let report = validate(wat);
+
+            assert_eq!(report.violations.len(), 1, "{:?}", report);
+            assert_eq!(report.violations[0].kind, ViolationKind::ForbiddenType);
+        }
+    }
+
+    #[test]
+    fn rejects_exports_named_like_the_deferred_start_function() {
+        let report = validate(r#"(module (func (export "opvm_start")))"#);
+
+        assert_eq!(report.violations.len(), 1, "{:?}", report);
+        assert_eq!(report.violations[0].kind, ViolationKind::ReservedExport);
+    }
+
+    #[test]
+    fn reports_malformed_modules() {
+        let report = BytecodeValidator::validate(&[0, 97, 115, 109, 1]);
+
+        assert!(!report.is_valid());
+        assert_eq!(report.violations[0].kind, ViolationKind::InvalidModule);
+    }
+}
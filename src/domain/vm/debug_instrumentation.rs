@@ -0,0 +1,322 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use wasmer::wasmparser::{BlockType, Operator, ValType};
+use wasmer::{
+    ExportIndex, FunctionMiddleware, FunctionType, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, TableType, Type,
+};
+use wasmer_types::ModuleInfo;
+
+use crate::domain::vm::{DebugSymbols, Probe, ProbeCursor, METERING_REMAINING_POINTS_EXPORT};
+
+pub const DEBUG_PROBE_TABLE_EXPORT: &str = "opvm_debug_probe";
+pub const DEBUG_STEPPING_EXPORT: &str = "opvm_debug_stepping";
+pub const DEBUG_LOCATION_EXPORT: &str = "opvm_debug_location";
+pub const DEBUG_EVENT_EXPORT: &str = "opvm_debug_event";
+pub const DEBUG_BREAK_PREFIX: &str = "opvm_debug_break_";
+pub const DEBUG_LOCAL_PREFIX: &str = "opvm_debug_local_";
+
+/// Locals past this index are not copied out at a stop.
+pub const MAX_DEBUG_LOCALS: usize = 256;
+
+/// `opvm_debug_break_{function}` value that stops at every probe of the function.
+pub const BREAK_AT_ANY_PROBE: i32 = -1;
+
+#[derive(Clone, Copy, Debug)]
+struct DebugGlobalIndexes {
+    remaining_points: u32,
+    /// Table and signature of the host probe.
+    probe_table: u32,
+    probe_signature: u32,
+    stepping: u32,
+    location: u32,
+    event: u32,
+    first_break: u32,
+    first_local: u32,
+}
+
+/// Probes every function of a debug build at the positions of [`ProbeCursor`].
+///
+/// A probe stops when `opvm_debug_stepping` is set, or when `opvm_debug_break_{function}`
+/// holds its position + 1 or [`BREAK_AT_ANY_PROBE`]. It then publishes where it is in
+/// `opvm_debug_location` (function << 32 | position) and `opvm_debug_event`, copies the
+/// locals of the function to the i64 globals `opvm_debug_local_{n}` and calls the host
+/// probe, which blocks until the debugger resumes. A middleware can not add host imports,
+/// so the probe is the only element of the funcref table `opvm_debug_probe`, filled in
+/// once the instance exists, and the debugger drives the globals from another thread.
+///
+/// Must be pushed right *after* the `Metering` middleware: the probes are not charged, so
+/// a paused call keeps its gas, and the instructions injected by metering are told apart
+/// by the remaining points global so positions only count the original instructions.
+/// A paused probe also gives up once the remaining points are drained, so the deadline
+/// and cancellation of the watchdog still end a call nobody resumes.
+pub struct DebugInstrumentation {
+    function_locals: Vec<Vec<ValType>>,
+    global_indexes: Mutex<Option<DebugGlobalIndexes>>,
+}
+
+impl DebugInstrumentation {
+    pub fn new(symbols: &DebugSymbols) -> Self {
+        Self {
+            function_locals: symbols.functions.iter().map(|function| function.locals.clone()).collect(),
+            global_indexes: Mutex::new(None),
+        }
+    }
+
+    pub fn break_export(local_function_index: u32) -> String {
+        format!("{}{}", DEBUG_BREAK_PREFIX, local_function_index)
+    }
+
+    pub fn local_export(local_index: u32) -> String {
+        format!("{}{}", DEBUG_LOCAL_PREFIX, local_index)
+    }
+
+    pub fn encode_location(function: u32, position: u32) -> i64 {
+        ((function as u64) << 32 | position as u64) as i64
+    }
+
+    pub fn decode_location(location: u64) -> (u32, u32) {
+        ((location >> 32) as u32, location as u32)
+    }
+
+    fn local_count(&self) -> usize {
+        self.function_locals.iter().map(Vec::len).max().unwrap_or_default().min(MAX_DEBUG_LOCALS)
+    }
+}
+
+impl fmt::Debug for DebugInstrumentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DebugInstrumentation")
+            .field("global_indexes", &self.global_indexes)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for DebugInstrumentation {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let global_indexes = self.global_indexes.lock().unwrap().unwrap();
+        let function = local_function_index.as_u32();
+
+        Box::new(FunctionDebugInstrumentation {
+            global_indexes,
+            function,
+            locals: self.function_locals.get(function as usize).cloned().unwrap_or_default(),
+            cursor: ProbeCursor::default(),
+            started: false,
+            metering: false,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+
+        if global_indexes.is_some() {
+            return Err(MiddlewareError::new(
+                "DebugInstrumentation",
+                "Attempting to use a `DebugInstrumentation` middleware from multiple modules.",
+            ));
+        }
+
+        let remaining_points = match module_info.exports.get(METERING_REMAINING_POINTS_EXPORT) {
+            Some(ExportIndex::Global(index)) => index.as_u32(),
+            _ => {
+                return Err(MiddlewareError::new(
+                    "DebugInstrumentation",
+                    "The metering middleware must run before the debug instrumentation",
+                ))
+            }
+        };
+
+        let local_functions = module_info.functions.len() - module_info.num_imported_functions;
+        if local_functions != self.function_locals.len() {
+            return Err(MiddlewareError::new(
+                "DebugInstrumentation",
+                "The debug symbols do not belong to this module",
+            ));
+        }
+
+        let mut push_global = |name: String, ty: Type| {
+            let index = module_info.globals.push(GlobalType::new(ty, Mutability::Var));
+            module_info.global_initializers.push(match ty {
+                Type::I64 => GlobalInit::I64Const(0),
+                _ => GlobalInit::I32Const(0),
+            });
+            module_info.exports.insert(name, ExportIndex::Global(index));
+
+            index.as_u32()
+        };
+
+        let stepping = push_global(DEBUG_STEPPING_EXPORT.to_string(), Type::I32);
+        let location = push_global(DEBUG_LOCATION_EXPORT.to_string(), Type::I64);
+        let event = push_global(DEBUG_EVENT_EXPORT.to_string(), Type::I32);
+
+        let first_break = (0..local_functions as u32)
+            .map(|function| push_global(Self::break_export(function), Type::I32))
+            .min()
+            .unwrap_or_default();
+        let first_local = (0..self.local_count() as u32)
+            .map(|local| push_global(Self::local_export(local), Type::I64))
+            .min()
+            .unwrap_or_default();
+
+        let probe_table = module_info.tables.push(TableType::new(Type::FuncRef, 1, Some(1)));
+        module_info.exports.insert(DEBUG_PROBE_TABLE_EXPORT.to_string(), ExportIndex::Table(probe_table));
+        let probe_signature = module_info.signatures.push(FunctionType::new(vec![], vec![]));
+
+        *global_indexes = Some(DebugGlobalIndexes {
+            remaining_points,
+            probe_table: probe_table.as_u32(),
+            probe_signature: probe_signature.as_u32(),
+            stepping,
+            location,
+            event,
+            first_break,
+            first_local,
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FunctionDebugInstrumentation {
+    global_indexes: DebugGlobalIndexes,
+    function: u32,
+    locals: Vec<ValType>,
+    cursor: ProbeCursor,
+    started: bool,
+    /// Inside the instructions injected by metering, which have no position.
+    metering: bool,
+}
+
+impl FunctionDebugInstrumentation {
+    fn probe(&self, state: &mut MiddlewareReaderState<'_>, probe: Probe) {
+        let DebugGlobalIndexes { probe_table, probe_signature, stepping, location, event, first_break, first_local, .. } =
+            self.global_indexes;
+        let break_at = first_break + self.function;
+
+        // if (stepping | break == position + 1 | break == -1)
+        state.extend(&[
+            Operator::GlobalGet { global_index: stepping },
+            Operator::GlobalGet { global_index: break_at },
+            Operator::I32Const { value: probe.position as i32 + 1 },
+            Operator::I32Eq,
+            Operator::I32Or,
+            Operator::GlobalGet { global_index: break_at },
+            Operator::I32Const { value: BREAK_AT_ANY_PROBE },
+            Operator::I32Eq,
+            Operator::I32Or,
+            Operator::If { blockty: BlockType::Empty },
+            Operator::I64Const { value: DebugInstrumentation::encode_location(self.function, probe.position) },
+            Operator::GlobalSet { global_index: location },
+            Operator::I32Const { value: probe.event as i32 },
+            Operator::GlobalSet { global_index: event },
+        ]);
+
+        for (local_index, ty) in self.locals.iter().enumerate().take(MAX_DEBUG_LOCALS) {
+            let widen: &[Operator] = match ty {
+                ValType::I32 => &[Operator::I64ExtendI32U],
+                ValType::I64 => &[],
+                ValType::F32 => &[Operator::I32ReinterpretF32, Operator::I64ExtendI32U],
+                ValType::F64 => &[Operator::I64ReinterpretF64],
+                _ => continue,
+            };
+
+            state.push_operator(Operator::LocalGet { local_index: local_index as u32 });
+            state.extend(widen);
+            state.push_operator(Operator::GlobalSet { global_index: first_local + local_index as u32 });
+        }
+
+        // Returns once resumed or drained, without points left the next metered instruction traps.
+        state.extend(&[
+            Operator::I32Const { value: 0 },
+            Operator::CallIndirect { type_index: probe_signature, table_index: probe_table, table_byte: 0 },
+            Operator::End,
+        ]);
+    }
+}
+
+impl FunctionMiddleware for FunctionDebugInstrumentation {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if !self.started {
+            self.started = true;
+            self.probe(state, ProbeCursor::entry());
+        }
+
+        // Metering always reads the remaining points first and writes them back last.
+        let remaining_points = self.global_indexes.remaining_points;
+        match operator {
+            Operator::GlobalGet { global_index } if global_index == remaining_points => self.metering = true,
+            Operator::GlobalSet { global_index } if global_index == remaining_points => {
+                self.metering = false;
+                state.push_operator(operator);
+
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        if self.metering {
+            state.push_operator(operator);
+
+            return Ok(());
+        }
+
+        let (before, after) = self.cursor.advance(&operator);
+        if let Some(probe) = before {
+            self.probe(state, probe);
+        }
+        state.push_operator(operator);
+        if let Some(probe) = after {
+            self.probe(state, probe);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_the_control_and_local_globals() {
+        let bytecode = wasmer::wat2wasm(b"(module (func (param i32 i64)) (func))").unwrap();
+        let symbols = DebugSymbols::from_bytecode(&bytecode).unwrap();
+
+        let mut module_info = ModuleInfo::new();
+        let signature = module_info.signatures.push(FunctionType::new(vec![], vec![]));
+        module_info.functions.push(signature);
+        module_info.functions.push(signature);
+        let remaining_points = module_info.globals.push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I64Const(0));
+        module_info.exports.insert(
+            METERING_REMAINING_POINTS_EXPORT.to_string(),
+            ExportIndex::Global(remaining_points),
+        );
+
+        DebugInstrumentation::new(&symbols).transform_module_info(&mut module_info).unwrap();
+
+        for export in [DEBUG_PROBE_TABLE_EXPORT, DEBUG_STEPPING_EXPORT, DEBUG_LOCATION_EXPORT, DEBUG_EVENT_EXPORT] {
+            assert!(module_info.exports.contains_key(export), "{}", export);
+        }
+        assert!(module_info.exports.contains_key(&DebugInstrumentation::break_export(1)));
+        assert!(module_info.exports.contains_key(&DebugInstrumentation::local_export(1)));
+        assert!(!module_info.exports.contains_key(&DebugInstrumentation::local_export(2)));
+    }
+
+    #[test]
+    fn packs_function_and_position_into_the_location() {
+        let location = DebugInstrumentation::encode_location(3, 17);
+
+        assert_eq!(DebugInstrumentation::decode_location(location as u64), (3, 17));
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use wasmer::wasmparser::{
+    BinaryReaderError, ExternalKind, FunctionBody, Name, NameSectionReader, Operator, Parser, Payload, TypeRef, ValType,
+};
+
+/// Why the instrumented code stopped at a probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeEvent {
+    Enter = 1,
+    Block = 2,
+    Leave = 3,
+}
+
+impl ProbeEvent {
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            1 => Some(Self::Enter),
+            2 => Some(Self::Block),
+            3 => Some(Self::Leave),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Probe {
+    pub event: ProbeEvent,
+    /// Index of the instruction the probe runs in front of.
+    pub position: u32,
+}
+
+/// Walks the instructions of a function body and tells where the probes go: on entry, at
+/// the start of every block, loop and branch of an `if`, after every `end`, and before
+/// leaving the function. Shared by the instrumentation and the symbols so they agree on
+/// the positions.
+#[derive(Debug)]
+pub struct ProbeCursor {
+    depth: u32,
+    position: u32,
+}
+
+impl Default for ProbeCursor {
+    fn default() -> Self {
+        Self { depth: 1, position: 0 }
+    }
+}
+
+impl ProbeCursor {
+    pub fn entry() -> Probe {
+        Probe { event: ProbeEvent::Enter, position: 0 }
+    }
+
+    /// The probes to run before and after `operator`.
+    pub fn advance(&mut self, operator: &Operator) -> (Option<Probe>, Option<Probe>) {
+        let position = self.position;
+        self.position += 1;
+
+        let block = Some(Probe { event: ProbeEvent::Block, position: position + 1 });
+        let leave = Some(Probe { event: ProbeEvent::Leave, position });
+
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                self.depth += 1;
+                (None, block)
+            }
+            Operator::Else => (None, block),
+            Operator::End => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 0 {
+                    (leave, None)
+                } else {
+                    (None, block)
+                }
+            }
+            Operator::Return => (leave, None),
+            _ => (None, None),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugFunction {
+    pub name: String,
+    /// Parameters first, then the declared locals.
+    pub locals: Vec<ValType>,
+    /// Module offset of every instruction of the body.
+    pub instruction_offsets: Vec<usize>,
+    pub probes: Vec<Probe>,
+}
+
+impl DebugFunction {
+    /// The first probe at or after the instruction at module offset `offset`.
+    pub fn probe_at(&self, offset: usize) -> Option<Probe> {
+        let instruction = self.instruction_offsets.partition_point(|start| *start < offset) as u32;
+
+        self.probes.iter().copied().find(|probe| probe.position >= instruction)
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        match (self.instruction_offsets.first(), self.instruction_offsets.last()) {
+            (Some(first), Some(last)) => (*first..=*last).contains(&offset),
+            _ => false,
+        }
+    }
+}
+
+/// Names, locals and probe positions of the local functions of a module, indexed like
+/// `LocalFunctionIndex`. Names come from the name section, then from the exports.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugSymbols {
+    pub functions: Vec<DebugFunction>,
+}
+
+impl DebugSymbols {
+    pub fn from_bytecode(bytecode: &[u8]) -> Result<Self, BinaryReaderError> {
+        let mut types: Vec<Vec<ValType>> = Vec::new();
+        let mut function_types: Vec<u32> = Vec::new();
+        let mut imported_functions: u32 = 0;
+        let mut names: HashMap<u32, String> = HashMap::new();
+        let mut export_names: HashMap<u32, String> = HashMap::new();
+        let mut functions: Vec<DebugFunction> = Vec::new();
+
+        for payload in Parser::new(0).parse_all(bytecode) {
+            match payload? {
+                Payload::TypeSection(reader) => {
+                    for ty in reader.into_iter_err_on_gc_types() {
+                        types.push(ty?.params().to_vec());
+                    }
+                }
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        if let TypeRef::Func(_) = import?.ty {
+                            imported_functions += 1;
+                        }
+                    }
+                }
+                Payload::FunctionSection(reader) => {
+                    for ty in reader {
+                        function_types.push(ty?);
+                    }
+                }
+                Payload::ExportSection(reader) => {
+                    for export in reader {
+                        let export = export?;
+                        if export.kind == ExternalKind::Func {
+                            export_names.entry(export.index).or_insert_with(|| export.name.to_string());
+                        }
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    let params = function_types
+                        .get(functions.len())
+                        .and_then(|ty| types.get(*ty as usize))
+                        .cloned()
+                        .unwrap_or_default();
+
+                    functions.push(Self::read_function(params, &body)?);
+                }
+                Payload::CustomSection(reader) if reader.name() == "name" => {
+                    // A malformed name section only costs the names.
+                    for name in NameSectionReader::new(reader.data(), reader.data_offset()).flatten() {
+                        if let Name::Function(map) = name {
+                            for naming in map.into_iter().flatten() {
+                                names.insert(naming.index, naming.name.to_string());
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for (local_index, function) in functions.iter_mut().enumerate() {
+            let index = imported_functions + local_index as u32;
+            function.name = names
+                .remove(&index)
+                .or_else(|| export_names.remove(&index))
+                .unwrap_or_else(|| format!("function_{}", index));
+        }
+
+        Ok(Self { functions })
+    }
+
+    pub fn function_by_name(&self, name: &str) -> Option<u32> {
+        self.functions.iter().position(|function| function.name == name).map(|index| index as u32)
+    }
+
+    /// The local function holding module offset `offset` and the first probe from there.
+    pub fn probe_at(&self, offset: usize) -> Option<(u32, Probe)> {
+        let index = self.functions.iter().position(|function| function.contains(offset))?;
+        let probe = self.functions[index].probe_at(offset)?;
+
+        Some((index as u32, probe))
+    }
+
+    pub fn max_locals(&self) -> usize {
+        self.functions.iter().map(|function| function.locals.len()).max().unwrap_or_default()
+    }
+
+    fn read_function(mut locals: Vec<ValType>, body: &FunctionBody) -> Result<DebugFunction, BinaryReaderError> {
+        let mut locals_reader = body.get_locals_reader()?;
+        for _ in 0..locals_reader.get_count() {
+            let (count, ty) = locals_reader.read()?;
+            locals.extend(std::iter::repeat_n(ty, count as usize));
+        }
+
+        let mut instruction_offsets = Vec::new();
+        let mut probes = vec![ProbeCursor::entry()];
+        let mut cursor = ProbeCursor::default();
+
+        let mut operators = body.get_operators_reader()?;
+        while !operators.eof() {
+            let (operator, offset) = operators.read_with_offset()?;
+            instruction_offsets.push(offset);
+
+            let (before, after) = cursor.advance(&operator);
+            probes.extend(before.into_iter().chain(after));
+        }
+
+        Ok(DebugFunction {
+            name: String::new(),
+            locals,
+            instruction_offsets,
+            probes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(wat: &str) -> DebugSymbols {
+        let bytecode = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        DebugSymbols::from_bytecode(&bytecode).unwrap()
+    }
+
+    #[test]
+    fn reads_names_locals_and_probes() {
+        let symbols = symbols(
+            r#"(module
+                (import "env" "log" (func (param i32)))
+                (func $add (param i32 i64) (local f64)
+                    (block (nop)))
+                (func (export "run")))"#,
+        );
+
+        assert_eq!(symbols.functions.len(), 2);
+        assert_eq!(symbols.function_by_name("add"), Some(0));
+        assert_eq!(symbols.function_by_name("run"), Some(1));
+
+        let add = &symbols.functions[0];
+        assert_eq!(add.locals, vec![ValType::I32, ValType::I64, ValType::F64]);
+
+        // block, nop, end, end
+        let positions: Vec<(ProbeEvent, u32)> = add.probes.iter().map(|probe| (probe.event, probe.position)).collect();
+        assert_eq!(
+            positions,
+            vec![
+                (ProbeEvent::Enter, 0),
+                (ProbeEvent::Block, 1),
+                (ProbeEvent::Block, 3),
+                (ProbeEvent::Leave, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolves_module_offsets_to_the_next_probe() {
+        let symbols = symbols("(module (func (nop) (loop (nop))))");
+        let function = &symbols.functions[0];
+
+        // nop, loop, nop, end, end: the first nop runs before the loop probe.
+        let (index, probe) = symbols.probe_at(function.instruction_offsets[0]).unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(probe, Probe { event: ProbeEvent::Enter, position: 0 });
+
+        let (_, probe) = symbols.probe_at(function.instruction_offsets[1]).unwrap();
+        assert_eq!(probe, Probe { event: ProbeEvent::Block, position: 2 });
+
+        assert!(symbols.probe_at(0).is_none());
+    }
+}
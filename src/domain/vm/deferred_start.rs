@@ -0,0 +1,57 @@
+use std::fmt;
+
+use wasmer::{ExportIndex, FunctionMiddleware, LocalFunctionIndex, MiddlewareError, ModuleMiddleware};
+use wasmer_types::ModuleInfo;
+
+pub const DEFERRED_START_EXPORT: &str = "opvm_start";
+
+/// Turns the module's start function into a regular export.
+///
+/// Wasmer runs the start function inside `Instance::new`, before the host gets a chance
+/// to set the gas limit. Deferring it lets the runner meter the constructor against the
+/// caller-supplied budget instead of the compile-time initial limit. Modules exporting
+/// [`DEFERRED_START_EXPORT`] themselves fail to compile, the runner would take their
+/// export for the constructor.
+#[derive(Default)]
+pub struct DeferredStart;
+
+impl DeferredStart {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl fmt::Debug for DeferredStart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredStart").finish()
+    }
+}
+
+impl ModuleMiddleware for DeferredStart {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionDeferredStart)
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        if module_info.exports.contains_key(DEFERRED_START_EXPORT) {
+            return Err(MiddlewareError::new(
+                "DeferredStart",
+                format!("the module exports `{}`, which is reserved for its start function", DEFERRED_START_EXPORT),
+            ));
+        }
+
+        if let Some(start_function) = module_info.start_function.take() {
+            module_info.exports.insert(
+                DEFERRED_START_EXPORT.to_string(),
+                ExportIndex::Function(start_function),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FunctionDeferredStart;
+
+impl FunctionMiddleware for FunctionDeferredStart {}
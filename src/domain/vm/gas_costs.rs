@@ -1,5 +1,9 @@
 use wasmer::wasmparser::Operator;
 
+/// Bump whenever `get_gas_cost` changes, so modules metered with an older schedule are
+/// never reused.
+pub const GAS_SCHEDULE_VERSION: u32 = 1;
+
 pub fn get_gas_cost(operator: &Operator) -> u64 {
     use Operator::*;
 
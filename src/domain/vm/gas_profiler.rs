@@ -0,0 +1,229 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use wasmer::wasmparser::Operator;
+use wasmer::{
+    ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::{GlobalIndex, ModuleInfo};
+
+pub const METERING_REMAINING_POINTS_EXPORT: &str = "wasmer_metering_remaining_points";
+pub const PROFILING_CHECKPOINT_EXPORT: &str = "opvm_profiling_checkpoint";
+pub const PROFILING_FUNCTION_GAS_PREFIX: &str = "opvm_profiling_gas_";
+pub const PROFILING_FUNCTION_CALLS_PREFIX: &str = "opvm_profiling_calls_";
+
+#[derive(Clone, Debug)]
+struct GasProfilerGlobalIndexes {
+    remaining_points: GlobalIndex,
+    checkpoint: GlobalIndex,
+    first_function: u32,
+}
+
+/// Attributes consumed metering points to the function that consumed them.
+///
+/// Must be pushed *after* the `Metering` middleware: it reads the remaining points
+/// global and never charges gas itself, so consensus gas results are unchanged.
+/// Every local function gets two exported i64 globals holding the gas spent in its own
+/// body and the number of times it was entered; host import costs are recorded on the
+/// host side.
+pub struct GasProfiler {
+    global_indexes: Mutex<Option<GasProfilerGlobalIndexes>>,
+}
+
+impl Default for GasProfiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GasProfiler {
+    pub fn new() -> Self {
+        Self {
+            global_indexes: Mutex::new(None),
+        }
+    }
+
+    pub fn function_gas_export(local_function_index: u32) -> String {
+        format!("{}{}", PROFILING_FUNCTION_GAS_PREFIX, local_function_index)
+    }
+
+    pub fn function_calls_export(local_function_index: u32) -> String {
+        format!("{}{}", PROFILING_FUNCTION_CALLS_PREFIX, local_function_index)
+    }
+}
+
+impl fmt::Debug for GasProfiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GasProfiler")
+            .field("global_indexes", &self.global_indexes)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for GasProfiler {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let global_indexes = self.global_indexes.lock().unwrap().clone().unwrap();
+        let function_gas = global_indexes.first_function + 2 * local_function_index.as_u32();
+
+        Box::new(FunctionGasProfiler {
+            remaining_points: global_indexes.remaining_points.as_u32(),
+            checkpoint: global_indexes.checkpoint.as_u32(),
+            function_gas,
+            function_calls: function_gas + 1,
+            depth: 1,
+            started: false,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+
+        if global_indexes.is_some() {
+            return Err(MiddlewareError::new(
+                "GasProfiler",
+                "Attempting to use a `GasProfiler` middleware from multiple modules.",
+            ));
+        }
+
+        let remaining_points = match module_info.exports.get(METERING_REMAINING_POINTS_EXPORT) {
+            Some(ExportIndex::Global(index)) => *index,
+            _ => {
+                return Err(MiddlewareError::new(
+                    "GasProfiler",
+                    "The metering middleware must run before the gas profiler",
+                ))
+            }
+        };
+
+        let checkpoint = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I64Const(0));
+        module_info.exports.insert(
+            PROFILING_CHECKPOINT_EXPORT.to_string(),
+            ExportIndex::Global(checkpoint),
+        );
+
+        let local_functions = module_info.functions.len() - module_info.num_imported_functions;
+        let mut first_function = None;
+        for local_index in 0..local_functions as u32 {
+            let gas = module_info
+                .globals
+                .push(GlobalType::new(Type::I64, Mutability::Var));
+            module_info.global_initializers.push(GlobalInit::I64Const(0));
+            module_info.exports.insert(
+                Self::function_gas_export(local_index),
+                ExportIndex::Global(gas),
+            );
+
+            let calls = module_info
+                .globals
+                .push(GlobalType::new(Type::I64, Mutability::Var));
+            module_info.global_initializers.push(GlobalInit::I64Const(0));
+            module_info.exports.insert(
+                Self::function_calls_export(local_index),
+                ExportIndex::Global(calls),
+            );
+
+            first_function.get_or_insert(gas.as_u32());
+        }
+
+        *global_indexes = Some(GasProfilerGlobalIndexes {
+            remaining_points,
+            checkpoint,
+            first_function: first_function.unwrap_or_default(),
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FunctionGasProfiler {
+    remaining_points: u32,
+    checkpoint: u32,
+    function_gas: u32,
+    function_calls: u32,
+    depth: u32,
+    started: bool,
+}
+
+impl FunctionGasProfiler {
+    /// globals[function_gas] += globals[checkpoint] - globals[remaining_points]
+    fn flush(&self, state: &mut MiddlewareReaderState<'_>) {
+        state.extend(&[
+            Operator::GlobalGet { global_index: self.function_gas },
+            Operator::GlobalGet { global_index: self.checkpoint },
+            Operator::GlobalGet { global_index: self.remaining_points },
+            Operator::I64Sub,
+            Operator::I64Add,
+            Operator::GlobalSet { global_index: self.function_gas },
+        ]);
+
+        self.reset(state);
+    }
+
+    /// globals[checkpoint] = globals[remaining_points]
+    fn reset(&self, state: &mut MiddlewareReaderState<'_>) {
+        state.extend(&[
+            Operator::GlobalGet { global_index: self.remaining_points },
+            Operator::GlobalSet { global_index: self.checkpoint },
+        ]);
+    }
+}
+
+impl FunctionMiddleware for FunctionGasProfiler {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if !self.started {
+            self.started = true;
+            self.reset(state);
+
+            // globals[function_calls] += 1
+            state.extend(&[
+                Operator::GlobalGet { global_index: self.function_calls },
+                Operator::I64Const { value: 1 },
+                Operator::I64Add,
+                Operator::GlobalSet { global_index: self.function_calls },
+            ]);
+        }
+
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                self.depth += 1;
+                state.push_operator(operator);
+            }
+            Operator::End => {
+                self.depth -= 1;
+                if self.depth == 0 {
+                    self.flush(state);
+                }
+                state.push_operator(operator);
+            }
+            // Callees account for themselves and host imports are recorded by the host,
+            // so the checkpoint only needs to be moved forward once the call returns.
+            Operator::Call { .. } | Operator::CallIndirect { .. } => {
+                self.flush(state);
+                state.push_operator(operator);
+                self.reset(state);
+            }
+            Operator::Return
+            | Operator::Br { .. }
+            | Operator::BrIf { .. }
+            | Operator::BrTable { .. } => {
+                self.flush(state);
+                state.push_operator(operator);
+            }
+            _ => state.push_operator(operator),
+        }
+
+        Ok(())
+    }
+}
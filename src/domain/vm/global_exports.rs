@@ -0,0 +1,77 @@
+use std::fmt;
+
+use wasmer::{ExportIndex, FunctionMiddleware, LocalFunctionIndex, MiddlewareError, ModuleMiddleware};
+use wasmer_types::ModuleInfo;
+
+pub const GLOBAL_EXPORT_PREFIX: &str = "opvm_global_";
+
+/// Exports every global of the module under `opvm_global_{index}`.
+///
+/// Wasmer only exposes exported globals to the host, so without this a snapshot of an
+/// instance could not capture the internal state of the contract, e.g. its allocator.
+#[derive(Default)]
+pub struct GlobalExports;
+
+impl GlobalExports {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn export_name(global_index: u32) -> String {
+        format!("{}{}", GLOBAL_EXPORT_PREFIX, global_index)
+    }
+}
+
+impl fmt::Debug for GlobalExports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GlobalExports").finish()
+    }
+}
+
+impl ModuleMiddleware for GlobalExports {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionGlobalExports)
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let globals: Vec<_> = module_info.global_initializers.keys().map(|index| module_info.global_index(index)).collect();
+
+        for global_index in globals {
+            module_info.exports.insert(
+                Self::export_name(global_index.as_u32()),
+                ExportIndex::Global(global_index),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FunctionGlobalExports;
+
+impl FunctionMiddleware for FunctionGlobalExports {}
+
+#[cfg(test)]
+mod tests {
+    use wasmer::{GlobalInit, GlobalType, Mutability, Type};
+
+    use super::*;
+
+    #[test]
+    fn exports_every_local_global() {
+        let mut module_info = ModuleInfo::new();
+        for value in 0..2 {
+            module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+            module_info.global_initializers.push(GlobalInit::I32Const(value));
+        }
+
+        GlobalExports::new().transform_module_info(&mut module_info).unwrap();
+
+        assert_eq!(module_info.exports.len(), 2);
+        assert!(matches!(
+            module_info.exports.get(&GlobalExports::export_name(1)),
+            Some(ExportIndex::Global(index)) if index.as_u32() == 1
+        ));
+    }
+}
@@ -0,0 +1,112 @@
+use wasmer::{ExternType, Module, Type};
+
+use crate::domain::vm::{ValidationReport, ViolationKind};
+
+pub struct HostFunction {
+    pub module: &'static str,
+    pub name: &'static str,
+    pub params: &'static [Type],
+    pub results: &'static [Type],
+}
+
+/// The host ABI. Must match the imports object built in `WasmerRunner::create_instance`.
+pub const HOST_FUNCTIONS: &[HostFunction] = &[
+    HostFunction { module: "env", name: "abort", params: &[Type::I32, Type::I32, Type::I32, Type::I32], results: &[] },
+    HostFunction { module: "env", name: "load", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "loadMany", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "store", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "call", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "deployFromAddress", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "encodeAddress", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "sha256", params: &[Type::I32], results: &[Type::I32] },
+    HostFunction { module: "env", name: "log", params: &[Type::I32], results: &[] },
+];
+
+pub const MAX_MEMORIES: usize = 1;
+
+/// Decides which imports a contract may declare.
+///
+/// Only functions of the host ABI with their exact signature are allowed. The host never
+/// provides tables, globals or memories, so importing one is always a violation.
+pub struct ImportPolicy;
+
+impl ImportPolicy {
+    pub fn check_function(module: &str, name: &str, params: &[Type], results: &[Type]) -> Result<(), String> {
+        let host_function = HOST_FUNCTIONS
+            .iter()
+            .find(|function| function.module == module && function.name == name)
+            .ok_or_else(|| format!("import {}.{} is not part of the host ABI", module, name))?;
+
+        if host_function.params != params || host_function.results != results {
+            return Err(format!(
+                "import {}.{} has signature {:?} -> {:?}, expected {:?} -> {:?}",
+                module, name, params, results, host_function.params, host_function.results
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_non_function(module: &str, name: &str, kind: &str) -> Result<(), String> {
+        Err(format!("import {}.{}: importing a {} is not allowed", module, name, kind))
+    }
+
+    pub fn check_memory_count(memories: usize) -> Result<(), String> {
+        if memories > MAX_MEMORIES {
+            return Err(format!("module declares {} memories, at most {} allowed", memories, MAX_MEMORIES));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the imports of a compiled module, used at instantiation time where the
+    /// original bytecode may no longer be available.
+    pub fn check_module(module: &Module) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        for import in module.imports() {
+            let result = match import.ty() {
+                ExternType::Function(function) => {
+                    Self::check_function(import.module(), import.name(), function.params(), function.results())
+                }
+                ExternType::Table(_) => Self::check_non_function(import.module(), import.name(), "table"),
+                ExternType::Global(_) => Self::check_non_function(import.module(), import.name(), "global"),
+                ExternType::Memory(_) => Self::check_non_function(import.module(), import.name(), "memory"),
+            };
+
+            if let Err(message) = result {
+                report.push(ViolationKind::ForbiddenImport, message, 0);
+            }
+        }
+
+        if let Err(message) = Self::check_memory_count(module.info().memories.len()) {
+            report.push(ViolationKind::ForbiddenImport, message, 0);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_host_functions_with_exact_signature() {
+        assert!(ImportPolicy::check_function("env", "load", &[Type::I32], &[Type::I32]).is_ok());
+        assert!(ImportPolicy::check_function("env", "abort", &[Type::I32; 4], &[]).is_ok());
+    }
+
+    #[test]
+    fn rejects_unknown_functions_and_wrong_signatures() {
+        assert!(ImportPolicy::check_function("env", "seed", &[], &[Type::I64]).is_err());
+        assert!(ImportPolicy::check_function("wasi", "load", &[Type::I32], &[Type::I32]).is_err());
+        assert!(ImportPolicy::check_function("env", "log", &[Type::I32], &[Type::I32]).is_err());
+    }
+
+    #[test]
+    fn allows_a_single_memory() {
+        assert!(ImportPolicy::check_memory_count(1).is_ok());
+        assert!(ImportPolicy::check_memory_count(2).is_err());
+    }
+}
@@ -6,27 +6,43 @@ use wasmer::{
     , vm::{self, MemoryError, MemoryStyle, TableStyle, VMMemoryDefinition, VMTableDefinition},
 };
 use wasmer::sys::VMConfig;
+use wasmer_types::entity::PrimaryMap;
+use wasmer_compiler::LinkError;
+use wasmer_types::{LocalGlobalIndex, LocalTableIndex, ModuleInfo, TableIndex};
+use wasmer_vm::{InternalStoreHandle, StoreObjects, VMGlobal, VMTable};
 
-/// A custom tunables that allows you to set a memory limit.
+/// A custom tunables that allows you to set memory, table and global limits.
 ///
-/// After adjusting the memory limits, it delegates all other logic
+/// After adjusting the limits, it delegates all other logic
 /// to the base tunables.
 pub struct LimitingTunables<T: Tunables> {
     /// The maximum a linear memory is allowed to be (in Wasm pages, 64 KiB each).
     /// Since Wasmer ensures there is only none or one memory, this is practically
     /// an upper limit for the guest memory.
     max_pages: Pages, // 1 page = 64 KiB
+    /// The maximum number of elements a table is allowed to hold, including
+    /// after growing.
+    max_table_elements: u32,
+    /// The maximum number of globals an instance may define, including the ones
+    /// injected by the middlewares.
+    max_globals: usize,
     vm_config: VMConfig,
     /// The base implementation we delegate all the logic to
     base: T,
 }
 
 impl<T: Tunables> LimitingTunables<T> {
-    pub fn new(base: T, max_pages: u32, stack_size: usize) -> Self {
+    pub fn new(base: T, max_pages: u32, stack_size: usize, max_table_elements: u32, max_globals: usize) -> Self {
         let vm_config = VMConfig {
             wasm_stack_size: Some(stack_size),
         };
-        Self { max_pages: Pages(max_pages), vm_config, base }
+        Self {
+            max_pages: Pages(max_pages),
+            max_table_elements,
+            max_globals,
+            vm_config,
+            base,
+        }
     }
 
     /// Takes an input memory type as requested by the guest and sets
@@ -62,6 +78,48 @@ impl<T: Tunables> LimitingTunables<T> {
 
         Ok(())
     }
+
+    /// Takes an input table type as requested by the guest and sets
+    /// a maximum if missing, so that unbounded tables can not grow past
+    /// the limit. validate_table must be called before creating the table.
+    fn adjust_table(&self, requested: &TableType) -> TableType {
+        let mut adjusted = *requested;
+        if requested.maximum.is_none() {
+            adjusted.maximum = Some(self.max_table_elements);
+        }
+        adjusted
+    }
+
+    /// Ensures that a given table type does not exceed the table limit.
+    /// Call this after adjusting the table.
+    fn validate_table(&self, ty: &TableType) -> Result<(), String> {
+        if ty.minimum > self.max_table_elements {
+            return Err("Minimum exceeds the allowed table limit".to_string());
+        }
+
+        if let Some(max) = ty.maximum {
+            if max > self.max_table_elements {
+                return Err("Maximum exceeds the allowed table limit".to_string());
+            }
+        } else {
+            return Err("Maximum unset".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Ensures that a module does not define more globals than allowed.
+    fn validate_globals(&self, module: &ModuleInfo) -> Result<(), String> {
+        let globals = module.globals.len() - module.num_imported_globals;
+        if globals > self.max_globals {
+            return Err(format!(
+                "{} globals exceed the allowed limit of {}",
+                globals, self.max_globals
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl<T: Tunables> Tunables for LimitingTunables<T> {
@@ -77,7 +135,8 @@ impl<T: Tunables> Tunables for LimitingTunables<T> {
     ///
     /// Delegated to base.
     fn table_style(&self, table: &TableType) -> TableStyle {
-        self.base.table_style(table)
+        let adjusted = self.adjust_table(table);
+        self.base.table_style(&adjusted)
     }
 
     /// Create a memory owned by the host given a [`MemoryType`] and a [`MemoryStyle`].
@@ -110,24 +169,126 @@ impl<T: Tunables> Tunables for LimitingTunables<T> {
 
     /// Create a table owned by the host given a [`TableType`] and a [`TableStyle`].
     ///
-    /// Delegated to base.
+    /// The requested table type is validated, adjusted to the limited and then passed to base.
     fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<vm::VMTable, String> {
-        self.base.create_host_table(ty, style)
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_host_table(&adjusted, style)
     }
 
     /// Create a table owned by the VM given a [`TableType`] and a [`TableStyle`].
     ///
-    /// Delegated to base.
+    /// The requested table type is validated, adjusted to the limited and then passed to base.
     unsafe fn create_vm_table(
         &self,
         ty: &TableType,
         style: &TableStyle,
         vm_definition_location: NonNull<VMTableDefinition>,
     ) -> Result<vm::VMTable, String> {
-        self.base.create_vm_table(ty, style, vm_definition_location)
+        let adjusted = self.adjust_table(ty);
+        self.validate_table(&adjusted)?;
+        self.base.create_vm_table(&adjusted, style, vm_definition_location)
+    }
+
+    /// Allocate the tables of an instance.
+    ///
+    /// Like base, except that tables added by a middleware get a style here: the styles are
+    /// computed before the middlewares run.
+    unsafe fn create_tables(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+        table_styles: &PrimaryMap<TableIndex, TableStyle>,
+        table_definition_locations: &[NonNull<VMTableDefinition>],
+    ) -> Result<PrimaryMap<LocalTableIndex, InternalStoreHandle<VMTable>>, LinkError> {
+        let mut tables = PrimaryMap::with_capacity(module.tables.len() - module.num_imported_tables);
+        for (index, location) in table_definition_locations
+            .iter()
+            .enumerate()
+            .take(module.tables.len())
+            .skip(module.num_imported_tables)
+        {
+            let index = TableIndex::from_u32(index as u32);
+            let ty = &module.tables[index];
+            let style = table_styles
+                .get(index)
+                .cloned()
+                .unwrap_or_else(|| self.table_style(ty));
+            let table = self.create_vm_table(ty, &style, *location).map_err(LinkError::Resource)?;
+
+            tables.push(InternalStoreHandle::new(context, table));
+        }
+
+        Ok(tables)
+    }
+
+    /// Allocate the globals of an instance.
+    ///
+    /// The number of globals is validated and then passed to base.
+    fn create_globals(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+    ) -> Result<PrimaryMap<LocalGlobalIndex, InternalStoreHandle<VMGlobal>>, LinkError> {
+        self.validate_globals(module).map_err(LinkError::Resource)?;
+        self.base.create_globals(context, module)
     }
 
     fn vmconfig(&self) -> &VMConfig {
         &self.vm_config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use wasmer::sys::{BaseTunables, EngineBuilder};
+    use wasmer::{imports, Instance, Module, Store, Value};
+    use wasmer_compiler_singlepass::Singlepass;
+    use wasmer_types::Target;
+
+    use super::*;
+
+    /// Instantiates `wat` in a store limited to 100 table elements and 2 globals.
+    fn instantiate(wat: &str) -> Result<(Store, Instance), String> {
+        let mut engine = EngineBuilder::new(Singlepass::default()).engine();
+        let base = BaseTunables::for_target(&Target::default());
+        engine.set_tunables(LimitingTunables::new(base, 16, 1024 * 1024, 100, 2));
+
+        let mut store = Store::new(engine);
+        let module = Module::new(&store, wat).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).map_err(|e| e.to_string())?;
+
+        Ok((store, instance))
+    }
+
+    #[test]
+    fn oversized_tables_fail_to_instantiate() {
+        let error = instantiate("(module (table 101 funcref))").err().unwrap();
+        assert!(error.contains("Minimum exceeds the allowed table limit"), "{}", error);
+
+        let error = instantiate("(module (table 1 101 funcref))").err().unwrap();
+        assert!(error.contains("Maximum exceeds the allowed table limit"), "{}", error);
+
+        assert!(instantiate("(module (table 100 100 funcref))").is_ok());
+    }
+
+    #[test]
+    fn unbounded_tables_can_only_grow_to_the_limit() {
+        let (mut store, instance) = instantiate(r#"(module (table (export "table") 1 funcref))"#).unwrap();
+        let table = instance.exports.get_table("table").unwrap();
+
+        assert_eq!(table.ty(&store).maximum, Some(100));
+        assert!(table.grow(&mut store, 100, Value::FuncRef(None)).is_err());
+        assert_eq!(table.grow(&mut store, 99, Value::FuncRef(None)).unwrap(), 1);
+    }
+
+    #[test]
+    fn too_many_globals_fail_to_instantiate() {
+        assert!(instantiate("(module (global i32 (i32.const 0)) (global i32 (i32.const 1)))").is_ok());
+
+        let error = instantiate("(module (global i32 (i32.const 0)) (global i32 (i32.const 1)) (global i32 (i32.const 2)))")
+            .err()
+            .unwrap();
+        assert!(error.contains("3 globals exceed the allowed limit of 2"), "{}", error);
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt;
+use std::sync::Mutex;
+
+use wasmer::wasmparser::Operator;
+use wasmer::{
+    ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::ModuleInfo;
+
+pub const MEMORY_REFUSED_PAGES_EXPORT: &str = "opvm_memory_refused_pages";
+
+#[derive(Clone, Copy, Debug)]
+struct MemoryGrowthGlobalIndexes {
+    requested: u32,
+    result: u32,
+    refused: u32,
+}
+
+/// Records the page delta of the last `memory.grow` the tunables refused.
+///
+/// After every `memory.grow`, the exported `opvm_memory_refused_pages` global holds the
+/// requested delta if the instruction returned -1, and 0 if it succeeded, so the host can
+/// tell an allocation failure apart from any other trap. Must be pushed *after* the
+/// `Metering` middleware, the injected instructions are not charged.
+///
+/// A refusal only explains a trap that follows it before the contract moves on. The
+/// global is cleared around every call, so a contract that handled the -1 and called or
+/// returned from anything since does not have its later, unrelated traps reported as out
+/// of memory. The AssemblyScript allocator traps right after a refused grow.
+pub struct MemoryGrowthTracker {
+    global_indexes: Mutex<Option<MemoryGrowthGlobalIndexes>>,
+}
+
+impl Default for MemoryGrowthTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemoryGrowthTracker {
+    pub fn new() -> Self {
+        Self {
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryGrowthTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryGrowthTracker")
+            .field("global_indexes", &self.global_indexes)
+            .finish()
+    }
+}
+
+impl ModuleMiddleware for MemoryGrowthTracker {
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let global_indexes = self.global_indexes.lock().unwrap().unwrap();
+
+        Box::new(FunctionMemoryGrowthTracker { global_indexes })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+
+        if global_indexes.is_some() {
+            return Err(MiddlewareError::new(
+                "MemoryGrowthTracker",
+                "Attempting to use a `MemoryGrowthTracker` middleware from multiple modules.",
+            ));
+        }
+
+        let mut push_global = || {
+            let index = module_info
+                .globals
+                .push(GlobalType::new(Type::I32, Mutability::Var));
+            module_info.global_initializers.push(GlobalInit::I32Const(0));
+
+            index
+        };
+
+        let requested = push_global();
+        let result = push_global();
+        let refused = push_global();
+
+        module_info.exports.insert(
+            MEMORY_REFUSED_PAGES_EXPORT.to_string(),
+            ExportIndex::Global(refused),
+        );
+
+        *global_indexes = Some(MemoryGrowthGlobalIndexes {
+            requested: requested.as_u32(),
+            result: result.as_u32(),
+            refused: refused.as_u32(),
+        });
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct FunctionMemoryGrowthTracker {
+    global_indexes: MemoryGrowthGlobalIndexes,
+}
+
+impl FunctionMiddleware for FunctionMemoryGrowthTracker {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let MemoryGrowthGlobalIndexes { requested, result, refused } = self.global_indexes;
+
+        match operator {
+            Operator::MemoryGrow { .. } => {
+                // globals[requested] = delta
+                state.extend(&[
+                    Operator::GlobalSet { global_index: requested },
+                    Operator::GlobalGet { global_index: requested },
+                ]);
+                state.push_operator(operator);
+
+                // globals[refused] = globals[result] == -1 ? globals[requested] : 0
+                state.extend(&[
+                    Operator::GlobalSet { global_index: result },
+                    Operator::GlobalGet { global_index: requested },
+                    Operator::I32Const { value: 0 },
+                    Operator::GlobalGet { global_index: result },
+                    Operator::I32Const { value: -1 },
+                    Operator::I32Eq,
+                    Operator::Select,
+                    Operator::GlobalSet { global_index: refused },
+                    Operator::GlobalGet { global_index: result },
+                ]);
+            }
+            Operator::Call { .. } | Operator::CallIndirect { .. } => {
+                // globals[refused] = 0, before and after the call
+                state.extend(&[Operator::I32Const { value: 0 }, Operator::GlobalSet { global_index: refused }]);
+                state.push_operator(operator);
+                state.extend(&[Operator::I32Const { value: 0 }, Operator::GlobalSet { global_index: refused }]);
+            }
+            _ => state.push_operator(operator),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_the_refused_pages_global() {
+        let mut module_info = ModuleInfo::new();
+        module_info.globals.push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info.global_initializers.push(GlobalInit::I32Const(0));
+
+        let tracker = MemoryGrowthTracker::new();
+        tracker.transform_module_info(&mut module_info).unwrap();
+
+        assert_eq!(module_info.globals.len(), 4);
+        assert!(matches!(
+            module_info.exports.get(MEMORY_REFUSED_PAGES_EXPORT),
+            Some(ExportIndex::Global(index)) if index.as_u32() == 3
+        ));
+        assert!(tracker.transform_module_info(&mut ModuleInfo::new()).is_err());
+    }
+}
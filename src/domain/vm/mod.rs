@@ -1,7 +1,27 @@
+pub use self::bytecode_validator::*;
+pub use self::debug_instrumentation::*;
+pub use self::debug_symbols::*;
+pub use self::deferred_start::*;
 pub use self::gas_costs::*;
+pub use self::gas_profiler::*;
+pub use self::global_exports::*;
+pub use self::import_policy::*;
 pub use self::limiting_tunables::*;
 pub use self::logger::*;
+pub use self::memory_growth_tracker::*;
+pub use self::module_limits::*;
+pub use self::module_scoped::*;
 
+mod bytecode_validator;
+mod debug_instrumentation;
+mod debug_symbols;
+mod deferred_start;
 mod gas_costs;
+mod gas_profiler;
+mod global_exports;
+mod import_policy;
 mod limiting_tunables;
 mod logger;
+mod memory_growth_tracker;
+mod module_limits;
+mod module_scoped;
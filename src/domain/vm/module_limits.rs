@@ -0,0 +1,228 @@
+use wasmer::wasmparser::{BinaryReaderError, FunctionBody, Operator, Parser, Payload, TableType, TypeRef};
+
+use crate::domain::vm::{ValidationReport, ViolationKind};
+
+/// Structural bounds checked before a module is compiled, so that a hostile
+/// deployment cannot make Singlepass compilation itself expensive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModuleLimits {
+    pub max_bytecode_size: usize,
+    pub max_functions: u32,
+    pub max_locals_per_function: u32,
+    pub max_globals: u32,
+    pub max_data_segments: u32,
+    pub max_table_size: u32,
+    pub max_nesting_depth: u32,
+}
+
+impl Default for ModuleLimits {
+    fn default() -> Self {
+        Self {
+            max_bytecode_size: 2 * 1024 * 1024, // 2 MiB
+            max_functions: 10_000,
+            max_locals_per_function: 1_024,
+            max_globals: 1_024,
+            max_data_segments: 4_096,
+            max_table_size: 10_000,
+            max_nesting_depth: 1_024,
+        }
+    }
+}
+
+impl ModuleLimits {
+    pub fn check(&self, bytecode: &[u8]) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        if bytecode.len() > self.max_bytecode_size {
+            report.push(
+                ViolationKind::LimitExceeded,
+                format!("bytecode is {} bytes, at most {} allowed", bytecode.len(), self.max_bytecode_size),
+                0,
+            );
+
+            // Do not even parse oversized modules.
+            return report;
+        }
+
+        if let Err(e) = self.walk(bytecode, &mut report) {
+            report.push(ViolationKind::InvalidModule, e.message().to_string(), e.offset());
+        }
+
+        report
+    }
+
+    fn walk(&self, bytecode: &[u8], report: &mut ValidationReport) -> Result<(), BinaryReaderError> {
+        let mut functions: u32 = 0;
+        let mut globals: u32 = 0;
+        let mut function_index: u32 = 0;
+
+        for payload in Parser::new(0).parse_all(bytecode) {
+            match payload? {
+                Payload::ImportSection(reader) => {
+                    for import in reader {
+                        match import?.ty {
+                            TypeRef::Func(_) => functions += 1,
+                            TypeRef::Global(_) => globals += 1,
+                            _ => {}
+                        }
+                    }
+                    function_index = functions;
+                }
+                Payload::FunctionSection(reader) => {
+                    functions = functions.saturating_add(reader.count());
+                    Self::check_count(report, "functions", functions, self.max_functions, reader.range().start);
+                }
+                Payload::GlobalSection(reader) => {
+                    globals = globals.saturating_add(reader.count());
+                    Self::check_count(report, "globals", globals, self.max_globals, reader.range().start);
+                }
+                Payload::DataSection(reader) => {
+                    Self::check_count(report, "data segments", reader.count(), self.max_data_segments, reader.range().start);
+                }
+                Payload::TableSection(reader) => {
+                    for table in reader.into_iter_with_offsets() {
+                        let (offset, table) = table?;
+                        self.check_table(report, &table.ty, offset);
+                    }
+                }
+                Payload::CodeSectionEntry(body) => {
+                    self.check_function_body(report, function_index, &body)?;
+                    function_index += 1;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_table(&self, report: &mut ValidationReport, table: &TableType, offset: usize) {
+        let size = table.maximum.unwrap_or(table.initial);
+        if size > self.max_table_size {
+            report.push(
+                ViolationKind::LimitExceeded,
+                format!("table declares {} elements, at most {} allowed", size, self.max_table_size),
+                offset,
+            );
+        }
+    }
+
+    fn check_function_body(
+        &self,
+        report: &mut ValidationReport,
+        function_index: u32,
+        body: &FunctionBody,
+    ) -> Result<(), BinaryReaderError> {
+        let mut locals_reader = body.get_locals_reader()?;
+        let mut locals: u32 = 0;
+        for _ in 0..locals_reader.get_count() {
+            let (count, _) = locals_reader.read()?;
+            locals = locals.saturating_add(count);
+        }
+
+        if locals > self.max_locals_per_function {
+            report.push(
+                ViolationKind::LimitExceeded,
+                format!(
+                    "function {} declares {} locals, at most {} allowed",
+                    function_index, locals, self.max_locals_per_function
+                ),
+                body.range().start,
+            );
+        }
+
+        let mut depth: u32 = 0;
+        let mut operators = body.get_operators_reader()?;
+        while !operators.eof() {
+            let (operator, offset) = operators.read_with_offset()?;
+            match operator {
+                Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } | Operator::Try { .. } => {
+                    depth += 1;
+                    if depth > self.max_nesting_depth {
+                        report.push(
+                            ViolationKind::LimitExceeded,
+                            format!(
+                                "function {} nests blocks deeper than {}",
+                                function_index, self.max_nesting_depth
+                            ),
+                            offset,
+                        );
+
+                        // One report per function is enough.
+                        return Ok(());
+                    }
+                }
+                Operator::End => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_count(report: &mut ValidationReport, what: &str, count: u32, max: u32, offset: usize) {
+        if count > max {
+            report.push(
+                ViolationKind::LimitExceeded,
+                format!("module declares {} {}, at most {} allowed", count, what, max),
+                offset,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(limits: ModuleLimits, wat: &str) -> ValidationReport {
+        let bytecode = wasmer::wat2wasm(wat.as_bytes()).unwrap();
+        limits.check(&bytecode)
+    }
+
+    #[test]
+    fn accepts_modules_within_limits() {
+        let report = check(
+            ModuleLimits::default(),
+            r#"(module
+                (table 1 1 funcref)
+                (func (local i32 i64) (block (loop (br 1)))))"#,
+        );
+
+        assert!(report.is_valid(), "{:?}", report);
+    }
+
+    #[test]
+    fn rejects_oversized_bytecode() {
+        let limits = ModuleLimits { max_bytecode_size: 4, ..Default::default() };
+
+        let report = check(limits, "(module)");
+
+        assert_eq!(report.violations.len(), 1);
+        assert!(report.violations[0].message.contains("bytes"));
+    }
+
+    #[test]
+    fn rejects_too_many_functions_and_locals() {
+        let limits = ModuleLimits { max_functions: 1, max_locals_per_function: 2, ..Default::default() };
+
+        let report = check(limits, "(module (func (local i32 i32 i32)) (func))");
+
+        let messages: Vec<&str> = report.violations.iter().map(|violation| violation.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("2 functions"));
+        assert!(messages[1].contains("3 locals"));
+    }
+
+    #[test]
+    fn rejects_deep_nesting_and_large_tables() {
+        let limits = ModuleLimits { max_nesting_depth: 2, max_table_size: 8, ..Default::default() };
+
+        let report = check(limits, "(module (table 1 16 funcref) (func (block (block (block)))))");
+
+        let messages: Vec<&str> = report.violations.iter().map(|violation| violation.message.as_str()).collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("table"));
+        assert!(messages[1].contains("deeper than 2"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use wasmer::{FunctionMiddleware, LocalFunctionIndex, MiddlewareError, ModuleMiddleware};
+use wasmer_types::ModuleInfo;
+
+type MiddlewareFactory<M> = Box<dyn Fn() -> M + Send + Sync>;
+
+/// Creates a fresh instance of a stateful middleware for every compiled module.
+///
+/// Middlewares such as `Metering` remember the globals they injected into the module
+/// they transformed and panic when reused, which would tie every compiled module to its
+/// own engine. Wasmer holds the engine lock for the whole compilation, so the current
+/// instance cannot be replaced while the functions of a module are being generated.
+pub struct ModuleScoped<M: ModuleMiddleware> {
+    factory: MiddlewareFactory<M>,
+    current: Mutex<Option<Arc<M>>>,
+}
+
+impl<M: ModuleMiddleware> ModuleScoped<M> {
+    pub fn new(factory: impl Fn() -> M + Send + Sync + 'static) -> Self {
+        Self {
+            factory: Box::new(factory),
+            current: Mutex::new(None),
+        }
+    }
+}
+
+impl<M: ModuleMiddleware> fmt::Debug for ModuleScoped<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ModuleScoped")
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+impl<M: ModuleMiddleware> ModuleMiddleware for ModuleScoped<M> {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let current = self
+            .current
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("ModuleScoped::generate_function_middleware called before transform_module_info");
+
+        current.generate_function_middleware(local_function_index)
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let middleware = Arc::new((self.factory)());
+        middleware.transform_module_info(module_info)?;
+
+        *self.current.lock().unwrap() = Some(middleware);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use napi::bindgen_prelude::Buffer;
+
+use crate::domain::runner::AccessList;
+
+/// Accesses a call declares up front. Declared storage keys are prefetched and charged
+/// the warm price.
+#[napi(object)]
+pub struct AccessListRequest {
+    /// 32 byte storage pointers.
+    pub storage_keys: Option<Vec<Buffer>>,
+    /// Addresses of the contracts, as `encodeAddress` returns them without the trailing NUL.
+    pub addresses: Option<Vec<Buffer>>,
+    /// Fail any access outside the list.
+    pub strict: Option<bool>,
+}
+
+impl TryFrom<AccessListRequest> for AccessList {
+    type Error = String;
+
+    fn try_from(request: AccessListRequest) -> Result<Self, Self::Error> {
+        AccessList::new(
+            request.storage_keys.unwrap_or_default().into_iter().map(|key| key.to_vec()),
+            request.addresses.unwrap_or_default().into_iter().map(|address| address.to_vec()),
+            request.strict.unwrap_or(false),
+        )
+    }
+}
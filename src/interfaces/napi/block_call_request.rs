@@ -0,0 +1,8 @@
+use napi::bindgen_prelude::BigInt;
+
+#[napi(object)]
+pub struct BlockCallRequest {
+    pub contract_id: BigInt,
+    pub func_name: String,
+    pub params: Vec<i32>,
+}
@@ -0,0 +1,12 @@
+use napi::bindgen_prelude::{Array, BigInt};
+
+#[napi(object)]
+pub struct BlockCallResponse {
+    #[napi(ts_type = "number[] | undefined")]
+    pub result: Option<Array>,
+    /// Why the call failed, in which case `result` is not set.
+    pub error: Option<String>,
+    pub gas_used: BigInt,
+    /// The speculative run conflicted with an earlier call and the call ran again.
+    pub re_executed: bool,
+}
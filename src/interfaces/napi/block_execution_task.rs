@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local};
+use napi::bindgen_prelude::BigInt;
+use napi::{Env, Error, JsObject};
+use wasmer::Value;
+
+use crate::application::contract::{BlockExecutor, BlockOutcome, ContractService, Execution};
+use crate::domain::runner::{InstanceSnapshot, StorageAccessLog, StorageOverlay, WasmerRunner};
+use crate::domain::vm::log_time_diff;
+use crate::interfaces::napi::js_contract::{JsContract, JsContractEnvironment};
+use crate::interfaces::{BlockCallResponse, HostRuntime};
+
+/// A call of a block, run on its own instance of the contract, in the state the contract
+/// was in when the call was prepared, e.g. with its calldata written.
+pub struct BlockCall {
+    runner: Arc<Mutex<WasmerRunner>>,
+    snapshot: InstanceSnapshot,
+    environment: JsContractEnvironment,
+    max_gas: u64,
+    func_name: String,
+    wasm_params: Vec<Value>,
+}
+
+impl BlockCall {
+    pub fn new(
+        runner: Arc<Mutex<WasmerRunner>>,
+        snapshot: InstanceSnapshot,
+        environment: JsContractEnvironment,
+        max_gas: u64,
+        func_name: &str,
+        wasm_params: &[Value],
+    ) -> Self {
+        Self {
+            runner,
+            snapshot,
+            environment,
+            max_gas,
+            func_name: func_name.to_string(),
+            wasm_params: wasm_params.to_vec(),
+        }
+    }
+
+    /// A speculative run buffers its writes and fails any nested call or deployment.
+    fn run(&self, speculative: bool) -> anyhow::Result<Execution<BlockCallOutcome>> {
+        let mut custom_env = self
+            .environment
+            .create_custom_env()
+            .map_err(|e| anyhow::anyhow!(e.reason))?;
+        custom_env.access_log = Some(Mutex::new(StorageAccessLog::default()));
+        if speculative {
+            custom_env.storage_overlay = Some(Mutex::new(StorageOverlay::default()));
+            custom_env.speculative = true;
+        }
+
+        let runner = {
+            let runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+            Arc::new(Mutex::new(runner.fork_from(&self.snapshot, self.max_gas, custom_env)?))
+        };
+
+        let mut contract = ContractService::new(self.max_gas, runner.clone());
+        let result = contract
+            .call(&self.func_name, &self.wasm_params)
+            .map_err(|e| format!("{:?}", e));
+        let gas_used = contract.get_used_gas();
+
+        let access_log = runner
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Failed to lock runner"))?
+            .take_storage_access_log()
+            .unwrap_or_default();
+
+        Ok(Execution {
+            outcome: BlockCallOutcome { result, gas_used },
+            access_log,
+        })
+    }
+
+    fn commit(&self, access_log: &StorageAccessLog) -> anyhow::Result<()> {
+        for (pointer, value) in access_log.writes() {
+            self.environment
+                .store(&[pointer.as_slice(), value.as_slice()].concat())
+                .map_err(|e| anyhow::anyhow!(e.reason))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct BlockCallOutcome {
+    result: Result<Box<[Value]>, String>,
+    gas_used: u64,
+}
+
+/// The calls of a block, coordinated from a continuation thread of the [`HostRuntime`]
+/// while the speculations run on threads of their own.
+pub struct BlockExecutionTask {
+    calls: Vec<BlockCall>,
+    parallelism: usize,
+    time: DateTime<Local>,
+}
+
+impl BlockExecutionTask {
+    pub fn new(calls: Vec<BlockCall>, parallelism: usize, time: DateTime<Local>) -> Self {
+        Self {
+            calls,
+            parallelism,
+            time,
+        }
+    }
+
+    /// Starts the block and returns a promise of its `BlockCallResponse`s.
+    pub fn spawn(self, env: &Env, runtime: &HostRuntime) -> napi::Result<JsObject> {
+        let Self { calls, parallelism, time } = self;
+
+        let execution = runtime.spawn_call(move || {
+            let scopes: Vec<&str> = calls.iter().map(|call| call.environment.address()).collect();
+
+            BlockExecutor::new(parallelism).execute(
+                &scopes,
+                |index| calls[index].run(true),
+                |index, access_log| calls[index].commit(access_log),
+                |index| calls[index].run(false),
+            )
+        });
+
+        env.execute_tokio_future(
+            async move {
+                execution
+                    .await
+                    .and_then(|outcomes: anyhow::Result<Vec<BlockOutcome<BlockCallOutcome>>>| outcomes)
+                    .map_err(|e| Error::from_reason(format!("{:?}", e)))
+            },
+            move |env, output| {
+                let responses = output
+                    .into_iter()
+                    .map(|outcome| {
+                        let BlockCallOutcome { result, gas_used } = outcome.outcome;
+                        let (result, error) = match result {
+                            Ok(values) => (Some(JsContract::box_values_to_js_array(env, values)?), None),
+                            Err(error) => (None, Some(error)),
+                        };
+
+                        Ok(BlockCallResponse {
+                            result,
+                            error,
+                            gas_used: BigInt::from(gas_used),
+                            re_executed: outcome.re_executed,
+                        })
+                    })
+                    .collect::<napi::Result<Vec<_>>>()?;
+
+                log_time_diff(&time, format!("ContractManager::execute_block: {} calls", responses.len()).as_str());
+
+                Ok(responses)
+            },
+        )
+    }
+}
@@ -0,0 +1,20 @@
+use crate::domain::runner::Breakpoint;
+
+/// Either a function, stopped at on entry, or a module offset, e.g. from a source map.
+#[napi(object)]
+pub struct BreakpointRequest {
+    pub function: Option<String>,
+    pub offset: Option<u32>,
+}
+
+impl TryFrom<BreakpointRequest> for Breakpoint {
+    type Error = String;
+
+    fn try_from(request: BreakpointRequest) -> Result<Self, Self::Error> {
+        match (request.function, request.offset) {
+            (Some(function), None) => Ok(Breakpoint::Function(function)),
+            (None, Some(offset)) => Ok(Breakpoint::Offset(offset as usize)),
+            _ => Err("a breakpoint needs either a function or an offset".to_string()),
+        }
+    }
+}
@@ -1,9 +1,21 @@
 use crate::interfaces::napi::bitcoin_network_request::BitcoinNetworkRequest;
-use bytes::Bytes;
+use crate::domain::runner::{ExecutionLimits, WasmerRunner};
+use crate::domain::vm::ModuleLimits;
+use wasmer::Module;
 
 pub struct JsContractParameter {
+    pub(crate) address: String,
     pub(crate) bytecode: Option<Vec<u8>>,
-    pub(crate) serialized: Option<Bytes>,
+    pub(crate) module: Option<Module>,
+    /// Hash of the bytecode `module` was compiled from.
+    pub(crate) code_hash: Option<[u8; 32]>,
+    pub(crate) runner: Option<WasmerRunner>,
     pub(crate) max_gas: u64,
+    /// Charged on top of the constructor when the contract is being deployed, 0 otherwise.
+    pub(crate) deployment_gas: u64,
     pub(crate) network: BitcoinNetworkRequest,
+    pub(crate) gas_profiling: bool,
+    pub(crate) debugging: bool,
+    pub(crate) module_limits: ModuleLimits,
+    pub(crate) execution_limits: ExecutionLimits,
 }
@@ -0,0 +1,14 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::interfaces::EvictionPolicyRequest;
+
+/// Budget and eviction policy of the in-memory compiled contract cache.
+#[napi(object)]
+pub struct ContractCacheRequest {
+    /// In bytes of serialized artifacts, defaults to 512 MiB.
+    pub max_size: Option<BigInt>,
+    /// Defaults to LRU.
+    pub policy: Option<EvictionPolicyRequest>,
+    /// Idle instances kept per compiled module for reuse, defaults to 4.
+    pub max_pooled_instances: Option<u32>,
+}
@@ -0,0 +1,28 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::domain::runner::ContractCacheStats;
+
+#[napi(object)]
+pub struct ContractCacheStatsResponse {
+    pub hits: BigInt,
+    pub misses: BigInt,
+    pub evictions: BigInt,
+    pub entries: u32,
+    pub pinned: u32,
+    pub size: BigInt,
+    pub max_size: BigInt,
+}
+
+impl From<ContractCacheStats> for ContractCacheStatsResponse {
+    fn from(stats: ContractCacheStats) -> Self {
+        ContractCacheStatsResponse {
+            hits: BigInt::from(stats.hits),
+            misses: BigInt::from(stats.misses),
+            evictions: BigInt::from(stats.evictions),
+            entries: stats.entries as u32,
+            pinned: stats.pinned as u32,
+            size: BigInt::from(stats.size),
+            max_size: BigInt::from(stats.max_size),
+        }
+    }
+}
@@ -1,64 +1,73 @@
 use std::sync::{Arc, Mutex};
 
 use chrono::{DateTime, Local};
-use napi::{Env, Error, Task};
 use napi::bindgen_prelude::BigInt;
+use napi::{Env, Error, JsObject};
 use wasmer::Value;
 
 use crate::application::contract::ContractService;
+use crate::domain::runner::CallOptions;
 use crate::domain::vm::log_time_diff;
-use crate::interfaces::CallResponse;
 use crate::interfaces::napi::js_contract::JsContract;
+use crate::interfaces::{CallResponse, HostRuntime};
 
+/// A contract call run on a continuation thread of the [`HostRuntime`], so that no thread
+/// of the libuv pool waits for it while its host imports go back and forth to JS.
 pub struct ContractCallTask {
     contract: Arc<Mutex<ContractService>>,
     func_name: String,
     wasm_params: Vec<Value>,
+    options: CallOptions,
     time: DateTime<Local>,
 }
 
 impl ContractCallTask {
-    pub fn new(contract: Arc<Mutex<ContractService>>, func_name: &str, wasm_params: &[Value], time: DateTime<Local>) -> Self {
+    pub fn new(
+        contract: Arc<Mutex<ContractService>>,
+        func_name: &str,
+        wasm_params: &[Value],
+        options: CallOptions,
+        time: DateTime<Local>,
+    ) -> Self {
         Self {
             contract,
             func_name: func_name.to_string(),
             wasm_params: wasm_params.to_vec(),
+            options,
             time,
         }
     }
-}
-
-impl Task for ContractCallTask {
-    type Output = Box<[Value]>;
-    type JsValue = CallResponse;
 
-    fn compute(&mut self) -> napi::Result<Self::Output> {
-        let mut contract = self.contract.lock().unwrap();
-
-        contract
-            .call(&self.func_name, &self.wasm_params)
-            .map_err(|e| Error::from_reason(format!("{:?}", e)))
-    }
+    /// Starts the call and returns a promise of its `CallResponse`.
+    pub fn spawn(self, env: &Env, runtime: &HostRuntime) -> napi::Result<JsObject> {
+        let Self { contract, func_name, wasm_params, options, time } = self;
 
-    fn resolve(&mut self, env: Env, output: Self::Output) -> napi::Result<Self::JsValue> {
-        let js_array = JsContract::box_values_to_js_array(&env, output)?;
-        let gas_used = self.contract.lock().unwrap().get_used_gas();
+        let call = {
+            let func_name = func_name.clone();
+            runtime.spawn_call(move || {
+                let mut contract = contract.lock().unwrap();
+                let result = contract.call_with_options(&func_name, &wasm_params, options)?;
 
-        let gas_used_bigint: BigInt = BigInt::from(gas_used);
+                Ok((result, contract.get_used_gas()))
+            })
+        };
 
-        log_time_diff(&self.time, format!("JsContract::call: {}", self.func_name).as_str());
+        env.execute_tokio_future(
+            async move {
+                call.await
+                    .and_then(|result: anyhow::Result<(Box<[Value]>, u64)>| result)
+                    .map_err(|e| Error::from_reason(format!("{:?}", e)))
+            },
+            move |env, (result, gas_used)| {
+                let js_array = JsContract::box_values_to_js_array(env, result)?;
 
-        Ok(CallResponse {
-            result: js_array,
-            gas_used: gas_used_bigint,
-        })
-    }
-
-    fn reject(&mut self, _env: Env, err: Error) -> napi::Result<Self::JsValue> {
-        Err(err)
-    }
+                log_time_diff(&time, format!("JsContract::call: {}", func_name).as_str());
 
-    fn finally(&mut self, _env: Env) -> napi::Result<()> {
-        Ok(())
+                Ok(CallResponse {
+                    result: js_array,
+                    gas_used: BigInt::from(gas_used),
+                })
+            },
+        )
     }
 }
@@ -0,0 +1,70 @@
+use wasmer::Value;
+
+use crate::domain::runner::DebugStop;
+use crate::domain::vm::ProbeEvent;
+
+#[napi(object)]
+pub struct DebugValueResponse {
+    pub name: String,
+    pub value_type: String,
+    /// Decimal, so 64 bit integers survive the trip to JS.
+    pub value: String,
+}
+
+#[napi(object)]
+pub struct DebugStopResponse {
+    pub function_index: u32,
+    pub function: String,
+    pub position: u32,
+    pub offset: Option<u32>,
+    /// `enter`, `block` or `leave`.
+    pub event: String,
+    pub locals: Vec<DebugValueResponse>,
+    pub globals: Vec<DebugValueResponse>,
+}
+
+impl DebugValueResponse {
+    fn new(name: String, value: &Value) -> Self {
+        let (value_type, value) = match value {
+            Value::I32(value) => ("i32", value.to_string()),
+            Value::I64(value) => ("i64", value.to_string()),
+            Value::F32(value) => ("f32", value.to_string()),
+            Value::F64(value) => ("f64", value.to_string()),
+            value => ("unknown", format!("{:?}", value)),
+        };
+
+        DebugValueResponse {
+            name,
+            value_type: value_type.to_string(),
+            value,
+        }
+    }
+}
+
+impl From<DebugStop> for DebugStopResponse {
+    fn from(stop: DebugStop) -> Self {
+        let event = match stop.event {
+            ProbeEvent::Enter => "enter",
+            ProbeEvent::Block => "block",
+            ProbeEvent::Leave => "leave",
+        };
+
+        DebugStopResponse {
+            function_index: stop.function_index,
+            function: stop.function,
+            position: stop.position,
+            offset: stop.offset.map(|offset| offset as u32),
+            event: event.to_string(),
+            locals: stop
+                .locals
+                .iter()
+                .map(|(index, value)| DebugValueResponse::new(format!("local{}", index), value))
+                .collect(),
+            globals: stop
+                .globals
+                .iter()
+                .map(|(name, value)| DebugValueResponse::new(name.clone(), value))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,16 @@
+use crate::domain::runner::EvictionPolicy;
+
+#[napi]
+pub enum EvictionPolicyRequest {
+    Lru,
+    Lfu,
+}
+
+impl From<EvictionPolicyRequest> for EvictionPolicy {
+    fn from(request: EvictionPolicyRequest) -> Self {
+        match request {
+            EvictionPolicyRequest::Lru => EvictionPolicy::Lru,
+            EvictionPolicyRequest::Lfu => EvictionPolicy::Lfu,
+        }
+    }
+}
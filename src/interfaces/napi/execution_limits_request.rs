@@ -0,0 +1,19 @@
+use crate::domain::runner::ExecutionLimits;
+
+/// Memory and stack limits of a contract instance, unset fields fall back to `defaults`.
+#[napi(object)]
+pub struct ExecutionLimitsRequest {
+    /// In 64 KiB pages.
+    pub max_pages: Option<u32>,
+    /// In bytes.
+    pub stack_size: Option<u32>,
+}
+
+impl ExecutionLimitsRequest {
+    pub fn into_limits(self, defaults: ExecutionLimits) -> ExecutionLimits {
+        ExecutionLimits {
+            max_pages: self.max_pages.unwrap_or(defaults.max_pages),
+            stack_size: self.stack_size.map(|size| size as usize).unwrap_or(defaults.stack_size),
+        }
+    }
+}
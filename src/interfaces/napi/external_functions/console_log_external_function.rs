@@ -4,9 +4,10 @@ use wasmer::RuntimeError;
 
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 
+type LogFunction = Box<dyn Fn(&[u8]) -> Result<(), RuntimeError> + Send + Sync>;
+
 pub struct ConsoleLogExternalFunction {
-    tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
-    id: u64,
+    log: LogFunction,
 }
 
 impl ConsoleLogExternalFunction {
@@ -14,23 +15,36 @@ impl ConsoleLogExternalFunction {
         tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
         id: u64,
     ) -> Self {
-        Self { tsfn, id }
-    }
-}
+        Self {
+            log: Box::new(move |data| {
+                let request = ThreadSafeJsImportResponse {
+                    buffer: Vec::from(data),
+                    contract_id: BigInt::from(id),
+                    is_static: false,
+                    is_simulation: false,
+                };
 
-impl ConsoleLogExternalFunction {
-    pub(crate) fn execute(&self, data: &[u8]) -> Result<(), RuntimeError> {
-        let request = ThreadSafeJsImportResponse {
-            buffer: Vec::from(data),
-            contract_id: BigInt::from(self.id),
-        };
+                //let time = chrono::offset::Local::now();
 
-        //let time = chrono::offset::Local::now();
+                tsfn.call(Ok(request), ThreadsafeFunctionCallMode::NonBlocking);
 
-        self.tsfn.call(Ok(request), ThreadsafeFunctionCallMode::NonBlocking);
+                //log_time_diff(&time, "GenericExternalFunction::log");
 
-        //log_time_diff(&time, "GenericExternalFunction::log");
+                Ok(())
+            }),
+        }
+    }
 
-        Ok(())
+    #[cfg(test)]
+    pub fn native(function: super::NativeHostFunction) -> Self {
+        Self {
+            log: Box::new(move |data| function(data, super::HostRequestMode::default()).map(|_| ())),
+        }
+    }
+}
+
+impl ConsoleLogExternalFunction {
+    pub(crate) fn execute(&self, data: &[u8]) -> Result<(), RuntimeError> {
+        (self.log)(data)
     }
 }
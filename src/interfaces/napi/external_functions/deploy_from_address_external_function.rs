@@ -1,8 +1,11 @@
+use std::time::Instant;
+
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
-use tokio::runtime::Runtime;
 use wasmer::RuntimeError;
 
-use crate::interfaces::napi::external_functions::GenericExternalFunction;
+use crate::domain::runner::CancellationToken;
+use crate::interfaces::napi::external_functions::{GenericExternalFunction, HostRequestMode};
+use crate::interfaces::napi::host_runtime::HostRuntime;
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 use crate::interfaces::ExternalFunction;
 
@@ -16,15 +19,41 @@ impl DeployFromAddressExternalFunction {
         id: u64,
     ) -> Self {
         Self {
-            external_function: GenericExternalFunction::new(tsfn, id),
+            external_function: GenericExternalFunction::nested(tsfn, id),
         }
     }
+
+    #[cfg(test)]
+    pub fn native(function: super::NativeHostFunction) -> Self {
+        Self {
+            external_function: GenericExternalFunction::native(function),
+        }
+    }
+
+    /// Deploys the contract the way `mode` says, a simulated deployment commits nothing.
+    pub fn execute_deploy(
+        &self,
+        data: &[u8],
+        mode: HostRequestMode,
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        self.external_function
+            .execute_with_mode(data, mode, runtime, deadline, cancellation)
+    }
 }
 
 impl ExternalFunction for DeployFromAddressExternalFunction {
-    fn execute(&self, data: &[u8], runtime: &Runtime) -> Result<Vec<u8>, RuntimeError> {
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
         //let time = chrono::offset::Local::now();
-        let resp = self.external_function.execute(data, runtime);
+        let resp = self.external_function.execute(data, runtime, deadline, cancellation);
 
         //log_time_diff(&time, "GenericExternalFunction::deploy");
 
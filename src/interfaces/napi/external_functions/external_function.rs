@@ -1,6 +1,19 @@
-use tokio::runtime::Runtime;
+use std::time::Instant;
+
 use wasmer::RuntimeError;
 
+use crate::domain::runner::CancellationToken;
+use crate::interfaces::napi::host_runtime::HostRuntime;
+
 pub trait ExternalFunction {
-    fn execute(&self, data: &[u8], runtime: &Runtime) -> Result<Vec<u8>, RuntimeError>;
+    /// Fails with [`crate::domain::runner::ExecutionTimeout`] if JS has not answered by
+    /// `deadline`, and with [`crate::domain::runner::ExecutionCancelled`] once `cancellation`
+    /// is cancelled.
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError>;
 }
@@ -1,35 +1,69 @@
+use std::pin::pin;
+use std::time::Instant;
+
+use futures::future::{select, Either};
 use napi::bindgen_prelude::{BigInt, Buffer, Promise};
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
-use tokio::runtime::Runtime;
 use wasmer::RuntimeError;
 
+use crate::domain::runner::{CancellationToken, ExecutionCancelled, ExecutionTimeout};
+use crate::interfaces::napi::host_runtime::HostRuntime;
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 use crate::interfaces::ExternalFunction;
 
-pub struct GenericExternalFunction {
-    tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
-    contract_id: u64,
+/// How the call making a request runs, which the host must honour when it answers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostRequestMode {
+    /// The call is static, a nested call must be static too.
+    pub is_static: bool,
+    /// The call only estimates its gas, nested calls and deployments must not commit anything.
+    pub is_simulation: bool,
 }
 
-impl GenericExternalFunction {
-    pub fn new(
-        tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
-        contract_id: u64,
-    ) -> Self {
-        Self { tsfn, contract_id }
-    }
+/// A host import answered in Rust instead of JS, by a test. Gets the request and how the
+/// call making it runs.
+#[cfg(test)]
+pub type NativeHostFunction = std::sync::Arc<dyn Fn(&[u8], HostRequestMode) -> Result<Vec<u8>, RuntimeError> + Send + Sync>;
+
+/// Where the requests of a host import go. Kept behind a trait object so code that only
+/// uses native hosts never links the Node-API.
+trait HostBackend: Send + Sync {
+    fn request(
+        &self,
+        data: &[u8],
+        mode: HostRequestMode,
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError>;
 }
 
-impl ExternalFunction for GenericExternalFunction {
-    fn execute(&self, data: &[u8], runtime: &Runtime) -> Result<Vec<u8>, RuntimeError> {
+struct JsHostBackend {
+    tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
+    contract_id: u64,
+    /// Whether answering runs other contracts, see [`HostRuntime::run_nested`].
+    nested: bool,
+}
+
+impl HostBackend for JsHostBackend {
+    fn request(
+        &self,
+        data: &[u8],
+        mode: HostRequestMode,
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
         let request = ThreadSafeJsImportResponse {
             buffer: Vec::from(data),
             contract_id: BigInt::from(self.contract_id),
+            is_static: mode.is_static,
+            is_simulation: mode.is_simulation,
         };
 
+        let tsfn = self.tsfn.clone();
         let deploy = async move {
-            let response: Result<Promise<Buffer>, RuntimeError> = self
-                .tsfn
+            let response: Result<Promise<Buffer>, RuntimeError> = tsfn
                 .call_async(Ok(request))
                 .await
                 .map_err(|e| RuntimeError::new(e.reason));
@@ -40,13 +74,116 @@ impl ExternalFunction for GenericExternalFunction {
                 .await
                 .map_err(|e| RuntimeError::new(e.reason))?;
 
-            let data = data.to_vec();
+            Ok(data.to_vec())
+        };
 
-            Ok(data.into())
+        let bounded = async move {
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), deploy)
+                    .await
+                    .unwrap_or_else(|_| Err(RuntimeError::user(Box::new(ExecutionTimeout)))),
+                None => deploy.await,
+            }
         };
 
-        let response = runtime.block_on(deploy);
+        // The JS promise keeps running when the call is cancelled, its result is dropped.
+        let cancellation = cancellation.cloned();
+        let cancellable = async move {
+            match cancellation {
+                Some(cancellation) => match select(pin!(bounded), pin!(cancellation.cancelled())).await {
+                    Either::Left((response, _)) => response,
+                    Either::Right(_) => Err(RuntimeError::user(Box::new(ExecutionCancelled))),
+                },
+                None => bounded.await,
+            }
+        };
+
+        let response = if self.nested {
+            runtime.run_nested(cancellable)
+        } else {
+            runtime.run(cancellable)
+        };
 
-        response
+        response.unwrap_or_else(|e| Err(RuntimeError::new(e.to_string())))
+    }
+}
+
+/// Answers in the calling thread, without going through the host runtime.
+#[cfg(test)]
+struct NativeHostBackend(NativeHostFunction);
+
+#[cfg(test)]
+impl HostBackend for NativeHostBackend {
+    fn request(
+        &self,
+        data: &[u8],
+        mode: HostRequestMode,
+        _runtime: &HostRuntime,
+        _deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        if cancellation.is_some_and(CancellationToken::is_cancelled) {
+            return Err(RuntimeError::user(Box::new(ExecutionCancelled)));
+        }
+
+        (self.0)(data, mode)
+    }
+}
+
+pub struct GenericExternalFunction {
+    backend: Box<dyn HostBackend>,
+}
+
+impl GenericExternalFunction {
+    pub fn new(
+        tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
+        contract_id: u64,
+    ) -> Self {
+        Self {
+            backend: Box::new(JsHostBackend { tsfn, contract_id, nested: false }),
+        }
+    }
+
+    /// For the imports answered by running other contracts.
+    pub fn nested(
+        tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
+        contract_id: u64,
+    ) -> Self {
+        Self {
+            backend: Box::new(JsHostBackend { tsfn, contract_id, nested: true }),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn native(function: NativeHostFunction) -> Self {
+        Self {
+            backend: Box::new(NativeHostBackend(function)),
+        }
+    }
+}
+
+impl GenericExternalFunction {
+    /// Like [`ExternalFunction::execute`], telling JS how the call making the request runs.
+    pub fn execute_with_mode(
+        &self,
+        data: &[u8],
+        mode: HostRequestMode,
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        self.backend.request(data, mode, runtime, deadline, cancellation)
+    }
+}
+
+impl ExternalFunction for GenericExternalFunction {
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        self.execute_with_mode(data, HostRequestMode::default(), runtime, deadline, cancellation)
     }
 }
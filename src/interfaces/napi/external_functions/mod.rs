@@ -1,7 +1,7 @@
 pub use self::{
     call_other_contract_external_function::*, console_log_external_function::*,
     deploy_from_address_external_function::*, external_function::*, generic_external_function::*,
-    storage_load_external_function::*, storage_store_external_function::*,
+    storage_load_external_function::*, storage_load_many_external_function::*, storage_store_external_function::*,
 };
 
 mod call_other_contract_external_function;
@@ -10,4 +10,5 @@ mod deploy_from_address_external_function;
 mod external_function;
 mod generic_external_function;
 mod storage_load_external_function;
+mod storage_load_many_external_function;
 mod storage_store_external_function;
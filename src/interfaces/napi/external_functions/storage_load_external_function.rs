@@ -1,8 +1,11 @@
+use std::time::Instant;
+
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
-use tokio::runtime::Runtime;
 use wasmer::RuntimeError;
 
+use crate::domain::runner::CancellationToken;
 use crate::interfaces::napi::external_functions::GenericExternalFunction;
+use crate::interfaces::napi::host_runtime::HostRuntime;
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 use crate::interfaces::ExternalFunction;
 
@@ -19,17 +22,30 @@ impl StorageLoadExternalFunction {
             external_function: GenericExternalFunction::new(tsfn, id)
         }
     }
+
+    #[cfg(test)]
+    pub fn native(function: super::NativeHostFunction) -> Self {
+        Self {
+            external_function: GenericExternalFunction::native(function),
+        }
+    }
 }
 
 impl ExternalFunction for StorageLoadExternalFunction {
-    fn execute(&self, data: &[u8], runtime: &Runtime) -> Result<Vec<u8>, RuntimeError> {
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
         //let time = chrono::offset::Local::now();
         /*if self.is_destroyed.load(Ordering::SeqCst) {
             return Err(RuntimeError::new("Contract is being destroyed"));
         }*/
 
         //self.pending_calls.fetch_add(1, Ordering::SeqCst);
-        let result = self.external_function.execute(data, runtime);
+        let result = self.external_function.execute(data, runtime, deadline, cancellation);
         //self.pending_calls.fetch_sub(1, Ordering::SeqCst);
         //log_time_diff(&time, "GenericExternalFunction::load");
 
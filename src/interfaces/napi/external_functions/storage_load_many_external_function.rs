@@ -0,0 +1,60 @@
+use std::time::Instant;
+
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
+use wasmer::RuntimeError;
+
+use crate::domain::runner::{CancellationToken, LoadMany};
+use crate::interfaces::napi::external_functions::GenericExternalFunction;
+use crate::interfaces::napi::host_runtime::HostRuntime;
+use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
+use crate::interfaces::ExternalFunction;
+
+/// Resolves a `loadMany` batch in one roundtrip when the host provides a batch callback,
+/// and one pointer at a time through the `load` callback otherwise.
+pub struct StorageLoadManyExternalFunction {
+    batch_function: Option<GenericExternalFunction>,
+    load_function: GenericExternalFunction,
+}
+
+impl StorageLoadManyExternalFunction {
+    pub fn new(
+        batch_tsfn: Option<ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>>,
+        load_tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
+        id: u64,
+    ) -> Self {
+        Self {
+            batch_function: batch_tsfn.map(|tsfn| GenericExternalFunction::new(tsfn, id)),
+            load_function: GenericExternalFunction::new(load_tsfn, id),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn native(batch_function: Option<super::NativeHostFunction>, load_function: super::NativeHostFunction) -> Self {
+        Self {
+            batch_function: batch_function.map(GenericExternalFunction::native),
+            load_function: GenericExternalFunction::native(load_function),
+        }
+    }
+}
+
+impl ExternalFunction for StorageLoadManyExternalFunction {
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        if let Some(batch_function) = &self.batch_function {
+            return batch_function.execute(data, runtime, deadline, cancellation);
+        }
+
+        let pointers = LoadMany::split_request(data).map_err(RuntimeError::new)?;
+        let values = pointers
+            .into_iter()
+            .map(|pointer| self.load_function.execute(pointer, runtime, deadline, cancellation))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(LoadMany::pack_response(&values))
+    }
+}
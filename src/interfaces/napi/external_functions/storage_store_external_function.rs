@@ -1,9 +1,13 @@
+use std::time::Instant;
+
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 use crate::interfaces::{ExternalFunction, GenericExternalFunction};
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
-use tokio::runtime::Runtime;
 use wasmer::RuntimeError;
 
+use crate::domain::runner::CancellationToken;
+use crate::interfaces::HostRuntime;
+
 pub struct StorageStoreExternalFunction {
     external_function: GenericExternalFunction,
 }
@@ -17,12 +21,25 @@ impl StorageStoreExternalFunction {
             external_function: GenericExternalFunction::new(tsfn, id)
         }
     }
+
+    #[cfg(test)]
+    pub fn native(function: super::NativeHostFunction) -> Self {
+        Self {
+            external_function: GenericExternalFunction::native(function),
+        }
+    }
 }
 
 impl ExternalFunction for StorageStoreExternalFunction {
-    fn execute(&self, data: &[u8], runtime: &Runtime) -> Result<Vec<u8>, RuntimeError> {
+    fn execute(
+        &self,
+        data: &[u8],
+        runtime: &HostRuntime,
+        deadline: Option<Instant>,
+        cancellation: Option<&CancellationToken>,
+    ) -> Result<Vec<u8>, RuntimeError> {
         //let time = chrono::offset::Local::now();
-        let resp = self.external_function.execute(data, runtime);
+        let resp = self.external_function.execute(data, runtime, deadline, cancellation);
 
         //log_time_diff(&time, "GenericExternalFunction::store");
 
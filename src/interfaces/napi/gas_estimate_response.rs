@@ -0,0 +1,10 @@
+use napi::bindgen_prelude::{Array, BigInt};
+
+#[napi(object)]
+pub struct GasEstimateResponse {
+    #[napi(ts_type = "number[]")]
+    pub result: Array,
+    pub estimated_gas: BigInt,
+    pub gas_used: BigInt,
+    pub attempts: u32,
+}
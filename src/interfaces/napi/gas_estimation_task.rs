@@ -0,0 +1,95 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Local};
+use napi::bindgen_prelude::BigInt;
+use napi::{Env, Error, JsObject};
+use wasmer::Value;
+
+use crate::application::contract::{ContractService, GasEstimate, GasEstimator};
+use crate::domain::runner::{StorageOverlay, WasmerRunner};
+use crate::domain::vm::log_time_diff;
+use crate::interfaces::napi::js_contract::{JsContract, JsContractEnvironment};
+use crate::interfaces::{GasEstimateResponse, HostRuntime};
+
+/// A gas estimation run on a continuation thread of the [`HostRuntime`], like a
+/// [`ContractCallTask`](crate::interfaces::ContractCallTask).
+pub struct GasEstimationTask {
+    runner: Arc<Mutex<WasmerRunner>>,
+    environment: JsContractEnvironment,
+    max_gas: u64,
+    func_name: String,
+    wasm_params: Vec<Value>,
+    time: DateTime<Local>,
+}
+
+impl GasEstimationTask {
+    pub fn new(
+        runner: Arc<Mutex<WasmerRunner>>,
+        environment: JsContractEnvironment,
+        max_gas: u64,
+        func_name: &str,
+        wasm_params: &[Value],
+        time: DateTime<Local>,
+    ) -> Self {
+        Self {
+            runner,
+            environment,
+            max_gas,
+            func_name: func_name.to_string(),
+            wasm_params: wasm_params.to_vec(),
+            time,
+        }
+    }
+
+    /// Runs the call on a copy of the instance whose storage writes never reach the host.
+    /// Nested calls and deployments are charged as usual, the host runs them against
+    /// throwaway state.
+    fn execute(&self, gas_limit: u64) -> anyhow::Result<(Box<[Value]>, u64)> {
+        let mut custom_env = self
+            .environment
+            .create_custom_env()
+            .map_err(|e| anyhow::anyhow!(e.reason))?;
+        custom_env.storage_overlay = Some(Mutex::new(StorageOverlay::default()));
+        custom_env.simulation = true;
+
+        let runner = {
+            let mut runner = self.runner.lock().map_err(|_| anyhow::anyhow!("Failed to lock runner"))?;
+            runner.fork(gas_limit, custom_env)?
+        };
+
+        let mut contract = ContractService::new(gas_limit, Arc::new(Mutex::new(runner)));
+        let result = contract.call(&self.func_name, &self.wasm_params)?;
+
+        Ok((result, contract.get_used_gas()))
+    }
+
+    /// Starts the estimation and returns a promise of its `GasEstimateResponse`.
+    pub fn spawn(self, env: &Env, runtime: &HostRuntime) -> napi::Result<JsObject> {
+        let (func_name, time) = (self.func_name.clone(), self.time);
+
+        let estimation = runtime.spawn_call(move || {
+            GasEstimator::new(self.max_gas).estimate(|gas_limit| self.execute(gas_limit))
+        });
+
+        env.execute_tokio_future(
+            async move {
+                estimation
+                    .await
+                    .and_then(|estimate: anyhow::Result<GasEstimate>| estimate)
+                    .map_err(|e| Error::from_reason(format!("{:?}", e)))
+            },
+            move |env, output| {
+                let js_array = JsContract::box_values_to_js_array(env, output.result)?;
+
+                log_time_diff(&time, format!("JsContract::estimate_gas: {}", func_name).as_str());
+
+                Ok(GasEstimateResponse {
+                    result: js_array,
+                    estimated_gas: BigInt::from(output.gas_limit),
+                    gas_used: BigInt::from(output.gas_used),
+                    attempts: output.attempts,
+                })
+            },
+        )
+    }
+}
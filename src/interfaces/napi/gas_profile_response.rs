@@ -0,0 +1,42 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::domain::runner::{GasProfile, GasProfileEntry};
+
+#[napi(object)]
+pub struct GasProfileEntryResponse {
+    pub name: String,
+    pub gas: BigInt,
+    pub calls: BigInt,
+}
+
+#[napi(object)]
+pub struct GasProfileResponse {
+    pub functions: Vec<GasProfileEntryResponse>,
+    pub imports: Vec<GasProfileEntryResponse>,
+    pub total_gas: BigInt,
+    pub collapsed_stacks: String,
+}
+
+impl From<GasProfileEntry> for GasProfileEntryResponse {
+    fn from(entry: GasProfileEntry) -> Self {
+        GasProfileEntryResponse {
+            name: entry.name,
+            gas: BigInt::from(entry.gas),
+            calls: BigInt::from(entry.calls),
+        }
+    }
+}
+
+impl From<GasProfile> for GasProfileResponse {
+    fn from(profile: GasProfile) -> Self {
+        let total_gas = BigInt::from(profile.total_gas());
+        let collapsed_stacks = profile.to_collapsed_stacks("contract");
+
+        GasProfileResponse {
+            functions: profile.functions.into_iter().map(|entry| entry.into()).collect(),
+            imports: profile.imports.into_iter().map(|entry| entry.into()).collect(),
+            total_gas,
+            collapsed_stacks,
+        }
+    }
+}
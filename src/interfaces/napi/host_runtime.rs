@@ -0,0 +1,601 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::thread;
+
+use futures::channel::oneshot;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinError;
+
+pub const DEFAULT_MAX_PENDING_HOST_CALLS: usize = 1024;
+
+thread_local! {
+    /// Address of the [`ContinuationPool`] the current thread belongs to, 0 for others.
+    static CONTINUATION_POOL: Cell<usize> = const { Cell::new(0) };
+}
+
+/// What a host call does when `max_pending_calls` are already waiting for JS, and what a
+/// contract call does when as many are running. Calls that run other contracts never wait
+/// for a slot, see [`HostRuntime::run_nested`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SaturationPolicy {
+    /// Parks, or queues a contract call, until another call completes.
+    #[default]
+    Wait,
+    /// Fails the call right away.
+    Reject,
+}
+
+/// Live state of the worker threads of a [`HostRuntime`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostRuntimeStats {
+    pub worker_threads: usize,
+    pub max_pending_calls: usize,
+    /// Threads parked with nothing to poll.
+    pub idle_threads: usize,
+    /// Threads polling host calls right now.
+    pub in_use_threads: usize,
+    pub created_threads: u64,
+    pub destroyed_threads: u64,
+    /// Continuation threads, at most `max_pending_calls` plus the ones parked in nested calls.
+    pub continuation_threads: usize,
+    /// Contract calls running on them.
+    pub running_calls: usize,
+    /// Contract calls waiting for one.
+    pub queued_calls: usize,
+    pub is_shut_down: bool,
+}
+
+/// Updated by the thread hooks of the runtime.
+#[derive(Default)]
+struct ThreadCounters {
+    created: AtomicU64,
+    destroyed: AtomicU64,
+    idle: AtomicUsize,
+}
+
+struct CallSlots {
+    pending: usize,
+    max_pending: usize,
+    is_shut_down: bool,
+}
+
+type Continuation = Box<dyn FnOnce() + Send>;
+
+struct ContinuationState {
+    queue: VecDeque<Continuation>,
+    /// Calls running and not parked in a nested call, at most `max_running`.
+    running: usize,
+    max_running: usize,
+    threads: usize,
+    /// Threads waiting for a call, `woken` of them already told to take one.
+    idle: usize,
+    woken: usize,
+    is_shut_down: bool,
+}
+
+/// The threads the wasm calls of a [`HostRuntime`] run on. They are started on demand and
+/// kept for the next calls, at most `max_running` calls run at once and the others queue
+/// or are rejected, as the saturation policy says.
+struct ContinuationPool {
+    state: Mutex<ContinuationState>,
+    work: Condvar,
+    saturation_policy: SaturationPolicy,
+}
+
+impl ContinuationPool {
+    fn new(max_running: usize, saturation_policy: SaturationPolicy) -> Self {
+        Self {
+            state: Mutex::new(ContinuationState {
+                queue: VecDeque::new(),
+                running: 0,
+                max_running,
+                threads: 0,
+                idle: 0,
+                woken: 0,
+                is_shut_down: false,
+            }),
+            work: Condvar::new(),
+            saturation_policy,
+        }
+    }
+
+    fn submit(self: &Arc<Self>, continuation: Continuation) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.is_shut_down {
+            return Err(anyhow::anyhow!("host runtime is shut down"));
+        }
+
+        let full = state.running + state.queue.len() >= state.max_running;
+        if full && self.saturation_policy == SaturationPolicy::Reject {
+            return Err(anyhow::anyhow!("too many running contract calls ({})", state.max_running));
+        }
+
+        state.queue.push_back(continuation);
+        if let Err(e) = self.dispatch(&mut state) {
+            state.queue.pop_back();
+
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Wakes up or starts a thread for every queued call that can run now.
+    fn dispatch(self: &Arc<Self>, state: &mut ContinuationState) -> anyhow::Result<()> {
+        while state.queue.len() > state.woken && state.running + state.woken < state.max_running {
+            if state.idle == state.woken {
+                let pool = self.clone();
+                thread::Builder::new()
+                    .name("opvm-call".to_string())
+                    .spawn(move || pool.work())?;
+
+                state.threads += 1;
+                state.idle += 1;
+            }
+
+            state.woken += 1;
+            self.work.notify_one();
+        }
+
+        Ok(())
+    }
+
+    fn work(self: Arc<Self>) {
+        CONTINUATION_POOL.with(|pool| pool.set(Arc::as_ptr(&self) as usize));
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            // Started or woken up by `dispatch`, or at shutdown.
+            state.idle -= 1;
+            state.woken = state.woken.saturating_sub(1);
+
+            while state.running < state.max_running {
+                let Some(continuation) = state.queue.pop_front() else {
+                    break;
+                };
+
+                state.running += 1;
+                drop(state);
+                continuation();
+                state = self.state.lock().unwrap();
+                state.running -= 1;
+            }
+
+            if state.is_shut_down {
+                state.threads -= 1;
+
+                return;
+            }
+
+            state.idle += 1;
+            state = self
+                .work
+                .wait_while(state, |state| state.woken == 0 && !state.is_shut_down)
+                .unwrap();
+        }
+    }
+
+    /// Gives up the place of the call on the current thread while it waits for a nested
+    /// call, which may need a thread of its own. Returns whether there was one to give up.
+    fn park(self: &Arc<Self>) -> anyhow::Result<bool> {
+        if CONTINUATION_POOL.with(Cell::get) != Arc::as_ptr(self) as usize {
+            return Ok(false);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        self.dispatch(&mut state)?;
+
+        Ok(true)
+    }
+
+    /// Takes the place back once the nested call returned, even past `max_running`.
+    fn unpark(&self) {
+        self.state.lock().unwrap().running += 1;
+    }
+
+    /// Drops the queued calls and stops the threads once their calls returned.
+    fn shutdown(&self) {
+        let queue = {
+            let mut state = self.state.lock().unwrap();
+            state.is_shut_down = true;
+
+            std::mem::take(&mut state.queue)
+        };
+
+        self.work.notify_all();
+        drop(queue);
+    }
+}
+
+/// Drives the JS roundtrips of the host imports of every contract of a manager.
+///
+/// Wasmer 4 has no asynchronous host functions and no way to yield out of a wasm stack,
+/// and asyncify would rewrite the bytecode under the gas metering. Calls are suspended
+/// on a continuation thread instead: [`HostRuntime::spawn_call`] runs the wasm call on a
+/// thread of a [`ContinuationPool`], which is what parks while a host import waits for JS.
+/// Neither the libuv pool nor a worker of the runtime is held, and a few workers serve
+/// any number of concurrent contracts.
+pub struct HostRuntime {
+    runtime: RwLock<Option<Runtime>>,
+    worker_threads: usize,
+    threads: Arc<ThreadCounters>,
+    slots: Mutex<CallSlots>,
+    capacity: Condvar,
+    saturation_policy: SaturationPolicy,
+    continuations: Arc<ContinuationPool>,
+}
+
+impl HostRuntime {
+    pub fn new(
+        worker_threads: usize,
+        max_pending_calls: usize,
+        saturation_policy: SaturationPolicy,
+    ) -> anyhow::Result<Self> {
+        let worker_threads = worker_threads.max(1);
+        let max_pending_calls = max_pending_calls.max(1);
+        let threads = Arc::new(ThreadCounters::default());
+
+        let runtime = {
+            let (started, stopped, parked, unparked) = (threads.clone(), threads.clone(), threads.clone(), threads.clone());
+
+            Builder::new_multi_thread()
+                .worker_threads(worker_threads)
+                .thread_name("opvm-host")
+                .on_thread_start(move || {
+                    started.created.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_thread_stop(move || {
+                    stopped.destroyed.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_thread_park(move || {
+                    parked.idle.fetch_add(1, Ordering::SeqCst);
+                })
+                .on_thread_unpark(move || {
+                    unparked.idle.fetch_sub(1, Ordering::SeqCst);
+                })
+                .enable_all()
+                .build()?
+        };
+
+        Ok(Self {
+            runtime: RwLock::new(Some(runtime)),
+            worker_threads,
+            threads,
+            slots: Mutex::new(CallSlots {
+                pending: 0,
+                max_pending: max_pending_calls,
+                is_shut_down: false,
+            }),
+            capacity: Condvar::new(),
+            saturation_policy,
+            continuations: Arc::new(ContinuationPool::new(max_pending_calls, saturation_policy)),
+        })
+    }
+
+    /// Runs `call` on a continuation thread and resolves with what it returns, see
+    /// [`HostRuntime`]. A panic of the call fails it. Takes one of the `max_pending_calls`
+    /// places of running calls, waiting for one in line or failing right away when they
+    /// are taken, as the saturation policy says.
+    pub fn spawn_call<F, T>(&self, call: F) -> impl Future<Output = anyhow::Result<T>> + Send + 'static
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let submitted = self.continuations.submit(Box::new(move || {
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(call)));
+        }));
+
+        async move {
+            submitted?;
+
+            match receiver.await {
+                Ok(Ok(output)) => Ok(output),
+                Ok(Err(panic)) => Err(anyhow::anyhow!("contract call panicked: {:?}", panic)),
+                Err(_) => Err(anyhow::anyhow!("host runtime is shut down")),
+            }
+        }
+    }
+
+    /// Runs `future` on the runtime and parks the calling thread until it completes. Host
+    /// imports call this from the continuation of their call, it must not be called from a
+    /// thread of the runtime itself. Takes one of the `max_pending_calls` slots.
+    pub fn run<F>(&self, future: F) -> anyhow::Result<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.acquire()?;
+        let result = self.block_on(future);
+        self.release();
+
+        Self::join(result)
+    }
+
+    /// Like [`HostRuntime::run`], for host calls that run other contracts, i.e. `call` and
+    /// `deployFromAddress`. What they wait for takes slots and continuation threads itself,
+    /// holding one meanwhile could leave all of them to calls that wait for each other.
+    /// They are not counted, and the calling continuation gives up its place until then.
+    pub fn run_nested<F>(&self, future: F) -> anyhow::Result<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        if self.slots.lock().unwrap().is_shut_down {
+            return Err(anyhow::anyhow!("host runtime is shut down"));
+        }
+
+        let parked = self.continuations.park()?;
+        let result = self.block_on(future);
+        if parked {
+            self.continuations.unpark();
+        }
+
+        Self::join(result)
+    }
+
+    fn block_on<F>(&self, future: F) -> anyhow::Result<Result<F::Output, JoinError>>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let task = match self.runtime.read().unwrap().as_ref() {
+            Some(runtime) => Ok(runtime.spawn(future)),
+            None => Err(anyhow::anyhow!("host runtime is shut down")),
+        };
+
+        task.map(futures::executor::block_on)
+    }
+
+    /// Resumes the panic of a task on the calling thread, once its slot is released.
+    fn join<T>(result: anyhow::Result<Result<T, JoinError>>) -> anyhow::Result<T> {
+        match result? {
+            Ok(output) => Ok(output),
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(_) => Err(anyhow::anyhow!("host runtime is shut down")),
+        }
+    }
+
+    /// Cancels the pending host calls and stops the worker threads. Calls made afterwards
+    /// fail right away.
+    pub fn shutdown(&self) {
+        let runtime = self.runtime.write().unwrap().take();
+        if let Some(runtime) = runtime {
+            runtime.shutdown_background();
+        }
+
+        self.slots.lock().unwrap().is_shut_down = true;
+        self.capacity.notify_all();
+        self.continuations.shutdown();
+    }
+
+    pub fn stats(&self) -> HostRuntimeStats {
+        let slots = self.slots.lock().unwrap();
+        let created_threads = self.threads.created.load(Ordering::SeqCst);
+        let destroyed_threads = self.threads.destroyed.load(Ordering::SeqCst);
+        let alive_threads = created_threads.saturating_sub(destroyed_threads) as usize;
+        let idle_threads = self.threads.idle.load(Ordering::SeqCst).min(alive_threads);
+        let continuations = self.continuations.state.lock().unwrap();
+
+        HostRuntimeStats {
+            worker_threads: self.worker_threads,
+            max_pending_calls: slots.max_pending,
+            idle_threads,
+            in_use_threads: alive_threads - idle_threads,
+            created_threads,
+            destroyed_threads,
+            continuation_threads: continuations.threads,
+            running_calls: continuations.running,
+            queued_calls: continuations.queue.len(),
+            is_shut_down: slots.is_shut_down,
+        }
+    }
+
+    fn acquire(&self) -> anyhow::Result<()> {
+        let mut slots = self.slots.lock().unwrap();
+
+        loop {
+            if slots.is_shut_down {
+                return Err(anyhow::anyhow!("host runtime is shut down"));
+            }
+
+            if slots.pending < slots.max_pending {
+                slots.pending += 1;
+
+                return Ok(());
+            }
+
+            match self.saturation_policy {
+                SaturationPolicy::Wait => slots = self.capacity.wait(slots).unwrap(),
+                SaturationPolicy::Reject => {
+                    return Err(anyhow::anyhow!("too many pending host calls ({})", slots.max_pending));
+                }
+            }
+        }
+    }
+
+    fn release(&self) {
+        self.slots.lock().unwrap().pending -= 1;
+        self.capacity.notify_one();
+    }
+
+    #[cfg(test)]
+    fn pending_calls(&self) -> usize {
+        self.slots.lock().unwrap().pending
+    }
+}
+
+impl Drop for HostRuntime {
+    fn drop(&mut self) {
+        // Dropping a runtime blocks until its workers stop, which must not happen on the
+        // JS thread.
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn serves_concurrent_callers_with_one_worker() {
+        let runtime = Arc::new(HostRuntime::new(1, 2, SaturationPolicy::Reject).unwrap());
+        let (sender, receiver) = tokio::sync::oneshot::channel::<u32>();
+
+        // The first caller waits for the second one, both park on the same worker.
+        let waiting = {
+            let runtime = runtime.clone();
+            std::thread::spawn(move || runtime.run(async move { receiver.await.unwrap() }).unwrap())
+        };
+        runtime.run(async move { sender.send(7).unwrap() }).unwrap();
+
+        assert_eq!(waiting.join().unwrap(), 7);
+        assert_eq!(runtime.pending_calls(), 0);
+
+        let stats = runtime.stats();
+        assert_eq!(stats.created_threads, 1);
+        assert_eq!(stats.idle_threads + stats.in_use_threads, 1);
+        assert_eq!(stats.destroyed_threads, 0);
+    }
+
+    #[test]
+    fn suspends_calls_on_their_own_thread() {
+        let runtime = Arc::new(HostRuntime::new(1, 1, SaturationPolicy::Reject).unwrap());
+        let (sender, receiver) = tokio::sync::oneshot::channel::<u32>();
+
+        // Returns right away, the continuation is what waits for the host.
+        let call = {
+            let inner = runtime.clone();
+            runtime.spawn_call(move || inner.run(async move { receiver.await.unwrap() }).unwrap())
+        };
+        while runtime.pending_calls() == 0 {
+            std::thread::yield_now();
+        }
+        sender.send(3).unwrap();
+
+        assert_eq!(futures::executor::block_on(call).unwrap(), 3);
+        assert!(futures::executor::block_on(runtime.spawn_call(|| panic!("trap"))).is_err());
+    }
+
+    #[test]
+    fn rejects_calls_beyond_capacity() {
+        let runtime = Arc::new(HostRuntime::new(1, 1, SaturationPolicy::Reject).unwrap());
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+
+        let blocking = {
+            let runtime = runtime.clone();
+            std::thread::spawn(move || runtime.run(async move { receiver.recv().unwrap() }))
+        };
+        while runtime.pending_calls() == 0 {
+            std::thread::yield_now();
+        }
+
+        assert!(runtime.run(async {}).is_err());
+
+        sender.send(()).unwrap();
+        blocking.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn nested_calls_do_not_wait_for_the_calls_they_serve() {
+        let runtime = Arc::new(HostRuntime::new(1, 1, SaturationPolicy::Wait).unwrap());
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+
+        // A `call` import waits for the callee, which needs the only slot for its own load.
+        let caller = {
+            let runtime = runtime.clone();
+            std::thread::spawn(move || runtime.run_nested(async move { receiver.await.unwrap() }))
+        };
+        let load = runtime.run(async { 5 }).unwrap();
+        sender.send(()).unwrap();
+
+        assert_eq!(load, 5);
+        caller.join().unwrap().unwrap();
+        assert_eq!(runtime.pending_calls(), 0);
+    }
+
+    /// A call resolves right before its thread goes back to the pool.
+    fn wait_for_idle_continuations(runtime: &HostRuntime) {
+        while runtime.stats().running_calls > 0 {
+            std::thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn reuses_continuation_threads() {
+        let runtime = HostRuntime::new(1, 4, SaturationPolicy::Reject).unwrap();
+
+        for call in 0..3 {
+            assert_eq!(futures::executor::block_on(runtime.spawn_call(move || call)).unwrap(), call);
+            wait_for_idle_continuations(&runtime);
+        }
+
+        assert_eq!(runtime.stats().continuation_threads, 1);
+    }
+
+    #[test]
+    fn queues_or_rejects_calls_beyond_capacity() {
+        for policy in [SaturationPolicy::Wait, SaturationPolicy::Reject] {
+            let runtime = HostRuntime::new(1, 1, policy).unwrap();
+            let (sender, receiver) = std::sync::mpsc::channel::<()>();
+
+            let blocking = runtime.spawn_call(move || receiver.recv().unwrap());
+            let queued = runtime.spawn_call(|| 2);
+            while runtime.stats().running_calls == 0 {
+                std::thread::yield_now();
+            }
+
+            let stats = runtime.stats();
+            assert_eq!(stats.queued_calls, usize::from(policy == SaturationPolicy::Wait));
+            sender.send(()).unwrap();
+
+            futures::executor::block_on(blocking).unwrap();
+            assert_eq!(futures::executor::block_on(queued).is_ok(), policy == SaturationPolicy::Wait);
+            assert_eq!(runtime.stats().continuation_threads, 1);
+        }
+    }
+
+    #[test]
+    fn nested_calls_give_up_their_continuation_thread() {
+        let runtime = Arc::new(HostRuntime::new(1, 1, SaturationPolicy::Wait).unwrap());
+
+        // The only place is taken by the caller until its `call` import waits for the callee.
+        let caller = {
+            let inner = runtime.clone();
+            runtime.spawn_call(move || {
+                let callee = inner.spawn_call(|| 4);
+                inner.run_nested(callee).unwrap().unwrap()
+            })
+        };
+
+        assert_eq!(futures::executor::block_on(caller).unwrap(), 4);
+        wait_for_idle_continuations(&runtime);
+        assert_eq!(runtime.stats().continuation_threads, 2);
+    }
+
+    #[test]
+    fn fails_calls_after_shutdown() {
+        let runtime = HostRuntime::new(1, 1, SaturationPolicy::Wait).unwrap();
+        runtime.shutdown();
+
+        assert!(runtime.run(async {}).is_err());
+        assert!(runtime.run_nested(async {}).is_err());
+        assert!(futures::executor::block_on(runtime.spawn_call(|| ())).is_err());
+
+        assert!(runtime.stats().is_shut_down);
+
+        // Workers stop in the background.
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        while runtime.stats().destroyed_threads < runtime.stats().created_threads {
+            assert!(std::time::Instant::now() < deadline, "workers did not stop");
+            std::thread::yield_now();
+        }
+    }
+}
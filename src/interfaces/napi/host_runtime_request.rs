@@ -0,0 +1,8 @@
+/// Bounds of the runtime that awaits the JS imports.
+#[napi(object)]
+pub struct HostRuntimeRequest {
+    /// Host calls that may wait for JS at the same time, defaults to 1024.
+    pub max_pending_calls: Option<u32>,
+    /// Fail host calls beyond `max_pending_calls` instead of waiting for a free slot.
+    pub reject_when_saturated: Option<bool>,
+}
@@ -0,0 +1,34 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::interfaces::HostRuntimeStats;
+
+#[napi(object)]
+pub struct HostRuntimeStatsResponse {
+    pub worker_threads: u32,
+    pub max_pending_calls: u32,
+    pub idle_threads: u32,
+    pub in_use_threads: u32,
+    pub created_threads: BigInt,
+    pub destroyed_threads: BigInt,
+    pub continuation_threads: u32,
+    pub running_calls: u32,
+    pub queued_calls: u32,
+    pub is_shut_down: bool,
+}
+
+impl From<HostRuntimeStats> for HostRuntimeStatsResponse {
+    fn from(stats: HostRuntimeStats) -> Self {
+        HostRuntimeStatsResponse {
+            worker_threads: stats.worker_threads as u32,
+            max_pending_calls: stats.max_pending_calls as u32,
+            idle_threads: stats.idle_threads as u32,
+            in_use_threads: stats.in_use_threads as u32,
+            created_threads: BigInt::from(stats.created_threads),
+            destroyed_threads: BigInt::from(stats.destroyed_threads),
+            continuation_threads: stats.continuation_threads as u32,
+            running_calls: stats.running_calls as u32,
+            queued_calls: stats.queued_calls as u32,
+            is_shut_down: stats.is_shut_down,
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::domain::runner::InstancePoolStats;
+
+#[napi(object)]
+pub struct InstancePoolStatsResponse {
+    pub hits: BigInt,
+    pub misses: BigInt,
+    pub idle: u32,
+    pub discarded: BigInt,
+    pub instantiations: BigInt,
+    pub instantiation_time_ns: BigInt,
+    pub resets: BigInt,
+    pub reset_time_ns: BigInt,
+}
+
+impl From<InstancePoolStats> for InstancePoolStatsResponse {
+    fn from(stats: InstancePoolStats) -> Self {
+        InstancePoolStatsResponse {
+            hits: BigInt::from(stats.hits),
+            misses: BigInt::from(stats.misses),
+            idle: stats.idle as u32,
+            discarded: BigInt::from(stats.discarded),
+            instantiations: BigInt::from(stats.instantiations),
+            instantiation_time_ns: BigInt::from(stats.instantiation_time.as_nanos() as u64),
+            resets: BigInt::from(stats.resets),
+            reset_time_ns: BigInt::from(stats.reset_time.as_nanos() as u64),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use napi::bindgen_prelude::BigInt;
+
+#[napi(object)]
+pub struct InstantiateResponse {
+    /// Gas used by the start function, already included in the contract's used gas.
+    pub constructor_gas_used: BigInt,
+}
@@ -0,0 +1,38 @@
+use crate::domain::runner::CancellationToken;
+
+/// Cancels the calls it is passed to, e.g. from the `abort` event of an `AbortSignal`.
+/// Cancelled calls reject with "execution cancelled".
+#[napi(js_name = "CancellationToken")]
+pub struct JsCancellationToken {
+    token: CancellationToken,
+}
+
+impl Default for JsCancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[napi]
+impl JsCancellationToken {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        Self {
+            token: CancellationToken::new(),
+        }
+    }
+
+    #[napi]
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    #[napi(getter)]
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub fn get_token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
@@ -1,158 +1,243 @@
-use bytes::Bytes;
 use chrono::Local;
 use napi::bindgen_prelude::*;
 use napi::bindgen_prelude::{Array, BigInt, Buffer, Undefined};
 use napi::Env;
 use napi::Error;
 use napi::JsNumber;
+use napi::JsObject;
 use napi::JsUnknown;
-use std::panic::catch_unwind;
+use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::{Arc, Mutex};
-use tokio::runtime::Runtime;
-use wasmer::Value;
+use wasmer::{Module, Value};
 
 use crate::application::contract::ContractService;
-use crate::domain::runner::{CustomEnv, WasmerRunner};
-use crate::domain::vm::log_time_diff;
+use crate::domain::runner::{BitcoinNetwork, Breakpoint, CallOptions, CustomEnv, DebugAdapter, Debugger, ExecutionTrace, InstanceSnapshot, WasmerRunner};
+use crate::domain::vm::{log_time_diff, ModuleLimits};
 use crate::interfaces::napi::contract::JsContractParameter;
 use crate::interfaces::napi::js_contract_manager::ContractManager;
-use crate::interfaces::napi::runtime_pool::RuntimePool;
+use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
 use crate::interfaces::{
-    AbortDataResponse, CallOtherContractExternalFunction, ConsoleLogExternalFunction,
-    ContractCallTask, DeployFromAddressExternalFunction,
-    StorageLoadExternalFunction, StorageStoreExternalFunction,
+    AbortDataResponse, BlockCall, BreakpointRequest, CallOtherContractExternalFunction, ConsoleLogExternalFunction,
+    ContractCallTask, DebugStopResponse, DeployFromAddressExternalFunction, ExternalFunction, GasEstimationTask, GasProfileResponse, HostRuntime,
+    StorageLoadExternalFunction, StorageLoadManyExternalFunction, StorageStoreExternalFunction, ValidationReportResponse,
 };
 /**/
 
+type JsImportFunction = ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>;
+
+/// Everything needed to build a `CustomEnv` for a contract, so that additional
+/// instances (e.g. for gas estimation) can be created off the main thread.
+#[derive(Clone)]
+pub struct JsContractEnvironment {
+    id: u64,
+    address: String,
+    network: BitcoinNetwork,
+    runtime: Arc<HostRuntime>,
+    storage_load_tsfn: JsImportFunction,
+    storage_load_many_tsfn: Option<JsImportFunction>,
+    storage_store_tsfn: JsImportFunction,
+    call_other_contract_tsfn: JsImportFunction,
+    deploy_from_address_tsfn: JsImportFunction,
+    console_log_tsfn: JsImportFunction,
+}
+
+impl JsContractEnvironment {
+    pub fn new(manager: &ContractManager, id: u64, address: String, network: BitcoinNetwork, runtime: Arc<HostRuntime>) -> Self {
+        Self {
+            id,
+            address,
+            network,
+            runtime,
+            storage_load_tsfn: manager.storage_load_tsfn.clone(),
+            storage_load_many_tsfn: manager.storage_load_many_tsfn.clone(),
+            storage_store_tsfn: manager.storage_store_tsfn.clone(),
+            call_other_contract_tsfn: manager.call_other_contract_tsfn.clone(),
+            deploy_from_address_tsfn: manager.deploy_from_address_tsfn.clone(),
+            console_log_tsfn: manager.console_log_tsfn.clone(),
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Sends a write buffered during a block straight to the host, as `store` would have.
+    pub fn store(&self, request: &[u8]) -> Result<()> {
+        StorageStoreExternalFunction::new(self.storage_store_tsfn.clone(), self.id)
+            .execute(request, &self.runtime, None, None)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        Ok(())
+    }
+
+    pub fn create_custom_env(&self) -> Result<CustomEnv> {
+        // Create ExternalFunction instances with contract_id
+        let storage_load_external = StorageLoadExternalFunction::new(self.storage_load_tsfn.clone(), self.id);
+        let storage_load_many_external = StorageLoadManyExternalFunction::new(
+            self.storage_load_many_tsfn.clone(),
+            self.storage_load_tsfn.clone(),
+            self.id,
+        );
+        let storage_store_external = StorageStoreExternalFunction::new(self.storage_store_tsfn.clone(), self.id);
+        let call_other_contract_external = CallOtherContractExternalFunction::new(self.call_other_contract_tsfn.clone(), self.id);
+        let deploy_from_address_external = DeployFromAddressExternalFunction::new(self.deploy_from_address_tsfn.clone(), self.id);
+        let console_log_external = ConsoleLogExternalFunction::new(self.console_log_tsfn.clone(), self.id);
+
+        CustomEnv::new(
+            self.network,
+            storage_load_external,
+            storage_load_many_external,
+            storage_store_external,
+            call_other_contract_external,
+            deploy_from_address_external,
+            console_log_external,
+            self.runtime.clone(),
+        ).map_err(|e| Error::from_reason(format!("{:?}", e)))
+    }
+}
+
 pub struct JsContract {
     //pending_calls: Arc<AtomicUsize>,
     //is_destroyed: Arc<AtomicBool>,
+    /// Declared first, so it is closed before the runner its debugger reads from can go.
+    debug_adapter: Mutex<Option<DebugAdapter>>,
     runner: Arc<Mutex<WasmerRunner>>,
     contract: Arc<Mutex<ContractService>>,
-    runtime: Arc<Runtime>,
-    runtime_pool: Arc<RuntimePool>,
+    environment: JsContractEnvironment,
+    max_gas: u64,
+    debugger: Option<Arc<Debugger>>,
 }
 
 impl JsContract {
     pub fn validate_bytecode(bytecode: Buffer,
-                             max_gas: BigInt) -> Result<bool> {
+                             max_gas: BigInt,
+                             limits: &ModuleLimits) -> Result<ValidationReportResponse> {
         catch_unwind(|| {
             let time = Local::now();
             let bytecode_vec = bytecode.to_vec();
             let max_gas = max_gas.get_u64().1;
 
-            let is_valid = WasmerRunner::validate_bytecode(
+            let report = WasmerRunner::validate_bytecode(
                 &bytecode_vec,
                 max_gas,
+                limits,
             ).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
 
             log_time_diff(&time, "JsContract::validate");
 
-            Ok(is_valid)
+            Ok(report.into())
         })
             .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e)))
             )
     }
 
     pub fn from(params: JsContractParameter, manager: &ContractManager, id: u64) -> Result<Self> {
-        catch_unwind(|| unsafe {
+        // A pooled runner holds a `Store`, which is not unwind safe. It is dropped on panic.
+        catch_unwind(AssertUnwindSafe(|| {
             let time = Local::now();
 
-            let storage_load_tsfn = manager.storage_load_tsfn.clone();
-            let storage_store_tsfn = manager.storage_store_tsfn.clone();
-            let call_other_contract_tsfn = manager.call_other_contract_tsfn.clone();
-            let deploy_from_address_tsfn = manager.deploy_from_address_tsfn.clone();
-            let console_log_tsfn = manager.console_log_tsfn.clone();
-
-            // Create ExternalFunction instances with contract_id
-            let storage_load_external = StorageLoadExternalFunction::new(storage_load_tsfn, id);
-            let storage_store_external = StorageStoreExternalFunction::new(storage_store_tsfn, id);
-            let call_other_contract_external = CallOtherContractExternalFunction::new(call_other_contract_tsfn, id);
-            let deploy_from_address_external = DeployFromAddressExternalFunction::new(deploy_from_address_tsfn, id);
-            let console_log_external = ConsoleLogExternalFunction::new(console_log_tsfn, id);
-
-            // Obtain a Runtime from the pool
-            let runtime = manager
-                .runtime_pool
-                .get_runtime()
-                .ok_or_else(|| Error::from_reason("No available runtimes in the pool".to_string()))?;
-
-            //let runtime = Arc::new(Runtime::new()?);
-            let custom_env: CustomEnv = CustomEnv::new(
-                params.network.into(),
-                storage_load_external,
-                storage_store_external,
-                call_other_contract_external,
-                deploy_from_address_external,
-                console_log_external,
-                runtime.clone(),
-            ).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+            let environment = JsContractEnvironment::new(manager, id, params.address.clone(), params.network.into(), manager.host_runtime.clone());
+            let custom_env: CustomEnv = environment.create_custom_env()?;
+
+            let mut runner: WasmerRunner;
 
-            let runner: WasmerRunner;
+            if let Some(mut pooled) = params.runner {
+                pooled
+                    .reset(params.max_gas, custom_env)
+                    .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
 
-            if let Some(bytecode) = params.bytecode {
+                runner = pooled;
+            } else if let Some(bytecode) = params.bytecode {
                 runner = WasmerRunner::from_bytecode(
                     &bytecode,
                     params.max_gas,
                     custom_env,
+                    params.gas_profiling,
+                    params.debugging,
+                    &params.module_limits,
+                    params.execution_limits,
                 )
                     .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
-            } else if let Some(serialized) = params.serialized {
-                runner = WasmerRunner::from_serialized(
-                    serialized,
+            } else if let Some(module) = params.module {
+                let code_hash = params
+                    .code_hash
+                    .ok_or_else(|| Error::from_reason("A compiled module needs the hash of its bytecode"))?;
+                runner = WasmerRunner::from_module(
+                    module,
+                    code_hash,
                     params.max_gas,
                     custom_env,
+                    &params.module_limits,
+                    params.execution_limits,
                 )
                     .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
             } else {
-                return Err(Error::from_reason("No bytecode or serialized data"));
+                return Err(Error::from_reason("No bytecode or compiled module"));
+            }
+
+            if params.deployment_gas > 0 {
+                runner
+                    .charge_deployment_gas(params.deployment_gas)
+                    .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
             }
 
-            let contract = JsContract::from_runner(runner, params.max_gas, runtime.clone(), manager.runtime_pool.clone())?; //, storage_load_tsfn, storage_store_tsfn, call_other_contract_tsfn, deploy_from_address_tsfn, console_log_tsfn
+            let contract = JsContract::from_runner(runner, params.max_gas, environment)?; //, storage_load_tsfn, storage_store_tsfn, call_other_contract_tsfn, deploy_from_address_tsfn, console_log_tsfn
             log_time_diff(&time, "JsContract::from");
 
             Ok(contract)
-        })
+        }))
             .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
     }
 
     fn from_runner(
         runner: WasmerRunner,
         max_gas: u64,
-        runtime: Arc<Runtime>,
-        runtime_pool: Arc<RuntimePool>,
+        environment: JsContractEnvironment,
     ) -> Result<Self> {
         let time = Local::now();
 
+        let debugger = runner.debugger();
         let runner = Arc::new(Mutex::new(runner));
         let contract = ContractService::new(max_gas, runner.clone());
 
         log_time_diff(&time, "JsContract::from_runner");
 
         Ok(Self {
+            debug_adapter: Mutex::new(None),
             runner,
             contract: Arc::new(Mutex::new(contract)),
-            runtime,
-            runtime_pool,
+            environment,
+            max_gas,
+            debugger,
         })
     }
 
-    pub fn serialize(&self) -> Result<Bytes> {
+    /// Gives back the runner once nothing else uses the contract anymore.
+    pub fn into_runner(self) -> Option<WasmerRunner> {
+        let runner = self.runner.clone();
+        drop(self);
+
+        Arc::try_unwrap(runner).ok()?.into_inner().ok()
+    }
+
+    pub fn get_module(&self) -> Result<Module> {
         catch_unwind(|| {
             let runner = self.runner.clone();
             let runner = runner.lock().map_err(|e| Error::from_reason(format!("{:?}", e)))?;
-            let serialized = runner.serialize().map_err(|e| Error::from_reason(format!("{:?}", e)))?;
 
-            Ok(serialized)
+            Ok(runner.get_module())
         })
             .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
     }
 
     pub fn call(
         &self,
+        env: &Env,
         func_name: String,
         params: Vec<JsNumber>,
-    ) -> Result<AsyncTask<ContractCallTask>> {
+        options: CallOptions,
+    ) -> Result<JsObject> {
         catch_unwind(|| {
             let time = Local::now();
             let mut wasm_params = Vec::new();
@@ -173,14 +258,76 @@ impl JsContract {
             }
 
             let contract = self.contract.clone();
-            let result = AsyncTask::new(ContractCallTask::new(
+            let task = ContractCallTask::new(
                 contract,
                 &func_name,
                 &wasm_params,
+                options,
                 time,
-            ));
+            );
 
-            Ok(result)
+            task.spawn(env, &self.environment.runtime)
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    /// Captures the contract as it is now, calldata included, for a call of a block.
+    pub fn prepare_block_call(&self, func_name: &str, params: &[i32]) -> Result<BlockCall> {
+        let wasm_params: Vec<Value> = params.iter().map(|param| Value::I32(*param)).collect();
+
+        let snapshot = self
+            .runner
+            .lock()
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?
+            .snapshot()
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        Ok(BlockCall::new(
+            self.runner.clone(),
+            snapshot,
+            self.environment.clone(),
+            self.max_gas,
+            func_name,
+            &wasm_params,
+        ))
+    }
+
+    pub fn get_constructor_gas_used(&self) -> Result<BigInt> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+            let gas = {
+                let contract = contract.lock().unwrap();
+                contract.get_constructor_gas_used()
+            };
+
+            Ok(BigInt::from(gas))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn estimate_gas(
+        &self,
+        env: &Env,
+        func_name: String,
+        params: Vec<JsNumber>,
+    ) -> Result<JsObject> {
+        catch_unwind(|| {
+            let time = Local::now();
+            let wasm_params = params
+                .iter()
+                .map(|param| param.get_int32().map(Value::I32))
+                .collect::<Result<Vec<Value>>>()?;
+
+            let task = GasEstimationTask::new(
+                self.runner.clone(),
+                self.environment.clone(),
+                self.max_gas,
+                &func_name,
+                &wasm_params,
+                time,
+            );
+
+            task.spawn(env, &self.environment.runtime)
         })
             .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
     }
@@ -310,14 +457,174 @@ impl JsContract {
         })
             .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
     }
-}
 
-impl Drop for JsContract {
-    fn drop(&mut self) {
-        //println!("Dropping JsContract");
+    pub fn snapshot(&self) -> Result<Buffer> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+            let snapshot = {
+                let contract = contract.lock().unwrap();
+                contract.snapshot()
+            };
+
+            let bytes = snapshot.and_then(|snapshot| snapshot.to_bytes()).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+            Ok(Buffer::from(bytes))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn restore(&self, snapshot: Buffer) -> Result<()> {
+        catch_unwind(|| {
+            let snapshot = InstanceSnapshot::from_bytes(&snapshot).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+            let contract = self.contract.clone();
 
-        // Return the runtime to the pool
-        self.runtime_pool.return_runtime(self.runtime.clone());
+            let contract = contract.lock().unwrap();
+            contract.restore(&snapshot).map_err(|e| Error::from_reason(format!("{:?}", e)))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn start_trace(&self) -> Result<()> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+            let contract = contract.lock().unwrap();
+            contract.start_trace();
+
+            Ok(())
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn take_trace(&self) -> Result<Option<Buffer>> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+            let trace = {
+                let contract = contract.lock().unwrap();
+                contract.take_trace()
+            };
+
+            Ok(trace.map(|mut trace| {
+                trace.header.address = self.environment.address().to_string();
+                Buffer::from(trace.encode())
+            }))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn start_replay(&self, trace: Buffer) -> Result<()> {
+        catch_unwind(|| {
+            let trace = ExecutionTrace::decode(&trace).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+            let contract = self.contract.clone();
+
+            let contract = contract.lock().unwrap();
+            contract.start_replay(trace).map_err(|e| Error::from_reason(format!("{:?}", e)))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn finish_replay(&self) -> Result<()> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+
+            let contract = contract.lock().unwrap();
+            contract.finish_replay().map_err(|e| Error::from_reason(format!("{:?}", e)))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
+    }
+
+    pub fn set_breakpoint(&self, request: BreakpointRequest) -> Result<()> {
+        let breakpoint = Breakpoint::try_from(request).map_err(Error::from_reason)?;
+
+        self.debugger()?
+            .set_breakpoint(&breakpoint)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))
+    }
+
+    pub fn clear_breakpoints(&self) -> Result<()> {
+        self.debugger()?.clear_breakpoints();
+
+        Ok(())
+    }
+
+    pub fn debug_poll(&self) -> Result<Option<DebugStopResponse>> {
+        Ok(self.debugger()?.poll().map(|stop| stop.into()))
+    }
+
+    pub fn debug_pause(&self) -> Result<()> {
+        self.debugger()?.pause();
+
+        Ok(())
+    }
+
+    pub fn debug_step(&self) -> Result<()> {
+        self.debugger()?.step();
+
+        Ok(())
+    }
+
+    pub fn debug_resume(&self) -> Result<()> {
+        self.debugger()?.resume();
+
+        Ok(())
+    }
+
+    pub fn debug_read_memory(&self, offset: BigInt, length: BigInt) -> Result<Buffer> {
+        let memory = self
+            .debugger()?
+            .read_memory(offset.get_u64().1, length.get_u64().1)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        Ok(Buffer::from(memory))
+    }
+
+    /// Serves the debugger to an IDE over the Debug Adapter Protocol, replacing the adapter
+    /// already listening if any. Returns the port.
+    pub fn debug_listen(&self, port: u16) -> Result<u16> {
+        let debugger = self
+            .debugger
+            .clone()
+            .ok_or(Error::from_reason("Debugging is not enabled for this contract"))?;
+
+        let mut debug_adapter = self.debug_adapter.lock().unwrap();
+        if let Some(mut adapter) = debug_adapter.take() {
+            adapter.close();
+        }
+
+        let adapter = DebugAdapter::listen(debugger, port).map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+        let port = adapter.port();
+        *debug_adapter = Some(adapter);
+
+        Ok(port)
+    }
+
+    /// Lets a stopped call run to completion, e.g. before the contract is destroyed.
+    pub fn detach_debugger(&self) {
+        if let Some(mut adapter) = self.debug_adapter.lock().unwrap().take() {
+            adapter.close();
+        }
+
+        if let Some(debugger) = &self.debugger {
+            debugger.detach();
+        }
+    }
+
+    fn debugger(&self) -> Result<&Debugger> {
+        self.debugger
+            .as_deref()
+            .ok_or(Error::from_reason("Debugging is not enabled for this contract"))
+    }
+
+    pub fn get_gas_profile(&self) -> Result<GasProfileResponse> {
+        catch_unwind(|| {
+            let contract = self.contract.clone();
+            let result: Option<GasProfileResponse> = {
+                let contract = contract.lock().unwrap();
+                contract.get_gas_profile().map(|profile| profile.into())
+            };
+
+            result.ok_or(Error::from_reason("Gas profiling is not enabled for this contract"))
+        })
+            .unwrap_or_else(|e| Err(Error::from_reason(format!("{:?}", e))))
     }
 }
 
@@ -1,17 +1,21 @@
 use crate::interfaces::napi::bitcoin_network_request::BitcoinNetworkRequest;
 use crate::interfaces::napi::contract::JsContractParameter;
 use crate::interfaces::napi::js_contract::JsContract;
-use crate::interfaces::napi::runtime_pool::RuntimePool;
 use crate::interfaces::napi::thread_safe_js_import_response::ThreadSafeJsImportResponse;
-use crate::interfaces::{AbortDataResponse, ContractCallTask};
+use crate::domain::runner::{code_hash, AccessList, CallOptions, ContractCache, EngineKey, EngineRegistry, ExecutionLimits, InstancePool, InstancePoolKey, ModuleCache, WasmerRunner, DEFAULT_CONTRACT_CACHE_SIZE, DEFAULT_MAX_POOLED_INSTANCES, DEFAULT_MODULE_CACHE_SIZE, STORAGE_POINTER_SIZE};
+use crate::domain::vm::ModuleLimits;
+use crate::interfaces::{AbortDataResponse, AccessListRequest, BlockCallRequest, BlockExecutionTask, BreakpointRequest, ContractCacheRequest, ContractCacheStatsResponse, DebugStopResponse, ExecutionLimitsRequest, GasProfileResponse, HostRuntime, HostRuntimeRequest, HostRuntimeStatsResponse, InstancePoolStatsResponse, InstantiateResponse, JsCancellationToken, ModuleCacheRequest, ModuleCacheStatsResponse, ModuleLimitsRequest, PrewarmContractRequest, SaturationPolicy, StorageEntryRequest, ValidationReportResponse, DEFAULT_MAX_PENDING_HOST_CALLS};
 use anyhow::anyhow;
-use bytes::Bytes;
-use napi::bindgen_prelude::{AsyncTask, BigInt, Buffer, Undefined};
+use napi::bindgen_prelude::{BigInt, Buffer, ClassInstance, Undefined};
 use napi::threadsafe_function::{ErrorStrategy, ThreadsafeFunction};
 use napi::Env;
-use napi::{Error, JsFunction, JsNumber};
+use napi::{Error, JsFunction, JsNumber, JsObject};
+use chrono::Local;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wasmer::Module;
+use wasmer_cache::{Cache, Hash};
 
 macro_rules! create_tsfn {
     ($id:ident) => {
@@ -33,13 +37,21 @@ macro_rules! abort_tsfn {
 #[napi(js_name = "ContractManager")]
 pub struct ContractManager {
     contracts: HashMap<u64, JsContract>,
-    contract_cache: HashMap<String, Bytes>,
+    contract_cache: ContractCache<Module>,
+    code_hashes: HashMap<String, String>,
+    instance_pool: InstancePool<WasmerRunner>,
+    pooled_contracts: HashMap<u64, InstancePoolKey>,
     next_id: u64,
+    module_limits: ModuleLimits,
+    max_execution_limits: ExecutionLimits,
+    module_cache: Option<ModuleCache>,
     #[napi(skip)]
-    pub runtime_pool: Arc<RuntimePool>,
+    pub host_runtime: Arc<HostRuntime>,
     #[napi(skip)]
     pub storage_load_tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
     #[napi(skip)]
+    pub storage_load_many_tsfn: Option<ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>>,
+    #[napi(skip)]
     pub storage_store_tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
     #[napi(skip)]
     pub call_other_contract_tsfn: ThreadsafeFunction<ThreadSafeJsImportResponse, ErrorStrategy::CalleeHandled>,
@@ -51,9 +63,12 @@ pub struct ContractManager {
 
 #[napi]
 impl ContractManager {
+    /// `host_worker_threads` sizes the runtime that awaits the JS imports of every contract.
+    /// Without `storage_load_many_js_function`, `loadMany` falls back to one `load` per pointer.
     #[napi(constructor)]
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        max_idling_runtimes: u32,
+        host_worker_threads: u32,
         #[napi(
             ts_arg_type = "(_: never, result: ThreadSafeJsImportResponse) => Promise<Buffer | Uint8Array>"
         )]
@@ -74,27 +89,83 @@ impl ContractManager {
             ts_arg_type = "(_: never, result: ThreadSafeJsImportResponse) => Promise<void>"
         )]
         console_log_js_function: JsFunction,
+        module_limits: Option<ModuleLimitsRequest>,
+        module_cache: Option<ModuleCacheRequest>,
+        contract_cache: Option<ContractCacheRequest>,
+        max_execution_limits: Option<ExecutionLimitsRequest>,
+        host_runtime: Option<HostRuntimeRequest>,
+        #[napi(
+            ts_arg_type = "(_: never, result: ThreadSafeJsImportResponse) => Promise<Buffer | Uint8Array>"
+        )]
+        storage_load_many_js_function: Option<JsFunction>,
     ) -> Result<Self, Error> {
         let storage_load_tsfn = create_tsfn!(storage_load_js_function);
+        let storage_load_many_tsfn = match storage_load_many_js_function {
+            Some(storage_load_many_js_function) => Some(create_tsfn!(storage_load_many_js_function)),
+            None => None,
+        };
         let storage_store_tsfn = create_tsfn!(storage_store_js_function);
         let call_other_contract_tsfn = create_tsfn!(call_other_contract_js_function);
         let deploy_from_address_tsfn = create_tsfn!(deploy_from_address_js_function);
         let console_log_tsfn = create_tsfn!(console_log_js_function);
 
-        let max_idling_runtimes = max_idling_runtimes as usize;
+        let module_cache = module_cache
+            .map(|request| {
+                let max_size = request.max_size.map(|size| size.get_u64().1).unwrap_or(DEFAULT_MODULE_CACHE_SIZE);
 
-        let runtime_pool = Arc::new(RuntimePool::new(max_idling_runtimes)); // 100 runtimes
+                ModuleCache::open(request.directory, max_size, &EngineKey::default())
+            })
+            .transpose()
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        let (contract_cache, instance_pool) = match contract_cache {
+            Some(request) => (
+                ContractCache::new(
+                    request.max_size.map(|size| size.get_u64().1).unwrap_or(DEFAULT_CONTRACT_CACHE_SIZE),
+                    request.policy.map(Into::into).unwrap_or_default(),
+                ),
+                InstancePool::new(request.max_pooled_instances.map(|max| max as usize).unwrap_or(DEFAULT_MAX_POOLED_INSTANCES)),
+            ),
+            None => (
+                ContractCache::new(DEFAULT_CONTRACT_CACHE_SIZE, Default::default()),
+                InstancePool::new(DEFAULT_MAX_POOLED_INSTANCES),
+            ),
+        };
+
+        let (max_pending_host_calls, saturation_policy) = match host_runtime {
+            Some(request) => (
+                request.max_pending_calls.map(|max| max as usize).unwrap_or(DEFAULT_MAX_PENDING_HOST_CALLS),
+                match request.reject_when_saturated {
+                    Some(true) => SaturationPolicy::Reject,
+                    _ => SaturationPolicy::Wait,
+                },
+            ),
+            None => (DEFAULT_MAX_PENDING_HOST_CALLS, SaturationPolicy::Wait),
+        };
+
+        let host_runtime = HostRuntime::new(host_worker_threads as usize, max_pending_host_calls, saturation_policy)
+            .map(Arc::new)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
 
         Ok(ContractManager {
             contracts: HashMap::new(),
-            contract_cache: HashMap::new(),
+            contract_cache,
+            code_hashes: HashMap::new(),
+            instance_pool,
+            pooled_contracts: HashMap::new(),
             next_id: 1, // Start the ID counter at 1 (or 0, if preferred)
+            module_limits: module_limits.map(ModuleLimits::from).unwrap_or_default(),
+            max_execution_limits: max_execution_limits
+                .map(|request| request.into_limits(ExecutionLimits::node_maxima()))
+                .unwrap_or_else(ExecutionLimits::node_maxima),
+            module_cache,
             storage_load_tsfn,
+            storage_load_many_tsfn,
             storage_store_tsfn,
             call_other_contract_tsfn,
             deploy_from_address_tsfn,
             console_log_tsfn,
-            runtime_pool,
+            host_runtime,
         })
     }
 
@@ -106,50 +177,229 @@ impl ContractManager {
     }
 
     #[napi]
+    #[allow(clippy::too_many_arguments)]
     pub fn instantiate(&mut self, reserved_id: BigInt, address: String, bytecode: Option<Buffer>,
-                       max_gas: BigInt, network: BitcoinNetworkRequest) -> Result<(), Error> {
+                       max_gas: BigInt, network: BitcoinNetworkRequest, gas_profiling: Option<bool>,
+                       execution_limits: Option<ExecutionLimitsRequest>, debugging: Option<bool>, deployment: Option<bool>) -> Result<InstantiateResponse, Error> {
         let max_gas = max_gas.get_u64().1;
         let id = reserved_id.get_u64().1;
+        let gas_profiling = gas_profiling.unwrap_or(false);
+        let debugging = debugging.unwrap_or(false);
+
+        // Deploying charges for the size of the code, which must be supplied to be measured.
+        let deployment_gas = match (deployment.unwrap_or(false), &bytecode) {
+            (false, _) => 0,
+            (true, Some(bytecode)) => WasmerRunner::get_deployment_gas(bytecode),
+            (true, None) => return Err(Error::from_reason(anyhow!("Bytecode is required to deploy").to_string())),
+        };
+        if deployment_gas > max_gas {
+            return Err(Error::from_reason(anyhow!("deployment out of gas: the code costs {} gas, the limit is {}", deployment_gas, max_gas).to_string()));
+        }
+
+        let execution_limits = execution_limits
+            .map(|request| request.into_limits(ExecutionLimits::default()))
+            .unwrap_or_default();
+        execution_limits
+            .check(&self.max_execution_limits)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        // Pooled instances keep the store they were created with, so only instances under
+        // the default limits are shared. A deployment is charged once, so it is never reused.
+        let poolable = execution_limits == ExecutionLimits::default() && deployment_gas == 0;
 
         let mut params: JsContractParameter = JsContractParameter {
+            address: address.clone(),
             bytecode: None,
-            serialized: None,
+            module: None,
+            code_hash: None,
+            runner: None,
             max_gas,
+            deployment_gas,
             network,
+            gas_profiling,
+            debugging,
+            module_limits: self.module_limits,
+            execution_limits,
         };
 
-        let mut should_cache: bool = false;
-        if self.contract_cache.contains_key(&address) {
-            let serialized = self.contract_cache.get(&address).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
-            params.serialized = Some(serialized.clone());
-        } else {
-            let bytecode = bytecode.ok_or_else(|| Error::from_reason(anyhow!("Bytecode is required").to_string()))?.to_vec();
+        if let Some(bytecode) = &bytecode {
+            self.check_code_hash(&address, &hex::encode(code_hash(bytecode)))?;
+        }
+
+        let mut should_cache: Option<[u8; 32]> = None;
+        let mut pool_key: Option<InstancePoolKey> = None;
+        if gas_profiling || debugging {
+            // Profiling and debugging instrument the module at compile time, so it never goes through the cache.
+            let bytecode = bytecode.ok_or_else(|| Error::from_reason(anyhow!("Bytecode is required for gas profiling and debugging").to_string()))?.to_vec();
 
-            should_cache = true;
             params.bytecode = Some(bytecode);
+        } else {
+            let bytecode = bytecode.map(|bytecode| bytecode.to_vec());
+
+            // Supplied bytecode matches what the address pointed to before, if anything.
+            let code_hash_key = match &bytecode {
+                Some(bytecode) => hex::encode(code_hash(bytecode)),
+                None => self.code_hashes.get(&address).cloned().ok_or_else(|| Error::from_reason(anyhow!("Bytecode is required").to_string()))?,
+            };
+
+            let key = InstancePoolKey { code_hash: code_hash_key.clone(), network: network.into() };
+            let pooled = if poolable { self.instance_pool.acquire(&key) } else { None };
+
+            if let Some(runner) = pooled {
+                params.runner = Some(runner);
+            } else if let Some(module) = self.contract_cache.get(&code_hash_key) {
+                params.module = Some(module);
+                params.code_hash = hex::decode(&code_hash_key).ok().and_then(|hash| hash.try_into().ok());
+            } else {
+                let bytecode = bytecode.ok_or_else(|| Error::from_reason(anyhow!("Bytecode is required").to_string()))?;
+                let bytecode_hash = code_hash(&bytecode);
+
+                if let Some(module) = self.load_cached_module(&bytecode, bytecode_hash) {
+                    params.module = Some(module);
+                    params.code_hash = Some(bytecode_hash);
+                } else {
+                    params.bytecode = Some(bytecode);
+                }
+
+                should_cache = Some(bytecode_hash);
+            }
+
+            pool_key = Some(key);
         }
 
+        let compiled = params.bytecode.is_some();
+        let pooled = params.runner.is_some();
+
+        let time = Instant::now();
         let js_contract: JsContract = JsContract::from(params, self, id)?;
-        if should_cache {
-            let serialized = js_contract.serialize()?;
-            self.contract_cache.insert(address, serialized);
+
+        // Only a contract that got instantiated points its address to the code.
+        if let Some(pool_key) = &pool_key {
+            self.code_hashes.insert(address, pool_key.code_hash.clone());
+        }
+
+        if pooled {
+            self.instance_pool.record_reset(time.elapsed());
+        } else {
+            self.instance_pool.record_instantiation(time.elapsed());
+        }
+        if let Some(bytecode_hash) = should_cache {
+            let module = js_contract.get_module()?;
+
+            if let Some(module_cache) = self.module_cache.as_mut().filter(|_| compiled) {
+                // The disk cache is only an optimisation, failing to persist must not fail the deployment.
+                let _ = module_cache.store(Hash::new(bytecode_hash), &module);
+            }
+
+            self.cache_module(hex::encode(bytecode_hash), module)?;
         }
 
+        let constructor_gas_used = js_contract.get_constructor_gas_used()?;
+
         self.add_contract(id, js_contract)?;
-        Ok(())
+        if let Some(pool_key) = pool_key {
+            self.pooled_contracts.insert(id, pool_key);
+        }
+
+        Ok(InstantiateResponse { constructor_gas_used })
+    }
+
+    /// Compiles the given contracts into the cache ahead of their first instantiation.
+    /// Returns how many modules were added, contracts sharing bytecode count once.
+    #[napi]
+    pub fn prewarm(&mut self, contracts: Vec<PrewarmContractRequest>) -> Result<u32, Error> {
+        let mut prewarmed = 0;
+
+        for contract in contracts {
+            let bytecode_hash = code_hash(&contract.bytecode);
+            let code_hash_key = hex::encode(bytecode_hash);
+            self.check_code_hash(&contract.address, &code_hash_key)?;
+
+            if !self.contract_cache.contains(&code_hash_key) {
+                let module = self.compile_module(&contract.bytecode, bytecode_hash)?;
+                if self.cache_module(code_hash_key.clone(), module)? {
+                    prewarmed += 1;
+                }
+            }
+
+            self.code_hashes.insert(contract.address, code_hash_key);
+        }
+
+        Ok(prewarmed)
+    }
+
+    /// Pins the code of `address`, which also pins every contract sharing its bytecode.
+    #[napi]
+    pub fn pin_contract(&mut self, address: String) -> bool {
+        match self.code_hashes.get(&address) {
+            Some(code_hash_key) => self.contract_cache.pin(code_hash_key),
+            None => false,
+        }
+    }
+
+    #[napi]
+    pub fn unpin_contract(&mut self, address: String) -> bool {
+        match self.code_hashes.get(&address) {
+            Some(code_hash_key) => self.contract_cache.unpin(code_hash_key),
+            None => false,
+        }
+    }
+
+    /// Drops the compiled code of `address`, contracts sharing its bytecode recompile on
+    /// their next instantiation. The address may then be instantiated with other bytecode.
+    #[napi]
+    pub fn evict_contract(&mut self, address: String) -> bool {
+        match self.code_hashes.remove(&address) {
+            Some(code_hash_key) => {
+                self.instance_pool.remove(&code_hash_key);
+                self.contract_cache.remove(&code_hash_key).is_some()
+            }
+            None => false,
+        }
     }
 
     #[napi]
-    pub fn validate_bytecode(&self, bytecode: Buffer, max_gas: BigInt) -> Result<bool, Error> {
-        JsContract::validate_bytecode(bytecode, max_gas)
+    pub fn get_cache_stats(&self) -> ContractCacheStatsResponse {
+        self.contract_cache.stats().into()
+    }
+
+    /// Statistics of the on-disk module cache, if one is configured.
+    #[napi]
+    pub fn get_module_cache_stats(&self) -> Option<ModuleCacheStatsResponse> {
+        self.module_cache.as_ref().map(Into::into)
+    }
+
+    #[napi]
+    pub fn get_instance_pool_stats(&self) -> InstancePoolStatsResponse {
+        self.instance_pool.stats().into()
+    }
+
+    #[napi]
+    pub fn get_host_runtime_stats(&self) -> HostRuntimeStatsResponse {
+        self.host_runtime.stats().into()
+    }
+
+    #[napi]
+    pub fn validate_bytecode(&self, bytecode: Buffer, max_gas: BigInt) -> Result<ValidationReportResponse, Error> {
+        JsContract::validate_bytecode(bytecode, max_gas, &self.module_limits)
     }
 
     #[napi]
     pub fn destroy_contract(&mut self, id: BigInt) -> Result<bool, Error> {
         let id = id.get_u64().1;
 
+        let pool_key = self.pooled_contracts.remove(&id);
+
         match self.contracts.remove(&id) {
-            Some(_) => Ok(true),
+            Some(contract) => {
+                contract.detach_debugger();
+
+                if let Some(pool_key) = pool_key {
+                    self.release_to_pool(pool_key, contract);
+                }
+
+                Ok(true)
+            }
             None => Ok(false),
         }
     }
@@ -157,12 +407,19 @@ impl ContractManager {
     #[napi]
     pub fn destroy(&mut self, env: Env) -> Result<(), Error> {
         abort_tsfn!(self.storage_load_tsfn, &env);
+        if let Some(storage_load_many_tsfn) = &mut self.storage_load_many_tsfn {
+            abort_tsfn!(storage_load_many_tsfn, &env);
+        }
         abort_tsfn!(self.storage_store_tsfn, &env);
         abort_tsfn!(self.call_other_contract_tsfn, &env);
         abort_tsfn!(self.deploy_from_address_tsfn, &env);
         abort_tsfn!(self.console_log_tsfn, &env);
 
-        //self.runtime_pool.destroy();
+        for contract in self.contracts.values() {
+            contract.detach_debugger();
+        }
+
+        self.host_runtime.shutdown();
 
         Ok(())
     }
@@ -170,6 +427,8 @@ impl ContractManager {
     #[napi]
     pub fn destroy_cache(&mut self) -> () {
         self.contract_cache.clear();
+        self.code_hashes.clear();
+        self.instance_pool.clear();
 
         ()
     }
@@ -178,6 +437,8 @@ impl ContractManager {
     pub fn destroy_all(&mut self) -> () {
         self.contracts.clear();
         self.contract_cache.clear();
+        self.code_hashes.clear();
+        self.instance_pool.clear();
 
         ()
     }
@@ -200,6 +461,72 @@ impl ContractManager {
         Ok(id)
     }
 
+    // Keep the runner of a destroyed contract for the next instantiation of the same code on the same network
+    fn release_to_pool(&mut self, pool_key: InstancePoolKey, contract: JsContract) {
+        match contract.into_runner() {
+            Some(runner) if runner.is_resettable() && runner.get_execution_limits() == ExecutionLimits::default() => {
+                self.instance_pool.release(pool_key, runner);
+            }
+            _ => self.instance_pool.record_discard(),
+        }
+    }
+
+    // Cache a compiled module, charging its serialized size against the cache budget
+    fn cache_module(&mut self, code_hash_key: String, module: Module) -> Result<bool, Error> {
+        let size = module.serialize().map_err(|e| Error::from_reason(format!("{:?}", e)))?.len() as u64;
+
+        Ok(self.contract_cache.insert(code_hash_key, module, size))
+    }
+
+    // Compile bytecode without instantiating it, going through the disk cache
+    fn compile_module(&mut self, bytecode: &[u8], bytecode_hash: [u8; 32]) -> Result<Module, Error> {
+        if let Some(module) = self.load_cached_module(bytecode, bytecode_hash) {
+            return Ok(module);
+        }
+
+        let report = WasmerRunner::check_bytecode(bytecode, &self.module_limits);
+        if !report.is_valid() {
+            return Err(Error::from_reason(format!("module is not valid: {}", report)));
+        }
+
+        let module = EngineRegistry::global()
+            .compile(&EngineKey::default(), bytecode)
+            .map_err(|e| Error::from_reason(format!("{:?}", e)))?;
+
+        if let Some(module_cache) = self.module_cache.as_mut() {
+            let _ = module_cache.store(Hash::new(bytecode_hash), &module);
+        }
+
+        Ok(module)
+    }
+
+    // Fails when the address already points to other code, only evicting it first repoints it
+    fn check_code_hash(&self, address: &str, code_hash_key: &str) -> Result<(), Error> {
+        match self.code_hashes.get(address) {
+            Some(indexed) if indexed != code_hash_key => Err(Error::from_reason(format!(
+                "bytecode of {} hashes to {}, but the address points to {}, evict it first",
+                address, code_hash_key, indexed
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // Load a compiled module from the disk cache, if the bytecode still passes the current checks
+    fn load_cached_module(&self, bytecode: &[u8], bytecode_hash: [u8; 32]) -> Option<Module> {
+        let module_cache = self.module_cache.as_ref().filter(|module_cache| !module_cache.is_empty())?;
+        if !WasmerRunner::check_bytecode(bytecode, &self.module_limits).is_valid() {
+            return None;
+        }
+
+        let hash = Hash::new(bytecode_hash);
+        if !module_cache.contains(&hash) {
+            return None;
+        }
+
+        let engine = EngineRegistry::global().runtime(&EngineKey::default());
+        unsafe { module_cache.load(&engine, hash).ok() }
+    }
+
     #[napi]
     pub fn use_gas(&self, contract_id: BigInt, gas: BigInt) -> Result<(), Error> {
         let id = contract_id.get_u64().1;
@@ -225,6 +552,145 @@ impl ContractManager {
     }
 
 
+    #[napi]
+    pub fn get_gas_profile(&self, contract_id: BigInt) -> Result<GasProfileResponse, Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.get_gas_profile()
+    }
+
+    /// Records every host import of the next calls of a contract, with its input, output
+    /// and gas, until `takeTrace`.
+    #[napi]
+    pub fn start_trace(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.start_trace()
+    }
+
+    /// Stops recording and returns the encoded trace, if one was started.
+    #[napi]
+    pub fn take_trace(&self, contract_id: BigInt) -> Result<Option<Buffer>, Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.take_trace()
+    }
+
+    /// Replays a trace taken from an instance of the same bytecode: the next calls get
+    /// their host imports from the trace and never reach JS.
+    #[napi]
+    pub fn start_replay(&self, contract_id: BigInt, trace: Buffer) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.start_replay(trace)
+    }
+
+    /// Ends a replay, failing if the calls did not consume the whole trace or wrote
+    /// different storage.
+    #[napi]
+    pub fn finish_replay(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.finish_replay()
+    }
+
+    /// Adds a breakpoint to a contract instantiated with `debugging`.
+    #[napi]
+    pub fn debug_set_breakpoint(&self, contract_id: BigInt, breakpoint: BreakpointRequest) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.set_breakpoint(breakpoint)
+    }
+
+    #[napi]
+    pub fn debug_clear_breakpoints(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.clear_breakpoints()
+    }
+
+    /// Where a running call of a debug build is stopped, if it is. Never blocks: the call
+    /// may need this thread for its host imports, so debug adapters poll.
+    #[napi]
+    pub fn debug_poll(&self, contract_id: BigInt) -> Result<Option<DebugStopResponse>, Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_poll()
+    }
+
+    /// Stops the call at its next probe.
+    #[napi]
+    pub fn debug_pause(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_pause()
+    }
+
+    /// Runs a stopped call to its next probe.
+    #[napi]
+    pub fn debug_step(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_step()
+    }
+
+    /// Runs a stopped call to its next breakpoint.
+    #[napi]
+    pub fn debug_resume(&self, contract_id: BigInt) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_resume()
+    }
+
+    /// Reads the memory of a stopped call.
+    #[napi]
+    pub fn debug_read_memory(&self, contract_id: BigInt, offset: BigInt, length: BigInt) -> Result<Buffer, Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_read_memory(offset, length)
+    }
+
+    /// Lets an IDE attach to a contract instantiated with `debugging` through the Debug
+    /// Adapter Protocol on `127.0.0.1:port`, any free port when omitted. Returns the port.
+    #[napi]
+    pub fn debug_listen(&self, contract_id: BigInt, port: Option<u32>) -> Result<u32, Error> {
+        let id = contract_id.get_u64().1;
+        let port = u16::try_from(port.unwrap_or(0)).map_err(|_| Error::from_reason("Invalid port"))?;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.debug_listen(port).map(u32::from)
+    }
+
+    /// Serializes the memory, globals, remaining gas and call state of a contract.
+    #[napi]
+    pub fn snapshot(&self, contract_id: BigInt) -> Result<Buffer, Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.snapshot()
+    }
+
+    /// Loads a snapshot into a contract instantiated from the same bytecode.
+    #[napi]
+    pub fn restore(&self, contract_id: BigInt, snapshot: Buffer) -> Result<(), Error> {
+        let id = contract_id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        contract.restore(snapshot)
+    }
+
     #[napi]
     pub fn set_remaining_gas(&self, id: BigInt, gas: BigInt) -> Result<(), Error> {
         let id = id.get_u64().1;
@@ -273,21 +739,121 @@ impl ContractManager {
         contract.read_memory(offset, length)
     }
 
+    /// `timeout_ms` bounds the wall-clock time of the call, including time spent awaiting
+    /// the JS imports. A call that runs out of time is rejected with "execution timed out",
+    /// one whose `cancellation_token` is cancelled with "execution cancelled". Neither
+    /// reports any gas.
+    ///
+    /// `read_cache` seeds storage values the host already knows. `load` and `loadMany`
+    /// serve them without a roundtrip, at the same gas cost.
+    ///
+    /// `access_list` declares the storage keys and contracts the call touches, see
+    /// `AccessListRequest`. A strict call that leaves the list fails with
+    /// "access list violation".
+    ///
+    /// An `is_static` call fails with "static call violation" as soon as it writes storage,
+    /// deploys or emits events. Its `call` requests carry `isStatic`, the host must make
+    /// the nested call static too and confirm it in its answer, or the call fails.
     #[napi(ts_return_type = "Promise<CallResponse>")]
+    #[allow(clippy::too_many_arguments)]
     pub fn call(
         &self,
+        env: Env,
         id: BigInt,
         func_name: String,
         params: Vec<JsNumber>,
-    ) -> Result<AsyncTask<ContractCallTask>, Error> {
+        timeout_ms: Option<u32>,
+        cancellation_token: Option<ClassInstance<JsCancellationToken>>,
+        read_cache: Option<Vec<StorageEntryRequest>>,
+        access_list: Option<AccessListRequest>,
+        is_static: Option<bool>,
+    ) -> Result<JsObject, Error> {
         let id = id.get_u64().1;
 
         let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
-        let result = contract.call(func_name, params)?;
+        let read_cache = read_cache
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| {
+                if entry.pointer.len() != STORAGE_POINTER_SIZE {
+                    return Err(Error::from_reason(format!(
+                        "Storage pointer must be {} bytes, got {}",
+                        STORAGE_POINTER_SIZE,
+                        entry.pointer.len()
+                    )));
+                }
+
+                Ok((entry.pointer.to_vec(), entry.value.to_vec()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let options = CallOptions {
+            timeout: timeout_ms.map(|timeout_ms| Duration::from_millis(timeout_ms as u64)),
+            cancellation: cancellation_token.map(|token| token.get_token()),
+            read_cache,
+            access_list: access_list
+                .map(AccessList::try_from)
+                .transpose()
+                .map_err(Error::from_reason)?,
+            is_static: is_static.unwrap_or(false),
+        };
+        let result = contract.call(&env, func_name, params, options)?;
 
         Ok(result)
     }
 
+    /// Finds the minimal gas limit the call succeeds with. Runs against a throwaway storage
+    /// overlay, storage writes are never sent to the host.
+    ///
+    /// Its `call` and `deployFromAddress` requests carry `isSimulation`, the host must run
+    /// them against throwaway state too and confirm it in its answer, or the estimate fails.
+    #[napi(ts_return_type = "Promise<GasEstimateResponse>")]
+    pub fn estimate_gas(
+        &self,
+        env: Env,
+        id: BigInt,
+        func_name: String,
+        params: Vec<JsNumber>,
+    ) -> Result<JsObject, Error> {
+        let id = id.get_u64().1;
+
+        let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+        let result = contract.estimate_gas(&env, func_name, params)?;
+
+        Ok(result)
+    }
+
+    /// Runs the calls of a block, each on a fresh instance of its contract, with the
+    /// results and storage writes of running them one after the other. Independent calls
+    /// run in parallel on up to `parallelism` threads, calls that conflict with an earlier
+    /// one run again in their turn, as do calls that call other contracts or deploy.
+    #[napi(ts_return_type = "Promise<BlockCallResponse[]>")]
+    pub fn execute_block(
+        &self,
+        env: Env,
+        calls: Vec<BlockCallRequest>,
+        parallelism: Option<u32>,
+    ) -> Result<JsObject, Error> {
+        let time = Local::now();
+
+        let calls = calls
+            .iter()
+            .map(|call| {
+                let id = call.contract_id.get_u64().1;
+                let contract = self.contracts.get(&id).ok_or_else(|| Error::from_reason(anyhow!("Contract not found").to_string()))?;
+
+                contract.prepare_block_call(&call.func_name, &call.params)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let parallelism = match parallelism {
+            Some(parallelism) => parallelism as usize,
+            None => std::thread::available_parallelism().map_or(1, |parallelism| parallelism.get()),
+        };
+
+        BlockExecutionTask::new(calls, parallelism, time).spawn(&env, &self.host_runtime)
+    }
+
     #[napi]
     pub fn length(&self) -> Result<BigInt, Error> {
         Ok(BigInt::from(self.contracts.len() as u64))
@@ -1,14 +1,43 @@
 pub use self::{
-    abort_data_response::*, call_response::*, contract_call_task::*, external_functions::*,
+    abort_data_response::*, access_list_request::*, block_call_request::*, block_call_response::*, block_execution_task::*, breakpoint_request::*, call_response::*, contract_cache_request::*,
+    contract_cache_stats_response::*, contract_call_task::*, debug_stop_response::*, eviction_policy_request::*,
+    execution_limits_request::*, external_functions::*, gas_estimate_response::*, gas_estimation_task::*,
+    gas_profile_response::*, host_runtime::*, host_runtime_request::*, host_runtime_stats_response::*, instance_pool_stats_response::*, instantiate_response::*, js_cancellation_token::*,
+    module_cache_request::*, module_cache_stats_response::*, module_limits_request::*,
+    prewarm_contract_request::*, storage_entry_request::*, validation_report_response::*,
 };
 
 mod abort_data_response;
+mod access_list_request;
+mod block_call_request;
+mod block_call_response;
+mod block_execution_task;
+mod breakpoint_request;
 mod call_response;
+mod contract_cache_request;
+mod contract_cache_stats_response;
 mod contract_call_task;
+mod debug_stop_response;
+mod eviction_policy_request;
+mod execution_limits_request;
 mod external_functions;
+mod gas_estimate_response;
+mod gas_estimation_task;
+mod gas_profile_response;
+mod host_runtime;
+mod host_runtime_request;
+mod host_runtime_stats_response;
+mod instance_pool_stats_response;
+mod instantiate_response;
+mod js_cancellation_token;
 mod js_contract;
 mod thread_safe_js_import_response;
 mod bitcoin_network_request;
 mod js_contract_manager;
+mod module_cache_request;
+mod module_cache_stats_response;
+mod module_limits_request;
+mod prewarm_contract_request;
+mod storage_entry_request;
 mod contract;
-mod runtime_pool;
+mod validation_report_response;
@@ -0,0 +1,9 @@
+use napi::bindgen_prelude::BigInt;
+
+/// Where and how large the on-disk compiled module cache may grow.
+#[napi(object)]
+pub struct ModuleCacheRequest {
+    pub directory: String,
+    /// In bytes, defaults to 1 GiB.
+    pub max_size: Option<BigInt>,
+}
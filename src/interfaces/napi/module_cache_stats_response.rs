@@ -0,0 +1,18 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::domain::runner::ModuleCache;
+
+#[napi(object)]
+pub struct ModuleCacheStatsResponse {
+    pub entries: u32,
+    pub size: BigInt,
+}
+
+impl From<&ModuleCache> for ModuleCacheStatsResponse {
+    fn from(module_cache: &ModuleCache) -> Self {
+        ModuleCacheStatsResponse {
+            entries: module_cache.len() as u32,
+            size: BigInt::from(module_cache.size()),
+        }
+    }
+}
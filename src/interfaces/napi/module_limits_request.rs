@@ -0,0 +1,29 @@
+use crate::domain::vm::ModuleLimits;
+
+/// Overrides for the structural module limits, unset fields keep their defaults.
+#[napi(object)]
+pub struct ModuleLimitsRequest {
+    pub max_bytecode_size: Option<u32>,
+    pub max_functions: Option<u32>,
+    pub max_locals_per_function: Option<u32>,
+    pub max_globals: Option<u32>,
+    pub max_data_segments: Option<u32>,
+    pub max_table_size: Option<u32>,
+    pub max_nesting_depth: Option<u32>,
+}
+
+impl From<ModuleLimitsRequest> for ModuleLimits {
+    fn from(request: ModuleLimitsRequest) -> Self {
+        let defaults = ModuleLimits::default();
+
+        ModuleLimits {
+            max_bytecode_size: request.max_bytecode_size.map(|size| size as usize).unwrap_or(defaults.max_bytecode_size),
+            max_functions: request.max_functions.unwrap_or(defaults.max_functions),
+            max_locals_per_function: request.max_locals_per_function.unwrap_or(defaults.max_locals_per_function),
+            max_globals: request.max_globals.unwrap_or(defaults.max_globals),
+            max_data_segments: request.max_data_segments.unwrap_or(defaults.max_data_segments),
+            max_table_size: request.max_table_size.unwrap_or(defaults.max_table_size),
+            max_nesting_depth: request.max_nesting_depth.unwrap_or(defaults.max_nesting_depth),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+use napi::bindgen_prelude::Buffer;
+
+#[napi(object)]
+pub struct PrewarmContractRequest {
+    pub address: String,
+    pub bytecode: Buffer,
+}
@@ -0,0 +1,7 @@
+use napi::bindgen_prelude::Buffer;
+
+#[napi(object)]
+pub struct StorageEntryRequest {
+    pub pointer: Buffer,
+    pub value: Buffer,
+}
@@ -4,4 +4,11 @@ use napi::bindgen_prelude::BigInt;
 pub struct ThreadSafeJsImportResponse {
     pub buffer: Vec<u8>,
     pub contract_id: BigInt,
+    /// Set on `call` requests made by a static call, the nested call must be static too.
+    /// The answer then confirms it with `static` between the gas used and the response.
+    pub is_static: bool,
+    /// Set on `call` and `deployFromAddress` requests made while estimating gas, the host
+    /// must run them against throwaway state. The answer then confirms it with `simulated`,
+    /// after `static` for a call and before the address for a deployment.
+    pub is_simulation: bool,
 }
@@ -0,0 +1,42 @@
+use napi::bindgen_prelude::BigInt;
+
+use crate::domain::vm::{ValidationReport, ValidationViolation};
+
+#[napi(object)]
+pub struct ValidationViolationResponse {
+    pub kind: String,
+    pub message: String,
+    pub opcode: Option<String>,
+    pub function_index: Option<u32>,
+    pub offset: u32,
+}
+
+#[napi(object)]
+pub struct ValidationReportResponse {
+    pub valid: bool,
+    pub violations: Vec<ValidationViolationResponse>,
+    /// Gas to charge the deployer, proportional to the bytecode size.
+    pub deployment_gas: BigInt,
+}
+
+impl From<ValidationViolation> for ValidationViolationResponse {
+    fn from(violation: ValidationViolation) -> Self {
+        ValidationViolationResponse {
+            kind: violation.kind.as_str().to_string(),
+            message: violation.message,
+            opcode: violation.opcode,
+            function_index: violation.function_index,
+            offset: violation.offset as u32,
+        }
+    }
+}
+
+impl From<ValidationReport> for ValidationReportResponse {
+    fn from(report: ValidationReport) -> Self {
+        ValidationReportResponse {
+            valid: report.is_valid(),
+            deployment_gas: BigInt::from(report.deployment_gas),
+            violations: report.violations.into_iter().map(|violation| violation.into()).collect(),
+        }
+    }
+}